@@ -0,0 +1,55 @@
+// Sending events in batches: recent events through `/track`, historical ones through
+// `/import`.
+//
+// Run with `cargo run --example batch`. Importing needs MIXPANEL_API_SECRET, as `/import`
+// authenticates with the project's API secret.
+
+use dotenv::dotenv;
+use mixpanel_rs::{Config, Event, Mixpanel};
+use serde_json::json;
+use std::{collections::HashMap, env};
+
+const DAY: u64 = 24 * 60 * 60;
+
+fn event(name: &str, distinct_id: &str, time: u64) -> Event {
+    let mut properties = HashMap::new();
+    properties.insert("distinct_id".to_string(), json!(distinct_id));
+    properties.insert("time".to_string(), json!(time));
+    Event {
+        event: name.to_string(),
+        properties,
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenv().ok();
+
+    let project_token = env::var("MIXPANEL_PROJECT_TOKEN")
+        .expect("MIXPANEL_PROJECT_TOKEN must be set in .env file");
+    let api_secret =
+        env::var("MIXPANEL_API_SECRET").expect("MIXPANEL_API_SECRET must be set in .env file");
+
+    let config = Config {
+        secret: Some(api_secret),
+        debug: true,
+        ..Default::default()
+    };
+    let mp = Mixpanel::init(&project_token, Some(config));
+    let now = Mixpanel::now();
+
+    // `/track` accepts events from the last 5 days
+    let recent: Vec<Event> = (0..3)
+        .map(|i| event("Page Viewed", "user_123", now - i * 60))
+        .collect();
+    mp.track_batch(recent).await?;
+
+    // Older events go through `/import`, split into requests of 50 events
+    let events: Vec<Event> = (0..120)
+        .map(|i| event("Order Placed", "user_123", now - 30 * DAY + i * 60))
+        .collect();
+    mp.import_batch(events.clone()).await?;
+
+    println!("Tracked 3 events and imported {} events", events.len());
+    Ok(())
+}
@@ -0,0 +1,83 @@
+// A long-lived client shared by the request handlers of a server.
+//
+// Run with `cargo run --example server`, then `curl localhost:3000/pricing`. Without
+// MIXPANEL_PROJECT_TOKEN set, the client runs in dry-run mode and only prints the events
+// it would send. Press Ctrl+C to shut down, sending whatever is still queued.
+//
+// With a web framework, keep the same `Arc<BufferedClient>` in the application state
+// (e.g. axum's `State`) instead of cloning it into each connection task.
+
+use dotenv::dotenv;
+use mixpanel_rs::{BufferedClient, Config, Mixpanel};
+use serde_json::json;
+use std::{collections::HashMap, env, sync::Arc, time::Duration};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenv().ok();
+
+    let project_token = env::var("MIXPANEL_PROJECT_TOKEN").ok();
+    let config = Config {
+        debug: true,
+        dry_run: project_token.is_none(),
+        ..Default::default()
+    };
+    let mp = Mixpanel::init(project_token.as_deref().unwrap_or("dry_run"), Some(config));
+
+    // Registered once, carried by every event any handler tracks
+    mp.register_runtime_context();
+    mp.register_defaults(env!("CARGO_PKG_VERSION"), "development");
+
+    let client = Arc::new(BufferedClient::new(mp));
+    let listener = TcpListener::bind("127.0.0.1:3000").await?;
+    println!("Listening on http://127.0.0.1:3000");
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, _) = accepted?;
+                let client = Arc::clone(&client);
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream, &client).await {
+                        eprintln!("request failed: {}", e);
+                    }
+                });
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    let report = client.graceful_shutdown(Duration::from_secs(5)).await;
+    let stats = client.mixpanel().stats();
+    println!(
+        "Shut down: sent {} events, dropped {} ({} requests)",
+        report.sent, report.dropped, stats.requests_sent
+    );
+    Ok(())
+}
+
+async fn handle(
+    mut stream: TcpStream,
+    client: &BufferedClient,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut buffer = [0u8; 4096];
+    let read = stream.read(&mut buffer).await?;
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/").to_string();
+
+    let mut properties = HashMap::new();
+    properties.insert("distinct_id".to_string(), json!("anonymous"));
+    properties.insert("path".to_string(), json!(path));
+    client.track("Page Viewed", Some(properties)).await?;
+
+    let body = format!("tracked {}\n", path);
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    Ok(())
+}
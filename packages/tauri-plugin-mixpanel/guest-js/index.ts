@@ -48,9 +48,9 @@ const people: People = {
     }
   },
 
-  async unset(prop: string | string[]): Promise<void> {
+  async unset(prop: string | string[], unregister?: boolean): Promise<void> {
     try {
-      await invoke("plugin:mixpanel|people_unset", { prop });
+      await invoke("plugin:mixpanel|people_unset", { prop, unregister });
     } catch (err) {
       if (isInvokeError(err)) {
         console.error(err);
@@ -252,9 +252,9 @@ const mixpanel = {
     }
   },
 
-  async reset(): Promise<void> {
+  async reset(force?: boolean): Promise<void> {
     try {
-      await invoke("plugin:mixpanel|reset");
+      await invoke("plugin:mixpanel|reset", { force });
     } catch (err) {
       if (isInvokeError(err)) {
         console.error(err);
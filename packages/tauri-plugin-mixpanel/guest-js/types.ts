@@ -11,7 +11,7 @@ export interface RegisterOptions extends PersistenceOptions {
 export interface People {
   set(prop: string | Dict, to?: any): Promise<void>;
   set_once(prop: string | Dict, to?: any): Promise<void>;
-  unset(prop: string | string[]): Promise<void>;
+  unset(prop: string | string[], unregister?: boolean): Promise<void>;
   increment(prop: string | Dict, by?: number): Promise<void>;
   append(list_name: string | Dict, value?: any): Promise<void>;
   remove(list_name: string | Dict, value?: any): Promise<void>;
@@ -34,7 +34,7 @@ export interface Mixpanel {
   unregister(property: string, options?: PersistenceOptions): Promise<void>;
   get_distinct_id(): Promise<string | null>;
   get_property(property_name: string): Promise<any | undefined>;
-  reset(): Promise<void>;
+  reset(force?: boolean): Promise<void>;
   time_event(event_name: string): Promise<void>;
 
   set_group(
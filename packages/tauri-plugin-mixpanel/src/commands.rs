@@ -1,17 +1,25 @@
 use serde_json::Value;
-use tauri::{command, ipc::InvokeError, AppHandle, Manager, Runtime};
+use tauri::{command, ipc::InvokeError, AppHandle, Manager, Runtime, State};
 
 use crate::state::MixpanelState;
 
 type Result<T> = std::result::Result<T, InvokeError>;
 
+/// The managed state, once its persisted data has loaded. Commands sent during
+/// startup wait here rather than reading an empty state.
+async fn ready_state<R: Runtime>(app_handle: &AppHandle<R>) -> State<'_, MixpanelState> {
+    let state = app_handle.state::<MixpanelState>();
+    state.ready().await;
+    state
+}
+
 #[command]
 pub async fn register<R: Runtime>(
     properties: Value,
     options: Option<Value>,
     app_handle: AppHandle<R>,
 ) -> Result<()> {
-    let state = app_handle.state::<crate::state::MixpanelState>();
+    let state = ready_state(&app_handle).await;
     state
         .register(properties, options)
         .await
@@ -26,7 +34,7 @@ pub async fn register_once<R: Runtime>(
     options: Option<Value>,
     app_handle: AppHandle<R>,
 ) -> Result<()> {
-    let state = app_handle.state::<crate::state::MixpanelState>();
+    let state = ready_state(&app_handle).await;
     state
         .register_once(properties, default_value, options)
         .map_err(InvokeError::from_error)?;
@@ -39,7 +47,7 @@ pub async fn unregister<R: Runtime>(
     options: Option<Value>,
     app_handle: AppHandle<R>,
 ) -> Result<()> {
-    let state = app_handle.state::<crate::state::MixpanelState>();
+    let state = ready_state(&app_handle).await;
     state
         .unregister(&property_name, options)
         .map_err(InvokeError::from_error)?;
@@ -47,14 +55,17 @@ pub async fn unregister<R: Runtime>(
 }
 
 #[command]
-pub fn get_property<R: Runtime>(property_name: String, app_handle: AppHandle<R>) -> Result<Option<Value>> {
-    let state = app_handle.state::<MixpanelState>();
+pub async fn get_property<R: Runtime>(
+    property_name: String,
+    app_handle: AppHandle<R>,
+) -> Result<Option<Value>> {
+    let state = ready_state(&app_handle).await;
     Ok(state.get_property(&property_name))
 }
 
 #[command]
-pub fn time_event<R: Runtime>(event_name: String, app_handle: AppHandle<R>) -> Result<()> {
-    let state = app_handle.state::<MixpanelState>();
+pub async fn time_event<R: Runtime>(event_name: String, app_handle: AppHandle<R>) -> Result<()> {
+    let state = ready_state(&app_handle).await;
     state.time_event(&event_name);
     Ok(())
 }
@@ -66,7 +77,7 @@ pub async fn set_group<R: Runtime>(
     options: Option<Value>,
     app_handle: AppHandle<R>,
 ) -> Result<()> {
-    let state = app_handle.state::<MixpanelState>();
+    let state = ready_state(&app_handle).await;
     state
         .set_group(&group_key, group_ids, options)
         .await
@@ -81,7 +92,7 @@ pub async fn add_group<R: Runtime>(
     options: Option<Value>,
     app_handle: AppHandle<R>,
 ) -> Result<()> {
-    let state = app_handle.state::<MixpanelState>();
+    let state = ready_state(&app_handle).await;
     state
         .add_group(&group_key, group_id, options)
         .await
@@ -96,7 +107,7 @@ pub async fn remove_group<R: Runtime>(
     options: Option<Value>,
     app_handle: AppHandle<R>,
 ) -> Result<()> {
-    let state = app_handle.state::<MixpanelState>();
+    let state = ready_state(&app_handle).await;
     state
         .remove_group(&group_key, group_id, options)
         .await
@@ -106,7 +117,7 @@ pub async fn remove_group<R: Runtime>(
 
 #[command]
 pub async fn identify<R: Runtime>(distinct_id: String, app_handle: AppHandle<R>) -> Result<()> {
-    let state = app_handle.state::<MixpanelState>();
+    let state = ready_state(&app_handle).await;
     state
         .identify(distinct_id)
         .await
@@ -120,7 +131,7 @@ pub async fn alias<R: Runtime>(
     original: Option<String>,
     app_handle: AppHandle<R>,
 ) -> Result<()> {
-    let state = app_handle.state::<MixpanelState>();
+    let state = ready_state(&app_handle).await;
     state
         .alias(alias, original)
         .await
@@ -129,9 +140,12 @@ pub async fn alias<R: Runtime>(
 }
 
 #[command]
-pub fn reset<R: Runtime>(app_handle: AppHandle<R>) -> Result<()> {
-    let state = app_handle.state::<MixpanelState>();
-    state.reset().map_err(InvokeError::from_error)?;
+pub async fn reset<R: Runtime>(force: Option<bool>, app_handle: AppHandle<R>) -> Result<()> {
+    let state = ready_state(&app_handle).await;
+    state
+        .reset(force.unwrap_or(false))
+        .await
+        .map_err(InvokeError::from_error)?;
     Ok(())
 }
 
@@ -141,7 +155,7 @@ pub async fn track<R: Runtime>(
     properties: Option<Value>,
     app_handle: AppHandle<R>,
 ) -> Result<()> {
-    let state = app_handle.state::<MixpanelState>();
+    let state = ready_state(&app_handle).await;
     state
         .track(event_name, properties)
         .await
@@ -150,8 +164,8 @@ pub async fn track<R: Runtime>(
 }
 
 #[command]
-pub fn get_distinct_id<R: Runtime>(app_handle: AppHandle<R>) -> Result<Option<String>> {
-    let state = app_handle.state::<MixpanelState>();
+pub async fn get_distinct_id<R: Runtime>(app_handle: AppHandle<R>) -> Result<Option<String>> {
+    let state = ready_state(&app_handle).await;
     Ok(state.get_distinct_id())
 }
 
@@ -163,7 +177,7 @@ pub async fn people_set<R: Runtime>(
     to: Option<Value>,
     app_handle: AppHandle<R>,
 ) -> Result<()> {
-    let state = app_handle.state::<MixpanelState>();
+    let state = ready_state(&app_handle).await;
     state
         .people
         .set(prop, to)
@@ -178,7 +192,7 @@ pub async fn people_set_once<R: Runtime>(
     to: Option<Value>,
     app_handle: AppHandle<R>,
 ) -> Result<()> {
-    let state = app_handle.state::<MixpanelState>();
+    let state = ready_state(&app_handle).await;
     state
         .people
         .set_once(prop, to)
@@ -188,13 +202,18 @@ pub async fn people_set_once<R: Runtime>(
 }
 
 #[command]
-pub async fn people_unset<R: Runtime>(prop: Value, app_handle: AppHandle<R>) -> Result<()> {
-    let state = app_handle.state::<MixpanelState>();
-    state
-        .people
-        .unset(prop)
-        .await
-        .map_err(InvokeError::from_error)?;
+pub async fn people_unset<R: Runtime>(
+    prop: Value,
+    unregister: Option<bool>,
+    app_handle: AppHandle<R>,
+) -> Result<()> {
+    let state = ready_state(&app_handle).await;
+    let result = if unregister.unwrap_or(false) {
+        state.people.unset_and_unregister(prop).await
+    } else {
+        state.people.unset(prop).await
+    };
+    result.map_err(InvokeError::from_error)?;
     Ok(())
 }
 
@@ -204,7 +223,7 @@ pub async fn people_increment<R: Runtime>(
     by: Option<Value>,
     app_handle: AppHandle<R>,
 ) -> Result<()> {
-    let state = app_handle.state::<MixpanelState>();
+    let state = ready_state(&app_handle).await;
     state
         .people
         .increment(prop, by)
@@ -219,7 +238,7 @@ pub async fn people_append<R: Runtime>(
     value: Option<Value>,
     app_handle: AppHandle<R>,
 ) -> Result<()> {
-    let state = app_handle.state::<MixpanelState>();
+    let state = ready_state(&app_handle).await;
     state
         .people
         .append(list_name, value)
@@ -234,7 +253,7 @@ pub async fn people_remove<R: Runtime>(
     value: Option<Value>,
     app_handle: AppHandle<R>,
 ) -> Result<()> {
-    let state = app_handle.state::<MixpanelState>();
+    let state = ready_state(&app_handle).await;
     state
         .people
         .remove(list_name, value)
@@ -249,7 +268,7 @@ pub async fn people_union<R: Runtime>(
     values: Option<Value>,
     app_handle: AppHandle<R>,
 ) -> Result<()> {
-    let state = app_handle.state::<MixpanelState>();
+    let state = ready_state(&app_handle).await;
     state
         .people
         .union(list_name, values)
@@ -260,7 +279,7 @@ pub async fn people_union<R: Runtime>(
 
 #[command]
 pub async fn people_delete_user<R: Runtime>(app_handle: AppHandle<R>) -> Result<()> {
-    let state = app_handle.state::<MixpanelState>();
+    let state = ready_state(&app_handle).await;
     state
         .people
         .delete_user()
@@ -8,9 +8,10 @@ pub enum Error {
     #[error("{0}")]
     Persistence(#[from] PersistenceError),
 
-    #[error("{0}")]
+    #[error(transparent)]
     MixpanelClient(mixpanel_rs::error::Error),
 
+    /// Failures with no underlying client error, e.g. calling People before `identify`
     #[error("{0}")]
     MixpanelError(String),
 
@@ -34,3 +35,26 @@ impl From<Error> for InvokeError {
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_client_error_source() {
+        let err = Error::from(mixpanel_rs::error::Error::ApiServerError(503));
+        assert!(matches!(
+            err,
+            Error::MixpanelClient(mixpanel_rs::error::Error::ApiServerError(503))
+        ));
+        assert_eq!(err.to_string(), "Mixpanel API server error (HTTP 503)");
+
+        // The chain continues with the client error's own source, not a repeat of it
+        assert!(std::error::Error::source(&err).is_none());
+        let json_error = serde_json::from_str::<Value>("{").unwrap_err();
+        let err = Error::from(mixpanel_rs::error::Error::JsonError(json_error));
+        let source = std::error::Error::source(&err).expect("client error has a source");
+        assert!(source.downcast_ref::<serde_json::Error>().is_some());
+    }
+}
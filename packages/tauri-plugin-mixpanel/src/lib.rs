@@ -1,11 +1,13 @@
 pub use mixpanel_rs::Config;
+use std::path::PathBuf;
 use tauri::{
     plugin::{Builder as PluginBuilder, TauriPlugin},
-    Manager, Runtime, State,
+    Manager, RunEvent, Runtime, State,
 };
 
 mod commands;
 mod error;
+mod pending;
 mod people;
 mod persistence;
 mod state;
@@ -31,6 +33,8 @@ impl<R: Runtime> MixpanelExt for tauri::AppHandle<R> {
 pub struct Builder {
     token: String,
     config: Option<Config>,
+    cache_set_once: bool,
+    persistence_subdir: Option<PathBuf>,
 }
 
 impl Builder {
@@ -38,12 +42,35 @@ impl Builder {
         Self {
             token: token.into(),
             config,
+            cache_set_once: false,
+            persistence_subdir: None,
         }
     }
 
+    /// Skip `people.set_once` requests for properties already sent for the current user
+    ///
+    /// The sent keys are kept in the plugin's persisted data, across sessions, and cleared
+    /// by `reset` or by identifying a different user. This is best-effort: the profile on
+    /// the server stays the source of truth, and a property unset elsewhere won't be set
+    /// again until the cache is cleared.
+    pub fn cache_set_once(mut self, enabled: bool) -> Self {
+        self.cache_set_once = enabled;
+        self
+    }
+
+    /// Store the persisted data (`mixpanel_{token}.json`) in this subdirectory of the app
+    /// data directory instead of directly in it, e.g. `analytics`. An absolute path is
+    /// used as is.
+    pub fn persistence_subdir(mut self, subdir: impl Into<PathBuf>) -> Self {
+        self.persistence_subdir = Some(subdir.into());
+        self
+    }
+
     pub fn build<R: Runtime>(self) -> TauriPlugin<R> {
         let token = self.token;
         let config = self.config;
+        let cache_set_once = self.cache_set_once;
+        let persistence_subdir = self.persistence_subdir;
 
         PluginBuilder::<R>::new("mixpanel")
             .invoke_handler(tauri::generate_handler![
@@ -69,17 +96,29 @@ impl Builder {
                 commands::people_union,
                 commands::people_delete_user,
             ])
-            .setup(
-                move |app_handle, _api| match MixpanelState::new(app_handle, &token, config) {
-                    Ok(state) => {
+            .setup(move |app_handle, _api| {
+                match MixpanelState::new(app_handle, &token, config, persistence_subdir.as_deref())
+                {
+                    Ok(mut state) => {
+                        if cache_set_once {
+                            state.enable_set_once_cache();
+                        }
                         app_handle.manage(state);
                         Ok(())
                     }
                     Err(e) => {
                         panic!("Failed to initialize Mixpanel: {:?}", e);
                     }
-                },
-            )
+                }
+            })
+            .on_event(|app_handle, event| {
+                // Managed state isn't guaranteed to be dropped when the app exits
+                if let RunEvent::Exit = event {
+                    if let Some(state) = app_handle.try_state::<MixpanelState>() {
+                        state.flush_persistence();
+                    }
+                }
+            })
             .build()
     }
 }
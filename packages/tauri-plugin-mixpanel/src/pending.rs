@@ -0,0 +1,100 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Outbound operations (tracks, People updates) that haven't completed yet.
+///
+/// Every operation holds a guard while it reads the current identity and sends,
+/// so `reset` can wait for them before wiping that identity.
+#[derive(Default)]
+pub(crate) struct PendingOps {
+    gate: RwLock<()>,
+    count: AtomicUsize,
+}
+
+/// Held by an outbound operation until it completes.
+pub(crate) struct PendingGuard<'a> {
+    _guard: RwLockReadGuard<'a, ()>,
+    count: &'a AtomicUsize,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl PendingOps {
+    /// Registers an outbound operation; waits while a flush is clearing state.
+    pub async fn start(&self) -> PendingGuard<'_> {
+        let guard = self.gate.read().await;
+        self.count.fetch_add(1, Ordering::SeqCst);
+        PendingGuard {
+            _guard: guard,
+            count: &self.count,
+        }
+    }
+
+    /// Number of operations currently in flight.
+    pub fn len(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Waits for in-flight operations to complete. New operations are held back
+    /// until the returned guard is dropped.
+    pub async fn flush(&self) -> RwLockWriteGuard<'_, ()> {
+        self.gate.write().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_flush_waits_for_pending_ops() {
+        let pending = Arc::new(PendingOps::default());
+        let sent = Arc::new(AtomicBool::new(false));
+
+        let guard_taken = Arc::new(tokio::sync::Notify::new());
+        let task = {
+            let pending = Arc::clone(&pending);
+            let sent = Arc::clone(&sent);
+            let guard_taken = Arc::clone(&guard_taken);
+            tokio::spawn(async move {
+                let _guard = pending.start().await;
+                guard_taken.notify_one();
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                sent.store(true, Ordering::SeqCst);
+            })
+        };
+
+        guard_taken.notified().await;
+        assert_eq!(pending.len(), 1);
+
+        let _flushed = pending.flush().await;
+        assert!(sent.load(Ordering::SeqCst));
+        assert_eq!(pending.len(), 0);
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ops_wait_for_flush() {
+        let pending = Arc::new(PendingOps::default());
+        let flushed = pending.flush().await;
+
+        let task = {
+            let pending = Arc::clone(&pending);
+            tokio::spawn(async move {
+                let _guard = pending.start().await;
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!task.is_finished());
+
+        drop(flushed);
+        task.await.unwrap();
+    }
+}
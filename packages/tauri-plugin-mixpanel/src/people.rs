@@ -1,9 +1,12 @@
 use crate::error::{Error, Result};
+use crate::pending::PendingOps;
 use crate::persistence::Persistence;
 use mixpanel_rs::Mixpanel;
-use serde_json::Value;
+use parking_lot::Mutex;
+use serde_json::{Number, Value};
 use std::collections::HashMap;
 use std::sync::Arc;
+use tokio::sync::watch;
 
 pub(crate) const SET_ACTION: &str = "$set";
 pub(crate) const SET_ONCE_ACTION: &str = "$set_once";
@@ -17,23 +20,43 @@ pub(crate) const DELETE_ACTION: &str = "$delete";
 pub struct MixpanelPeople {
     client: Mixpanel,
     persistence: Arc<Persistence>,
+    super_properties: Arc<Mutex<HashMap<String, Value>>>,
+    pending: Arc<PendingOps>,
+    ready: watch::Receiver<bool>,
+    cache_set_once: bool,
 }
 
 impl MixpanelPeople {
-    pub(crate) fn new(client: Mixpanel, persistence: Arc<Persistence>) -> Self {
+    pub(crate) fn new(
+        client: Mixpanel,
+        persistence: Arc<Persistence>,
+        super_properties: Arc<Mutex<HashMap<String, Value>>>,
+        pending: Arc<PendingOps>,
+        ready: watch::Receiver<bool>,
+    ) -> Self {
         Self {
             client,
             persistence,
+            super_properties,
+            pending,
+            ready,
+            cache_set_once: false,
         }
     }
 
+    /// Skips `set_once` requests whose keys were all sent for the current user, see
+    /// [`Builder::cache_set_once`](crate::Builder::cache_set_once).
+    pub(crate) fn enable_set_once_cache(&mut self) {
+        self.cache_set_once = true;
+    }
+
     fn get_distinct_id(&self) -> Option<String> {
         self.persistence.get_distinct_id()
     }
 
     fn identify_called(&self) -> bool {
         self.get_distinct_id()
-            .map_or(false, |id| !id.starts_with("$device:"))
+            .is_some_and(|id| !Mixpanel::is_device_id(&id))
     }
 
     fn is_reserved_property(&self, prop: &str) -> bool {
@@ -45,6 +68,8 @@ impl MixpanelPeople {
 
     /// Internal function to prepare and send the people request.
     async fn send_request(&self, action: &str, properties: HashMap<String, Value>) -> Result<()> {
+        crate::state::wait_ready(&self.ready).await;
+        let _pending = self.pending.start().await;
         if !self.identify_called() {
             println!("Mixpanel People: identify() must be called before using People API methods. Operation queued (in theory - queuing not fully implemented yet).");
             return Ok(());
@@ -79,25 +104,28 @@ impl MixpanelPeople {
                     .map_err(map_err)?
             }
             ADD_ACTION => {
-                let mut increment_props: HashMap<String, i64> = HashMap::new();
+                let mut increment_props = HashMap::new();
                 for (key, value) in properties {
-                    if let Some(num) = value.as_i64() {
-                        increment_props.insert(key, num);
-                    } else {
-                        eprintln!(
-                            "Mixpanel People: Invalid increment value for key '{}' - must be convertible to i64.",
-                            key
-                        );
-                        return Err(Error::MixpanelError(format!(
-                            "Invalid increment value for key '{}'",
-                            key
-                        )));
+                    match increment_amount(&value) {
+                        Some(num) => {
+                            increment_props.insert(key, num);
+                        }
+                        None => {
+                            eprintln!(
+                                "Mixpanel People: Invalid increment value for key '{}' - must be a number.",
+                                key
+                            );
+                            return Err(Error::MixpanelError(format!(
+                                "Invalid increment value for key '{}'",
+                                key
+                            )));
+                        }
                     }
                 }
 
                 self.client
                     .people
-                    .increment(&distinct_id, increment_props, None)
+                    .increment_numbers(&distinct_id, increment_props, None)
                     .await
                     .map_err(map_err)?
             }
@@ -208,13 +236,44 @@ impl MixpanelPeople {
             return Ok(());
         }
 
-        self.send_request(SET_ONCE_ACTION, properties).await
+        let cached_for = self
+            .get_distinct_id()
+            .filter(|_| self.cache_set_once && self.identify_called());
+        let Some(distinct_id) = cached_for else {
+            return self.send_request(SET_ONCE_ACTION, properties).await;
+        };
+
+        let sent = self.persistence.set_once_keys(&distinct_id);
+        let mut properties = properties;
+        properties.retain(|key, _| !sent.contains(key));
+        if properties.is_empty() {
+            return Ok(());
+        }
+
+        let keys: Vec<String> = properties.keys().cloned().collect();
+        self.send_request(SET_ONCE_ACTION, properties).await?;
+        self.persistence.cache_set_once(&distinct_id, keys);
+        Ok(())
     }
 
     /// Unset properties on a user profile.
     ///
     /// `prop` should be a String (single key) or an Array of Strings (multiple keys).
+    ///
+    /// Super properties are sent with every event but are separate from the profile,
+    /// so a key that is both keeps showing up on events after it is unset here. Use
+    /// [`unset_and_unregister`](Self::unset_and_unregister) to remove it from both.
     pub async fn unset(&self, prop: Value) -> Result<()> {
+        self.unset_keys(prop, false).await
+    }
+
+    /// Unset properties on a user profile, like [`unset`](Self::unset), and also remove
+    /// the keys from the persisted and in-memory super properties.
+    pub async fn unset_and_unregister(&self, prop: Value) -> Result<()> {
+        self.unset_keys(prop, true).await
+    }
+
+    async fn unset_keys(&self, prop: Value, unregister: bool) -> Result<()> {
         let mut keys_to_unset = HashMap::new();
 
         match prop {
@@ -248,6 +307,16 @@ impl MixpanelPeople {
             return Ok(());
         }
 
+        if unregister {
+            // The background load would overwrite the unregistered keys
+            crate::state::wait_ready(&self.ready).await;
+            let mut super_props = self.super_properties.lock();
+            for key in keys_to_unset.keys() {
+                self.persistence.unregister(key);
+                super_props.remove(key);
+            }
+        }
+
         self.send_request(UNSET_ACTION, keys_to_unset).await
     }
 
@@ -417,3 +486,127 @@ impl MixpanelPeople {
         self.send_request(DELETE_ACTION, HashMap::new()).await
     }
 }
+
+/// Normalize an increment value, turning whole floats like `5.0` into integers
+/// and keeping other numbers (fractional floats, large `u64`s) as they are.
+fn increment_amount(value: &Value) -> Option<Number> {
+    let num = match value {
+        Value::Number(num) => num,
+        _ => return None,
+    };
+    match num.as_f64() {
+        Some(f) if num.is_f64() && f.fract() == 0.0 && f.abs() < i64::MAX as f64 => {
+            Some(Number::from(f as i64))
+        }
+        _ => Some(num.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mixpanel_rs::mock_server::MockServer;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn test_increment_amount() {
+        assert_eq!(increment_amount(&json!(5.0)), Some(Number::from(5)));
+        assert_eq!(increment_amount(&json!(-3)), Some(Number::from(-3)));
+        assert_eq!(increment_amount(&json!(2.5)), Number::from_f64(2.5));
+        assert_eq!(
+            increment_amount(&json!(u64::MAX)),
+            Some(Number::from(u64::MAX))
+        );
+        assert_eq!(
+            increment_amount(&json!(9_007_199_254_740_993i64)),
+            Some(Number::from(9_007_199_254_740_993i64))
+        );
+        assert_eq!(increment_amount(&json!("5")), None);
+        assert_eq!(increment_amount(&json!(null)), None);
+    }
+
+    #[tokio::test]
+    async fn test_set_once_cache() {
+        let server = MockServer::start().await;
+        let dir = tempfile::tempdir().unwrap();
+        let persistence = Arc::new(Persistence::new(dir.path().join("mixpanel.json")));
+        persistence.set_distinct_id(Some("user_123".to_string()));
+        let mut people = MixpanelPeople::new(
+            Mixpanel::init("test_token", Some(server.config())),
+            Arc::clone(&persistence),
+            Arc::default(),
+            Arc::new(PendingOps::default()),
+            watch::channel(true).1,
+        );
+        people.enable_set_once_cache();
+
+        let first_seen = json!({"first_seen": "2024-01-01"});
+        people.set_once(first_seen.clone(), None).await.unwrap();
+        people.set_once(first_seen, None).await.unwrap();
+        assert_eq!(server.requests().len(), 1);
+
+        persistence.set_distinct_id(Some("user_456".to_string()));
+        people
+            .set_once(json!({"first_seen": "2024-02-01"}), None)
+            .await
+            .unwrap();
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_unset_unregisters_super_properties() {
+        let dir = tempfile::tempdir().unwrap();
+        let persistence = Arc::new(Persistence::new(dir.path().join("mixpanel.json")));
+        persistence.register(HashMap::from([("plan".to_string(), json!("pro"))]), None);
+        let super_properties = Arc::new(Mutex::new(HashMap::from([
+            ("plan".to_string(), json!("pro")),
+            ("theme".to_string(), json!("dark")),
+        ])));
+        let people = MixpanelPeople::new(
+            Mixpanel::init("test_token", None),
+            Arc::clone(&persistence),
+            Arc::clone(&super_properties),
+            Arc::new(PendingOps::default()),
+            watch::channel(true).1,
+        );
+
+        people.unset(json!("theme")).await.unwrap();
+        assert!(super_properties.lock().contains_key("theme"));
+
+        people
+            .unset_and_unregister(json!(["plan", "theme"]))
+            .await
+            .unwrap();
+        assert!(!persistence.get_properties().contains_key("plan"));
+        assert!(super_properties.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unset_unregisters_after_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mixpanel.json");
+        let saved = Persistence::new(&path);
+        saved.register(HashMap::from([("plan".to_string(), json!("pro"))]), None);
+        saved.save_sync().unwrap();
+
+        let persistence = Arc::new(Persistence::deferred(&path));
+        let (ready_tx, ready) = watch::channel(false);
+        let people = MixpanelPeople::new(
+            Mixpanel::init("test_token", None),
+            Arc::clone(&persistence),
+            Arc::default(),
+            Arc::new(PendingOps::default()),
+            ready,
+        );
+        let loader = Arc::clone(&persistence);
+        tokio::task::spawn_blocking(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            loader.load();
+            let _ = ready_tx.send(true);
+        });
+
+        people.unset_and_unregister(json!("plan")).await.unwrap();
+        assert!(!persistence.get_properties().contains_key("plan"));
+    }
+}
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
@@ -18,8 +18,15 @@ pub enum PersistenceError {
 
     #[error("{0}")]
     PathError(String),
+
+    #[error("unsupported persistence schema version {0}, newer than {SCHEMA_VERSION}")]
+    VersionError(u64),
 }
 
+/// Version of the `PersistentData` layout written to disk. Files written before it was
+/// versioned have no `version` and are read as version 1.
+pub(crate) const SCHEMA_VERSION: u32 = 2;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RegisterOptions {
     #[serde(default = "default_persistent")]
@@ -58,13 +65,59 @@ impl RegisterOptions {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct PersistentData {
+    version: u32,
     distinct_id: Option<String>,
     alias: Option<String>,
     event_timers: HashMap<String, u64>,
     properties: HashMap<String, Value>,
     store_expires_at: Option<u64>,
+    set_once: Option<SetOnceCache>,
+}
+
+impl Default for PersistentData {
+    fn default() -> Self {
+        PersistentData {
+            version: SCHEMA_VERSION,
+            distinct_id: None,
+            alias: None,
+            event_timers: HashMap::new(),
+            properties: HashMap::new(),
+            store_expires_at: None,
+            set_once: None,
+        }
+    }
+}
+
+impl PersistentData {
+    /// Upgrades data written by an older schema version to the current layout.
+    fn migrate(mut data: Value) -> Result<Value, PersistenceError> {
+        let version = data.get("version").and_then(Value::as_u64).unwrap_or(1);
+        // A version beyond `u32` is from a newer schema as well
+        let version = match u32::try_from(version) {
+            Ok(version) if version <= SCHEMA_VERSION => version,
+            _ => return Err(PersistenceError::VersionError(version)),
+        };
+        let Some(fields) = data.as_object_mut() else {
+            // Let deserialization report the malformed file
+            return Ok(data);
+        };
+
+        if version < 2 {
+            // v2 added the persisted `people.set_once` cache
+            fields.entry("set_once").or_insert(Value::Null);
+        }
+        fields.insert("version".to_string(), SCHEMA_VERSION.into());
+        Ok(data)
+    }
+}
+
+/// Profile properties already sent with `people.set_once` for one distinct_id.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub(crate) struct SetOnceCache {
+    distinct_id: String,
+    keys: HashSet<String>,
 }
 
 pub(crate) struct Persistence {
@@ -80,23 +133,55 @@ fn current_time_millis() -> u64 {
 }
 
 impl Persistence {
+    /// Creates a store for `path`, loading it immediately.
+    #[cfg(test)]
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
-        let path_buf = path.as_ref().to_path_buf();
-        let initial_data = match Self::load_sync(&path_buf) {
+        let persistence = Self::deferred(path);
+        persistence.load();
+        persistence
+    }
+
+    /// Creates an empty store for `path` without reading it, see [`Self::load`].
+    pub fn deferred<P: AsRef<Path>>(path: P) -> Self {
+        Persistence {
+            path: path.as_ref().to_path_buf(),
+            data: Arc::new(RwLock::new(PersistentData::default())),
+        }
+    }
+
+    /// Reads the persisted data from disk, replacing what's in memory.
+    ///
+    /// A file that can't be read, e.g. invalid JSON or written by a newer version of the
+    /// plugin, is moved aside to `<file>.corrupt-<timestamp>` before starting fresh, so the
+    /// old distinct_id can still be recovered from it.
+    pub fn load(&self) {
+        let loaded = match Self::load_sync(&self.path) {
             Ok(data) => data,
+            Err(PersistenceError::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                PersistentData::default()
+            }
             Err(e) => {
-                eprintln!(
-                    "[Mixpanel Persistence] Failed to load initial data from {}: {}. Starting fresh.",
-                    path_buf.display(),
-                    e
-                );
+                match Self::back_up_corrupt(&self.path) {
+                    Ok(backup) => eprintln!(
+                        "[Mixpanel Persistence] Failed to load {}: {}. Moved it to {} and starting fresh.",
+                        self.path.display(),
+                        e,
+                        backup.display()
+                    ),
+                    Err(backup_err) => eprintln!(
+                        "[Mixpanel Persistence] Failed to load {}: {}. Could not back it up ({}), starting fresh.",
+                        self.path.display(),
+                        e,
+                        backup_err
+                    ),
+                }
                 PersistentData::default()
             }
         };
 
-        Persistence {
-            path: path_buf,
-            data: Arc::new(RwLock::new(initial_data)),
+        match self.data.write() {
+            Ok(mut data_guard) => *data_guard = loaded,
+            Err(e) => eprintln!("[Mixpanel Persistence] Lock error during load: {}", e),
         }
     }
 
@@ -105,7 +190,8 @@ impl Persistence {
             return Ok(PersistentData::default());
         }
         let contents = std::fs::read_to_string(path)?;
-        let data: PersistentData = serde_json::from_str(&contents)?;
+        let data = PersistentData::migrate(serde_json::from_str(&contents)?)?;
+        let data: PersistentData = serde_json::from_value(data)?;
 
         let now = current_time_millis();
         if let Some(expires_at) = data.store_expires_at {
@@ -116,6 +202,14 @@ impl Persistence {
         Ok(data)
     }
 
+    fn back_up_corrupt(path: &Path) -> std::io::Result<PathBuf> {
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(format!(".corrupt-{}", current_time_millis()));
+        let backup = PathBuf::from(backup);
+        std::fs::rename(path, &backup)?;
+        Ok(backup)
+    }
+
     async fn write_data_async(
         &self,
         data_to_write: PersistentData,
@@ -129,6 +223,20 @@ impl Persistence {
         Ok(())
     }
 
+    /// Writes the current data to disk, blocking until done. Used on shutdown,
+    /// where the detached saves from `trigger_save` may never get to run.
+    pub fn save_sync(&self) -> Result<(), PersistenceError> {
+        let contents = {
+            let data_guard = self.data.read().unwrap_or_else(|e| e.into_inner());
+            serde_json::to_string_pretty(&*data_guard)?
+        };
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.path, contents)?;
+        Ok(())
+    }
+
     fn trigger_save(&self) {
         match self.data.read() {
             Ok(data_guard) => {
@@ -343,6 +451,44 @@ impl Persistence {
         }
     }
 
+    /// Keys recorded by `cache_set_once` for `distinct_id`. Keys cached for another
+    /// distinct_id don't count, so switching users invalidates the cache.
+    pub fn set_once_keys(&self, distinct_id: &str) -> HashSet<String> {
+        match self.data.read() {
+            Ok(data_guard) => match data_guard.set_once {
+                Some(ref cache) if cache.distinct_id == distinct_id => cache.keys.clone(),
+                _ => HashSet::new(),
+            },
+            Err(e) => {
+                eprintln!(
+                    "[Mixpanel Persistence] Lock error during set_once_keys: {}",
+                    e
+                );
+                HashSet::new()
+            }
+        }
+    }
+
+    /// Records profile properties sent with `people.set_once` for `distinct_id`.
+    pub fn cache_set_once(&self, distinct_id: &str, keys: impl IntoIterator<Item = String>) {
+        match self.data.write() {
+            Ok(mut data_guard) => {
+                let cache = data_guard.set_once.get_or_insert_with(Default::default);
+                if cache.distinct_id != distinct_id {
+                    cache.distinct_id = distinct_id.to_string();
+                    cache.keys.clear();
+                }
+                cache.keys.extend(keys);
+                drop(data_guard);
+                self.trigger_save();
+            }
+            Err(e) => eprintln!(
+                "[Mixpanel Persistence] Lock error during cache_set_once: {}",
+                e
+            ),
+        }
+    }
+
     pub fn clear_all_data(&self) {
         match self.data.write() {
             Ok(mut data_guard) => {
@@ -593,6 +739,43 @@ mod tests {
         cleanup_test_file(&file_path);
     }
 
+    #[tokio::test]
+    async fn test_set_once_cache() {
+        let (persistence, file_path) = setup_test_persistence("set_once_cache");
+
+        persistence.cache_set_once("user_a", ["plan".to_string()]);
+        assert!(persistence.set_once_keys("user_a").contains("plan"));
+        assert!(persistence.set_once_keys("user_b").is_empty());
+
+        persistence.cache_set_once("user_b", ["seats".to_string()]);
+        assert!(persistence.set_once_keys("user_a").is_empty());
+
+        persistence.clear_all_data();
+        assert!(persistence.set_once_keys("user_b").is_empty());
+
+        wait_for_save().await;
+        cleanup_test_file(&file_path);
+    }
+
+    #[tokio::test]
+    async fn test_save_sync() {
+        let (persistence, file_path) = setup_test_persistence("save_sync");
+        let mut props = HashMap::new();
+        props.insert("last_screen".to_string(), json!("settings"));
+        persistence.register(props, None);
+
+        // No wait: the data must be on disk as soon as save_sync returns
+        persistence.save_sync().unwrap();
+        let contents = std_fs::read_to_string(&file_path).unwrap();
+        let file_data: PersistentData = serde_json::from_str(&contents).unwrap();
+        assert_eq!(
+            file_data.properties.get("last_screen"),
+            Some(&json!("settings"))
+        );
+
+        cleanup_test_file(&file_path);
+    }
+
     #[tokio::test]
     async fn test_register_with_expiration() {
         let (persistence, file_path) = setup_test_persistence("register_expiry");
@@ -657,6 +840,81 @@ mod tests {
         cleanup_test_file(&file_path);
     }
 
+    #[tokio::test]
+    async fn test_load_v1_file() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("mixpanel_v1.json");
+        let v1 = json!({
+            "distinct_id": "user",
+            "alias": null,
+            "event_timers": {"Checkout": 1000},
+            "properties": {"plan": "pro"},
+            "store_expires_at": null
+        });
+        std_fs::write(&file_path, v1.to_string()).unwrap();
+
+        let persistence = Persistence::new(&file_path);
+        assert_eq!(persistence.get_distinct_id().as_deref(), Some("user"));
+        assert_eq!(persistence.get_property("plan"), Some(json!("pro")));
+        assert!(persistence.set_once_keys("user").is_empty());
+        let data = persistence.data.read().unwrap().clone();
+        assert_eq!(data.version, SCHEMA_VERSION);
+        assert_eq!(data.event_timers.get("Checkout"), Some(&1000));
+
+        let newer = json!({"version": SCHEMA_VERSION + 1});
+        assert!(matches!(
+            PersistentData::migrate(newer),
+            Err(PersistenceError::VersionError(_))
+        ));
+        let overflowing = json!({"version": u64::from(u32::MAX) + 2});
+        assert!(matches!(
+            PersistentData::migrate(overflowing),
+            Err(PersistenceError::VersionError(version)) if version == u64::from(u32::MAX) + 2
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_load_corrupt_file() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("mixpanel_token.json");
+        std_fs::write(&file_path, "{\"distinct_id\": \"user\", ").unwrap();
+
+        let persistence = Persistence::new(&file_path);
+        assert!(persistence.get_distinct_id().is_none());
+        assert!(persistence.get_properties().is_empty());
+        assert!(!file_path.exists());
+
+        let backups: Vec<_> = std_fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(backups.len(), 1);
+        assert!(backups[0].starts_with("mixpanel_token.json.corrupt-"));
+        assert_eq!(
+            std_fs::read_to_string(dir.path().join(&backups[0])).unwrap(),
+            "{\"distinct_id\": \"user\", "
+        );
+    }
+
+    #[tokio::test]
+    async fn test_load_newer_version_file() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("mixpanel_token.json");
+        let newer = json!({"version": SCHEMA_VERSION + 1, "distinct_id": "user"});
+        std_fs::write(&file_path, newer.to_string()).unwrap();
+
+        let persistence = Persistence::new(&file_path);
+        assert!(persistence.get_distinct_id().is_none());
+        assert!(!file_path.exists());
+
+        let backups: Vec<_> = std_fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(backups.len(), 1);
+        assert!(backups[0].starts_with("mixpanel_token.json.corrupt-"));
+    }
+
     #[test]
     fn test_register_options_parsing() {
         // persistent: true (default), days: None (default)
@@ -1,13 +1,16 @@
 use crate::error::{Error, Result};
-use mixpanel_rs::{Config, Mixpanel};
+use mixpanel_rs::{Clock, Config, Mixpanel, SystemClock};
 use parking_lot::Mutex;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use tauri::Manager;
 use tauri::{AppHandle, Runtime};
+use tokio::sync::watch;
 
+use crate::pending::PendingOps;
 use crate::people::MixpanelPeople;
 use crate::persistence::{Persistence, PersistenceError, RegisterOptions};
 
@@ -15,48 +18,113 @@ pub struct MixpanelState {
     pub(crate) client: Mixpanel,
     super_properties: Arc<Mutex<HashMap<String, Value>>>,
     persistence: Arc<Persistence>,
+    pending: Arc<PendingOps>,
+    ready: watch::Receiver<bool>,
     pub people: MixpanelPeople,
 }
 
+impl Drop for MixpanelState {
+    fn drop(&mut self) {
+        self.flush_persistence();
+    }
+}
+
+/// Loads `persistence` and registers the initial properties on a blocking thread,
+/// returning a receiver that turns `true` once done.
+fn spawn_load<F>(persistence: Arc<Persistence>, initial_properties: F) -> watch::Receiver<bool>
+where
+    F: FnOnce(&Persistence) -> Result<HashMap<String, Value>> + Send + 'static,
+{
+    let (ready_tx, ready) = watch::channel(false);
+    tauri::async_runtime::spawn_blocking(move || {
+        persistence.load();
+        match initial_properties(&persistence) {
+            Ok(props) if !props.is_empty() => persistence.register(props, None),
+            Ok(_) => {}
+            Err(e) => eprintln!("Mixpanel: Failed to gather initial properties: {}", e),
+        }
+        let _ = ready_tx.send(true);
+    });
+    ready
+}
+
 impl MixpanelState {
+    /// Creates the state without blocking on disk I/O: persisted data and the initial
+    /// properties are loaded in the background. Commands, tracks and People updates wait
+    /// for the load to finish (see [`Self::ready`]) instead of seeing an empty state.
     pub fn new<R: Runtime>(
         app_handle: &AppHandle<R>,
         token: &str,
         config: Option<Config>,
+        persistence_subdir: Option<&Path>,
     ) -> Result<Self> {
-        let client = Mixpanel::init(token, config);
-        let persistence = Self::initialize_persistence(app_handle, token)?;
+        let client = Mixpanel::try_init(token, config)?;
+        let persistence = Self::initialize_persistence(app_handle, token, persistence_subdir)?;
 
-        let initial_props = Self::gather_initial_properties(app_handle, &persistence)?;
-        if !initial_props.is_empty() {
-            persistence.register(initial_props, None);
-        }
+        let loader_handle = app_handle.clone();
+        let ready = spawn_load(Arc::clone(&persistence), move |persistence| {
+            Self::gather_initial_properties(&loader_handle, persistence)
+        });
 
+        Ok(Self::from_parts(client, persistence, ready))
+    }
+
+    fn from_parts(
+        client: Mixpanel,
+        persistence: Arc<Persistence>,
+        ready: watch::Receiver<bool>,
+    ) -> Self {
         let super_properties = Arc::new(Mutex::new(HashMap::new()));
-        let people = MixpanelPeople::new(client.clone(), Arc::clone(&persistence));
+        let pending = Arc::new(PendingOps::default());
+        let people = MixpanelPeople::new(
+            client.clone(),
+            Arc::clone(&persistence),
+            Arc::clone(&super_properties),
+            Arc::clone(&pending),
+            ready.clone(),
+        );
 
-        Ok(Self {
+        Self {
             client,
             super_properties,
             persistence,
+            pending,
+            ready,
             people,
-        })
+        }
     }
 
-    /// Initializes the persistence layer.
+    /// Current time from the client's `Config::clock`, so tests can control timestamps
+    /// and `$duration`.
+    fn now(&self) -> SystemTime {
+        match self.client.config.clock {
+            Some(ref clock) => clock.now(),
+            None => SystemClock.now(),
+        }
+    }
+
+    /// Enables the People `set_once` cache, see `MixpanelPeople::enable_set_once_cache`.
+    pub(crate) fn enable_set_once_cache(&mut self) {
+        self.people.enable_set_once_cache();
+    }
+
+    /// Waits until the persisted data has been loaded.
+    pub async fn ready(&self) {
+        wait_ready(&self.ready).await;
+    }
+
+    /// Initializes the persistence layer, without loading it yet.
     fn initialize_persistence<R: Runtime>(
         app_handle: &AppHandle<R>,
         token: &str,
+        persistence_subdir: Option<&Path>,
     ) -> Result<Arc<Persistence>> {
-        let persistence_path = app_handle
-            .path()
-            .app_data_dir()
-            .map_err(|_| {
-                PersistenceError::PathError("Failed to get app data directory".to_string())
-            })?
-            .join(format!("mixpanel_{}.json", token));
+        let app_data_dir = app_handle.path().app_data_dir().map_err(|_| {
+            PersistenceError::PathError("Failed to get app data directory".to_string())
+        })?;
+        let persistence_path = persistence_path(&app_data_dir, persistence_subdir, token);
 
-        Ok(Arc::new(Persistence::new(persistence_path)))
+        Ok(Arc::new(Persistence::deferred(persistence_path)))
     }
 
     /// Gathers initial properties (distinct_id, device_id, os, browser, etc.)
@@ -110,6 +178,18 @@ impl MixpanelState {
         Ok(initial_props)
     }
 
+    /// Synchronously saves persisted properties, so registrations made right before
+    /// the app exits aren't lost with a pending async save.
+    pub fn flush_persistence(&self) {
+        // Saving before the load finished would overwrite the file with empty data
+        if !*self.ready.borrow() {
+            return;
+        }
+        if let Err(e) = self.persistence.save_sync() {
+            eprintln!("Mixpanel: Failed to save persisted data on exit: {}", e);
+        }
+    }
+
     /// Gets the distinct ID currently stored in persistence.
     pub fn get_distinct_id(&self) -> Option<String> {
         self.persistence.get_distinct_id()
@@ -206,7 +286,8 @@ impl MixpanelState {
     /// When the event is tracked using `track()`, the duration since `time_event` was called
     /// will be automatically included as a `$duration` property.
     pub fn time_event(&self, event_name: &str) {
-        let timestamp = SystemTime::now()
+        let timestamp = self
+            .now()
             .duration_since(UNIX_EPOCH)
             .map(|d| d.as_millis() as u64)
             .unwrap_or(0);
@@ -369,13 +450,20 @@ impl MixpanelState {
     /// Identifies a user, associating all future events with their profile.
     /// Switches the distinct_id and sends an $identify event.
     pub async fn identify(&self, new_distinct_id: String) -> Result<()> {
+        let _pending = self.pending.start().await;
+        self.identify_pending(new_distinct_id).await
+    }
+
+    /// `identify` for a caller already holding a pending guard: taking a second one
+    /// would wait behind a `reset` that is itself waiting for the first.
+    async fn identify_pending(&self, new_distinct_id: String) -> Result<()> {
         let old_distinct_id_opt = self.get_distinct_id();
         let old_alias_opt = self
             .get_property("$alias")
             .and_then(|v| v.as_str().map(String::from));
 
         if old_distinct_id_opt.as_ref() != Some(&new_distinct_id) {
-            if new_distinct_id.starts_with("$device:") {
+            if Mixpanel::is_device_id(&new_distinct_id) {
                 eprintln!("Mixpanel Error: distinct_id cannot have $device: prefix");
                 return Ok(());
             }
@@ -428,12 +516,7 @@ impl MixpanelState {
                     Value::String(old_distinct_id),
                 );
 
-                self.client
-                    .track("$identify", Some(identify_props))
-                    .await
-                    .map_err(|e| {
-                        Error::MixpanelError(format!("Failed to track $identify event: {}", e))
-                    })?;
+                self.client.track("$identify", Some(identify_props)).await?;
             }
         }
 
@@ -442,6 +525,7 @@ impl MixpanelState {
 
     /// Creates an alias, associating a new ID with the current distinct ID.
     pub async fn alias(&self, alias: String, original: Option<String>) -> Result<()> {
+        let _pending = self.pending.start().await;
         let original_id = match original {
             Some(id) => id,
             None => self.get_distinct_id().ok_or_else(|| {
@@ -451,7 +535,7 @@ impl MixpanelState {
 
         if alias == original_id {
             println!("Mixpanel: alias matches current distinct_id. Skipping api call.");
-            self.identify(alias).await?;
+            self.identify_pending(alias).await?;
             return Ok(());
         }
 
@@ -480,18 +564,31 @@ impl MixpanelState {
 
         self.client
             .track("$create_alias", Some(event_props))
-            .await
-            .map_err(|e| {
-                Error::MixpanelError(format!("Failed to track $create_alias event: {}", e))
-            })?;
+            .await?;
 
-        self.identify(alias).await?;
+        self.identify_pending(alias).await?;
 
         Ok(())
     }
 
     /// Resets the instance, clearing super properties and generating a new distinct ID.
-    pub fn reset(&self) -> Result<()> {
+    ///
+    /// Waits for in-flight tracks and People updates to be sent first, so they aren't
+    /// lost or attributed to the new anonymous ID. `force` skips the wait.
+    pub async fn reset(&self, force: bool) -> Result<()> {
+        self.ready().await;
+        let _flushed = if force {
+            if self.pending.len() > 0 {
+                eprintln!(
+                    "Mixpanel: Forced reset with {} operations still in flight.",
+                    self.pending.len()
+                );
+            }
+            None
+        } else {
+            Some(self.pending.flush().await)
+        };
+
         self.persistence.clear_all_data();
         self.super_properties.lock().clear();
 
@@ -518,6 +615,8 @@ impl MixpanelState {
     /// Tracks an event with the associated properties.
     /// Merges input properties with superproperties (in-memory and persistent) and adds timing information if available.
     pub async fn track(&self, event_name: String, properties: Option<Value>) -> Result<()> {
+        self.ready().await;
+        let _pending = self.pending.start().await;
         let distinct_id = self.get_distinct_id().ok_or_else(|| {
             Error::MixpanelError("Distinct ID not set. Call identify or alias first.".to_string())
         })?;
@@ -534,7 +633,7 @@ impl MixpanelState {
         final_props.extend(input_props);
 
         if let Some(start_time_ms) = self.persistence.remove_event_timer(&event_name) {
-            match SystemTime::now().duration_since(UNIX_EPOCH) {
+            match self.now().duration_since(UNIX_EPOCH) {
                 Ok(now_duration) => {
                     let now_ms = now_duration.as_millis();
                     if now_ms >= start_time_ms as u128 {
@@ -563,7 +662,7 @@ impl MixpanelState {
         }
 
         final_props.insert("distinct_id".to_string(), Value::String(distinct_id));
-        match SystemTime::now().duration_since(UNIX_EPOCH) {
+        match self.now().duration_since(UNIX_EPOCH) {
             Ok(now_duration) => {
                 final_props.insert(
                     "time".to_string(),
@@ -579,13 +678,179 @@ impl MixpanelState {
             }
         }
 
-        self.client
-            .track(&event_name, Some(final_props))
-            .await
-            .map_err(|e| {
-                Error::MixpanelError(format!("Failed to track event '{}': {}", event_name, e))
-            })?;
+        self.client.track(&event_name, Some(final_props)).await?;
 
         Ok(())
     }
 }
+
+/// Where the persisted data for `token` is stored: `mixpanel_{token}.json` in the app
+/// data directory, or in `subdir` of it. An absolute `subdir` replaces the app data
+/// directory.
+fn persistence_path(app_data_dir: &Path, subdir: Option<&Path>, token: &str) -> PathBuf {
+    let dir = match subdir {
+        Some(subdir) => app_data_dir.join(subdir),
+        None => app_data_dir.to_path_buf(),
+    };
+    dir.join(format!("mixpanel_{}.json", token))
+}
+
+/// Waits for `ready` to turn `true`. A dropped sender means the load task panicked,
+/// in which case there is nothing left to wait for.
+pub(crate) async fn wait_ready(ready: &watch::Receiver<bool>) {
+    let _ = ready.clone().wait_for(|ready| *ready).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mixpanel_rs::mock_server::{MockResponse, MockServer};
+    use mixpanel_rs::Event;
+    use serde_json::json;
+    use std::time::Duration;
+
+    /// A clock that only moves when told to
+    struct MockClock(Mutex<SystemTime>);
+
+    impl MockClock {
+        fn advance(&self, by: Duration) {
+            *self.0.lock() += by;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> SystemTime {
+            *self.0.lock()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ready_waits_for_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mixpanel.json");
+        let saved = Persistence::new(&path);
+        saved.set_distinct_id(Some("user_123".to_string()));
+        saved.save_sync().unwrap();
+
+        let persistence = Arc::new(Persistence::deferred(&path));
+        let ready = spawn_load(Arc::clone(&persistence), |_| {
+            std::thread::sleep(Duration::from_millis(50));
+            Ok(HashMap::from([("$os".to_string(), Value::from("Linux"))]))
+        });
+        assert!(!*ready.borrow());
+
+        wait_ready(&ready).await;
+        assert_eq!(persistence.get_distinct_id(), Some("user_123".to_string()));
+        assert_eq!(persistence.get_property("$os"), Some(Value::from("Linux")));
+    }
+
+    #[test]
+    fn test_persistence_path() {
+        let app_data_dir = Path::new("/data/com.example.app");
+        assert_eq!(
+            persistence_path(app_data_dir, None, "token"),
+            Path::new("/data/com.example.app/mixpanel_token.json")
+        );
+        assert_eq!(
+            persistence_path(app_data_dir, Some(Path::new("analytics")), "token"),
+            Path::new("/data/com.example.app/analytics/mixpanel_token.json")
+        );
+        assert_eq!(
+            persistence_path(app_data_dir, Some(Path::new("/cache/app")), "token"),
+            Path::new("/cache/app/mixpanel_token.json")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_duration_with_mock_clock() {
+        let clock = Arc::new(MockClock(Mutex::new(
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        )));
+        let tracked = Arc::new(Mutex::new(Vec::new()));
+        let captured = Arc::clone(&tracked);
+        let config = Config {
+            dry_run: true,
+            clock: Some(Arc::clone(&clock) as Arc<dyn Clock>),
+            event_filter: Some(Arc::new(move |event: &Event| {
+                captured.lock().push(event.clone());
+                true
+            })),
+            ..Default::default()
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let persistence = Arc::new(Persistence::new(dir.path().join("mixpanel.json")));
+        persistence.set_distinct_id(Some("user_123".to_string()));
+        let (_ready_tx, ready) = watch::channel(true);
+        let state = MixpanelState::from_parts(
+            Mixpanel::init("test_token", Some(config)),
+            persistence,
+            ready,
+        );
+
+        state.time_event("Upload");
+        clock.advance(Duration::from_millis(2500));
+        state.track("Upload".to_string(), None).await.unwrap();
+
+        let tracked = tracked.lock();
+        assert_eq!(tracked.len(), 1);
+        assert_eq!(tracked[0].properties["$duration"], 2.5);
+        assert_eq!(tracked[0].properties["time"], 1_700_000_002);
+    }
+
+    /// A loaded state for `distinct_id`, persisted in `dir`
+    fn identified_state(config: Config, dir: &Path, distinct_id: &str) -> MixpanelState {
+        let persistence = Arc::new(Persistence::new(dir.join("mixpanel.json")));
+        persistence.set_distinct_id(Some(distinct_id.to_string()));
+        let (_ready_tx, ready) = watch::channel(true);
+        MixpanelState::from_parts(
+            Mixpanel::init("test_token", Some(config)),
+            persistence,
+            ready,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_reset() {
+        let server = MockServer::start().await;
+        let dir = tempfile::tempdir().unwrap();
+        let state = identified_state(server.config(), dir.path(), "user_123");
+        state.register(json!({"plan": "pro"}), None).await.unwrap();
+
+        state.reset(false).await.unwrap();
+
+        let distinct_id = state.get_distinct_id().unwrap();
+        assert!(Mixpanel::is_device_id(&distinct_id), "{}", distinct_id);
+        assert_eq!(state.get_property("plan"), None);
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reset_waits_for_identity_events() {
+        for alias in [false, true] {
+            let slow = MockResponse::ok().delay(Duration::from_millis(200));
+            let server = MockServer::with_responses(vec![slow]).await;
+            let dir = tempfile::tempdir().unwrap();
+            let state = Arc::new(identified_state(server.config(), dir.path(), "anon"));
+
+            let task = {
+                let state = Arc::clone(&state);
+                tokio::spawn(async move {
+                    if alias {
+                        state.alias("user".to_string(), None).await
+                    } else {
+                        state.identify("user".to_string()).await
+                    }
+                })
+            };
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            state.reset(false).await.unwrap();
+
+            // Reset ran after the identity events were sent, not before them
+            task.await.unwrap().unwrap();
+            let sent = if alias { 2 } else { 1 };
+            assert_eq!(server.requests().len(), sent);
+            assert!(Mixpanel::is_device_id(&state.get_distinct_id().unwrap()));
+        }
+    }
+}
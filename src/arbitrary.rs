@@ -0,0 +1,74 @@
+// proptest strategies for the property tests of the payload encoding path.
+
+use proptest::prelude::*;
+use serde_json::{Number, Value};
+use std::collections::HashMap;
+
+/// Numbers at the edges of what JSON and Mixpanel's API handle
+fn number() -> impl Strategy<Value = Number> {
+    prop_oneof![
+        any::<i64>().prop_map(Number::from),
+        any::<u64>().prop_map(Number::from),
+        Just(Number::from(i64::MIN)),
+        Just(Number::from(u64::MAX)),
+        any::<f64>()
+            .prop_filter("JSON has no NaN or infinity", |f| f.is_finite())
+            .prop_map(|f| Number::from_f64(f).unwrap()),
+        prop::sample::select(vec![
+            0.0,
+            -0.0,
+            f64::MIN_POSITIVE,
+            f64::MAX,
+            f64::MIN,
+            1e-300
+        ])
+        .prop_map(|f| Number::from_f64(f).unwrap()),
+    ]
+}
+
+/// Keys and strings, including unicode, control characters and the empty string
+pub fn key() -> impl Strategy<Value = String> {
+    prop_oneof!["\\PC{0,12}", ".{0,8}", Just(String::new())]
+}
+
+/// Any JSON value, nested up to 3 levels
+pub fn value() -> impl Strategy<Value = Value> {
+    let leaf = prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::Bool),
+        number().prop_map(Value::Number),
+        key().prop_map(Value::String),
+    ];
+    leaf.prop_recursive(3, 32, 6, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..6).prop_map(Value::Array),
+            prop::collection::hash_map(key(), inner, 0..6)
+                .prop_map(|map| Value::Object(map.into_iter().collect())),
+        ]
+    })
+}
+
+/// Event or profile properties
+pub fn properties() -> impl Strategy<Value = HashMap<String, Value>> {
+    prop::collection::hash_map(key(), value(), 0..12)
+}
+
+/// Whether two values are equal, allowing for the few bits of precision a float can
+/// lose when serde_json parses it back without its `float_roundtrip` feature
+pub fn approx_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) if a.is_f64() || b.is_f64() => {
+            let (a, b) = (a.as_f64().unwrap(), b.as_f64().unwrap());
+            a == b || (a - b).abs() <= 8.0 * f64::EPSILON * a.abs().max(b.abs())
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| approx_eq(a, b))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| approx_eq(a, b)))
+        }
+        (a, b) => a == b,
+    }
+}
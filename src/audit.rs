@@ -0,0 +1,133 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// Callback receiving an [`AuditRecord`] for every request Mixpanel accepted
+pub type AuditSink = Arc<dyn Fn(&AuditRecord) + Send + Sync>;
+
+/// A record of a request confirmed by Mixpanel, with property values redacted
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditRecord {
+    /// Endpoint the payload was sent to, e.g. `/track` or `/engage`
+    pub endpoint: String,
+    /// distinct_ids (or group ids) the payload refers to
+    pub distinct_ids: Vec<String>,
+    /// Event names or profile operations with their property keys, without values
+    pub summary: String,
+    /// Unix timestamp (seconds) at which the send was confirmed
+    pub timestamp: u64,
+}
+
+impl AuditRecord {
+    pub(crate) fn new(endpoint: &str, payload: &Value, timestamp: u64) -> Self {
+        let items = match payload {
+            Value::Array(items) => items.iter().collect(),
+            item => vec![item],
+        };
+
+        let mut distinct_ids = Vec::new();
+        let mut summary = Vec::new();
+        for item in items {
+            let id = item
+                .get("properties")
+                .and_then(|p| p.get("distinct_id"))
+                .or_else(|| item.get("$distinct_id"))
+                .or_else(|| item.get("$group_id"));
+            if let Some(id) = id {
+                let id = id
+                    .as_str()
+                    .map(String::from)
+                    .unwrap_or_else(|| id.to_string());
+                distinct_ids.push(id);
+            }
+            summary.push(summarize(item));
+        }
+
+        Self {
+            endpoint: endpoint.to_string(),
+            distinct_ids,
+            summary: summary.join("; "),
+            timestamp,
+        }
+    }
+}
+
+// Describe a single event or profile update, keeping only keys
+fn summarize(item: &Value) -> String {
+    if let Some(event) = item.get("event").and_then(|e| e.as_str()) {
+        return format!("{} {}", event, keys(item.get("properties")));
+    }
+
+    let Some(map) = item.as_object() else {
+        return String::new();
+    };
+    map.iter()
+        .filter(|(key, _)| key.starts_with('$') && !RESERVED_KEYS.contains(&key.as_str()))
+        .map(|(key, value)| format!("{} {}", key, keys(Some(value))))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+const RESERVED_KEYS: &[&str] = &[
+    "$token",
+    "$distinct_id",
+    "$group_key",
+    "$group_id",
+    "$ip",
+    "$ignore_time",
+    "$ignore_alias",
+    "$time",
+    "$latitude",
+    "$longitude",
+];
+
+fn keys(value: Option<&Value>) -> String {
+    let mut keys: Vec<&str> = match value {
+        Some(Value::Object(map)) => map.keys().map(String::as_str).collect(),
+        Some(Value::Array(items)) => items.iter().filter_map(|i| i.as_str()).collect(),
+        _ => Vec::new(),
+    };
+    keys.sort_unstable();
+    format!("[{}]", keys.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_event_record() {
+        let payload = json!({
+            "event": "Signed Up",
+            "properties": {"distinct_id": "user_1", "plan": "premium", "token": "secret"}
+        });
+
+        let record = AuditRecord::new("/track", &payload, 1234567890);
+
+        assert_eq!(record.endpoint, "/track");
+        assert_eq!(record.distinct_ids, vec!["user_1"]);
+        assert_eq!(record.summary, "Signed Up [distinct_id, plan, token]");
+        assert!(!record.summary.contains("premium"));
+        assert_eq!(record.timestamp, 1234567890);
+    }
+
+    #[test]
+    fn test_engage_record() {
+        let payload = json!({
+            "$token": "token",
+            "$distinct_id": "user_1",
+            "$ip": "1.2.3.4",
+            "$set": {"$email": "user@example.com"},
+            "$unset": ["plan"]
+        });
+
+        let record = AuditRecord::new("/engage", &payload, 0);
+
+        assert_eq!(record.distinct_ids, vec!["user_1"]);
+        assert!(record.summary.contains("$set [$email]"));
+        assert!(record.summary.contains("$unset [plan]"));
+        assert!(!record.summary.contains("user@example.com"));
+        assert!(!record.summary.contains("1.2.3.4"));
+    }
+}
@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+/// Decides how long to wait before retrying a failed request, see `Config::backoff`
+///
+/// `attempt` counts the retries already made, starting at 0 for the first retry. A
+/// `Retry-After` from a rate-limited response takes precedence over the strategy.
+pub trait BackoffStrategy: Send + Sync {
+    fn next_delay(&self, attempt: u32) -> Duration;
+}
+
+/// Doubles the delay after each retry, from `base_delay_ms` up to `max_delay_ms`
+///
+/// This is the default, built from `Config::retry_base_delay_ms` and
+/// `Config::retry_max_delay_ms`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExponentialBackoff {
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl BackoffStrategy for ExponentialBackoff {
+    fn next_delay(&self, attempt: u32) -> Duration {
+        let factor = 1u64.checked_shl(attempt).unwrap_or(u64::MAX);
+        let delay = self.base_delay_ms.saturating_mul(factor);
+        Duration::from_millis(delay.min(self.max_delay_ms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exponential_backoff() {
+        let backoff = ExponentialBackoff {
+            base_delay_ms: 100,
+            max_delay_ms: 1000,
+        };
+        let delays: Vec<u64> = (0..5)
+            .map(|attempt| backoff.next_delay(attempt).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1000]);
+        assert_eq!(backoff.next_delay(100), Duration::from_millis(1000));
+    }
+}
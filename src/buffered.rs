@@ -0,0 +1,355 @@
+use crate::{DropReason, Error, Event, Mixpanel, Result, MAX_BATCH_SIZE};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+/// A client that queues events in memory and sends them to Mixpanel in batches
+///
+/// Events are sent once a full batch is queued, when [`flush`](Self::flush) is called,
+/// or when the client is shut down with [`graceful_shutdown`](Self::graceful_shutdown).
+/// Queued events are lost if the process exits without flushing, so services should
+/// shut the client down before the runtime stops:
+///
+/// ```no_run
+/// use mixpanel_rs::{BufferedClient, Mixpanel};
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let client = BufferedClient::new(Mixpanel::init("token", None));
+///
+///     // ... track events while serving requests ...
+///
+///     // On Unix, listen for SIGTERM with `tokio::signal::unix::signal` instead
+///     tokio::signal::ctrl_c().await.unwrap();
+///     let report = client.graceful_shutdown(Duration::from_secs(5)).await;
+///     println!("sent {} events, dropped {}", report.sent, report.dropped);
+/// }
+/// ```
+pub struct BufferedClient {
+    mixpanel: Mixpanel,
+    buffer: Mutex<Vec<Event>>,
+    capacity: usize,
+    closed: AtomicBool,
+}
+
+/// Whether [`BufferedClient::track`] queued an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnqueueOutcome {
+    Accepted,
+    /// The queue already held `capacity` events, e.g. because Mixpanel kept failing
+    DroppedBufferFull,
+}
+
+/// Outcome of [`BufferedClient::graceful_shutdown`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Events Mixpanel accepted during shutdown
+    pub sent: usize,
+    /// Events that failed to send or were still queued when the deadline passed
+    pub dropped: usize,
+}
+
+impl BufferedClient {
+    /// A client with an unbounded queue
+    pub fn new(mixpanel: Mixpanel) -> Self {
+        Self::with_capacity(mixpanel, usize::MAX)
+    }
+
+    /// A client queueing at most `capacity` events, so a Mixpanel outage can't grow the
+    /// queue without bounds. Events tracked while it's full are dropped.
+    ///
+    /// Batches put back by a failed [`flush`](Self::flush) are kept even past `capacity`.
+    pub fn with_capacity(mixpanel: Mixpanel, capacity: usize) -> Self {
+        Self {
+            mixpanel,
+            buffer: Mutex::new(Vec::new()),
+            capacity,
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// The wrapped client, for profile updates and super properties
+    pub fn mixpanel(&self) -> &Mixpanel {
+        &self.mixpanel
+    }
+
+    /// Queue an event, sending the queue once it holds a full batch
+    ///
+    /// Returns `DroppedBufferFull` instead of queueing when the queue is at capacity, so
+    /// callers can react to backpressure. Such events are reported to `Config::drop_hook`
+    /// and counted in `ClientStats::events_dropped_buffer_full`.
+    ///
+    /// A queued event is `Accepted` even if sending the full batch then fails: the batch
+    /// stays queued for the next flush, and the failure is reported through
+    /// `Config::warning_hook`.
+    pub async fn track<S: Into<String>>(
+        &self,
+        event: S,
+        properties: Option<HashMap<String, Value>>,
+    ) -> Result<EnqueueOutcome> {
+        let event = Event {
+            event: event.into(),
+            properties: properties.unwrap_or_default(),
+        };
+        let full = {
+            let mut buffer = self.buffer.lock().unwrap();
+            if self.closed.load(Ordering::SeqCst) {
+                return Err(Error::ClientShutdown);
+            }
+            if buffer.len() >= self.capacity {
+                drop(buffer);
+                self.drop_buffer_full(&event);
+                return Ok(EnqueueOutcome::DroppedBufferFull);
+            }
+            buffer.push(event);
+            buffer.len() >= MAX_BATCH_SIZE
+        };
+
+        if full {
+            if let Err(e) = self.flush().await {
+                self.mixpanel.warn(&format!(
+                    "sending a full batch failed, keeping it queued: {}",
+                    e
+                ));
+            }
+        }
+        Ok(EnqueueOutcome::Accepted)
+    }
+
+    fn drop_buffer_full(&self, event: &Event) {
+        if self.mixpanel.config.debug {
+            println!("Mixpanel: queue is full, dropping event '{}'", event.event);
+        }
+        self.mixpanel
+            .stats
+            .events_dropped_buffer_full
+            .fetch_add(1, Ordering::Relaxed);
+        self.mixpanel.report_drop(event, DropReason::BufferFull);
+    }
+
+    /// Number of events waiting to be sent
+    pub fn pending(&self) -> usize {
+        self.buffer.lock().unwrap().len()
+    }
+
+    /// Most events the queue holds, see [`with_capacity`](Self::with_capacity)
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Send every queued event
+    ///
+    /// If a batch fails, it and the batches after it are put back in the queue. Once the
+    /// client is shut down they're dropped instead, as nothing would send them.
+    pub async fn flush(&self) -> Result<()> {
+        let mut events = std::mem::take(&mut *self.buffer.lock().unwrap());
+        while !events.is_empty() {
+            let rest = events.split_off(events.len().min(MAX_BATCH_SIZE));
+            if let Err(e) = self.mixpanel.track_batch(events.clone()).await {
+                events.extend(rest);
+                let mut buffer = self.buffer.lock().unwrap();
+                if self.closed.load(Ordering::SeqCst) {
+                    drop(buffer);
+                    if self.mixpanel.config.debug {
+                        println!(
+                            "Mixpanel: dropping {} events after shutdown: {}",
+                            events.len(),
+                            e
+                        );
+                    }
+                    return Err(e);
+                }
+                events.append(&mut buffer);
+                *buffer = events;
+                return Err(e);
+            }
+            events = rest;
+        }
+        Ok(())
+    }
+
+    /// Stop accepting events and send everything queued before `timeout` elapses
+    ///
+    /// Events that fail to send, or are still queued at the deadline, are dropped and
+    /// counted in the returned report.
+    pub async fn graceful_shutdown(&self, timeout: Duration) -> ShutdownReport {
+        let deadline = Instant::now() + timeout;
+        let mut events = {
+            let mut buffer = self.buffer.lock().unwrap();
+            self.closed.store(true, Ordering::SeqCst);
+            std::mem::take(&mut *buffer)
+        };
+        let mut report = ShutdownReport::default();
+
+        while !events.is_empty() {
+            let rest = events.split_off(events.len().min(MAX_BATCH_SIZE));
+            let count = events.len();
+            match time::timeout_at(deadline, self.mixpanel.track_batch(events)).await {
+                Ok(Ok(())) => report.sent += count,
+                Ok(Err(e)) => {
+                    if self.mixpanel.config.debug {
+                        println!("Mixpanel: dropping {} events on shutdown: {}", count, e);
+                    }
+                    report.dropped += count;
+                }
+                Err(_) => {
+                    report.dropped += count + rest.len();
+                    break;
+                }
+            }
+            events = rest;
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{MockResponse, MockServer};
+    use crate::Config;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_flush_at_full_batch() {
+        let server = MockServer::start().await;
+        let client = BufferedClient::new(Mixpanel::init("test_token", Some(server.config())));
+
+        for _ in 0..MAX_BATCH_SIZE - 1 {
+            client.track("Queued", None).await.unwrap();
+        }
+        assert!(server.requests().is_empty());
+
+        client.track("Queued", None).await.unwrap();
+        assert_eq!(client.pending(), 0);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].data().as_array().unwrap().len(), MAX_BATCH_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_full_buffer() {
+        let server = MockServer::start().await;
+        let dropped = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&dropped);
+        let config = Config {
+            drop_hook: Some(Arc::new(move |event: &Event, reason| {
+                recorded.lock().unwrap().push((event.event.clone(), reason))
+            })),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        let client = BufferedClient::with_capacity(mp, 3);
+
+        for _ in 0..3 {
+            let outcome = client.track("Queued", None).await.unwrap();
+            assert_eq!(outcome, EnqueueOutcome::Accepted);
+        }
+        let outcome = client.track("Dropped", None).await.unwrap();
+        assert_eq!(outcome, EnqueueOutcome::DroppedBufferFull);
+        assert_eq!(client.pending(), 3);
+        assert_eq!(
+            *dropped.lock().unwrap(),
+            vec![("Dropped".to_string(), DropReason::BufferFull)]
+        );
+        assert_eq!(client.mixpanel().stats().events_dropped_buffer_full, 1);
+
+        client.flush().await.unwrap();
+        let outcome = client.track("Queued", None).await.unwrap();
+        assert_eq!(outcome, EnqueueOutcome::Accepted);
+
+        let sent = server.requests()[0].data();
+        assert_eq!(sent.as_array().unwrap().len(), 3);
+        assert!(sent
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|event| event["event"] == "Queued"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_flush_at_full_batch() {
+        let server = MockServer::with_responses(vec![MockResponse::new(500, "")]).await;
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&warnings);
+        let config = Config {
+            internal_retries_enabled: false,
+            warning_hook: Some(Arc::new(move |message: &str| {
+                recorded.lock().unwrap().push(message.to_string())
+            })),
+            ..server.config()
+        };
+        let client = BufferedClient::new(Mixpanel::init("test_token", Some(config)));
+
+        for _ in 0..MAX_BATCH_SIZE {
+            let outcome = client.track("Queued", None).await.unwrap();
+            assert_eq!(outcome, EnqueueOutcome::Accepted);
+        }
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(client.pending(), MAX_BATCH_SIZE);
+        assert_eq!(warnings.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown() {
+        let server = MockServer::start().await;
+        let client = BufferedClient::new(Mixpanel::init("test_token", Some(server.config())));
+
+        for _ in 0..10 {
+            client.track("Queued", None).await.unwrap();
+        }
+        let report = client.graceful_shutdown(Duration::from_secs(5)).await;
+
+        assert_eq!(report.sent, 10);
+        assert_eq!(report.dropped, 0);
+        assert_eq!(server.requests().len(), 1);
+        assert!(matches!(
+            client.track("Late", None).await,
+            Err(Error::ClientShutdown)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown_deadline() {
+        let slow = MockResponse::ok().delay(Duration::from_secs(5));
+        let server = MockServer::with_responses(vec![slow]).await;
+        let client = BufferedClient::new(Mixpanel::init("test_token", Some(server.config())));
+
+        for _ in 0..3 {
+            client.track("Queued", None).await.unwrap();
+        }
+        let report = client.graceful_shutdown(Duration::from_millis(50)).await;
+
+        assert_eq!(report.sent, 0);
+        assert_eq!(report.dropped, 3);
+        assert_eq!(client.pending(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_failed_flush_after_shutdown() {
+        let failing = MockResponse::new(500, "").delay(Duration::from_millis(200));
+        let server = MockServer::with_responses(vec![failing]).await;
+        let config = Config {
+            internal_retries_enabled: false,
+            ..server.config()
+        };
+        let client = BufferedClient::new(Mixpanel::init("test_token", Some(config)));
+
+        for _ in 0..3 {
+            client.track("Queued", None).await.unwrap();
+        }
+        let (flushed, report) = tokio::join!(client.flush(), async {
+            time::sleep(Duration::from_millis(50)).await;
+            client.graceful_shutdown(Duration::from_secs(1)).await
+        });
+
+        assert!(flushed.is_err());
+        assert_eq!(report, ShutdownReport::default());
+        assert_eq!(client.pending(), 0);
+    }
+}
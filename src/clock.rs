@@ -0,0 +1,18 @@
+use std::time::SystemTime;
+
+/// Source of the current time, see `Config::clock`
+///
+/// Replace it in tests to control event timestamps and durations without sleeping.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The system's wall clock, used when `Config::clock` isn't set
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+use std::collections::HashMap;
+
+/// A single user profile update, as sent to `/engage`
+///
+/// Operations serialize, so they can be logged, queued and replayed later with
+/// [`MixpanelPeople::send_op`](crate::people::MixpanelPeople::send_op).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum EngageOp {
+    Set {
+        distinct_id: String,
+        properties: HashMap<String, Value>,
+    },
+    SetOnce {
+        distinct_id: String,
+        properties: HashMap<String, Value>,
+    },
+    Add {
+        distinct_id: String,
+        properties: HashMap<String, Number>,
+    },
+    Append {
+        distinct_id: String,
+        properties: HashMap<String, Value>,
+    },
+    Remove {
+        distinct_id: String,
+        properties: HashMap<String, Value>,
+    },
+    Union {
+        distinct_id: String,
+        properties: HashMap<String, Value>,
+    },
+    Unset {
+        distinct_id: String,
+        properties: Vec<String>,
+    },
+    Delete {
+        distinct_id: String,
+    },
+}
+
+impl EngageOp {
+    /// The profile this operation applies to
+    pub fn distinct_id(&self) -> &str {
+        match self {
+            EngageOp::Set { distinct_id, .. }
+            | EngageOp::SetOnce { distinct_id, .. }
+            | EngageOp::Add { distinct_id, .. }
+            | EngageOp::Append { distinct_id, .. }
+            | EngageOp::Remove { distinct_id, .. }
+            | EngageOp::Union { distinct_id, .. }
+            | EngageOp::Unset { distinct_id, .. }
+            | EngageOp::Delete { distinct_id } => distinct_id,
+        }
+    }
+
+    /// Mixpanel's name for the operation, e.g. `$set`
+    pub fn action(&self) -> &'static str {
+        match self {
+            EngageOp::Set { .. } => "$set",
+            EngageOp::SetOnce { .. } => "$set_once",
+            EngageOp::Add { .. } => "$add",
+            EngageOp::Append { .. } => "$append",
+            EngageOp::Remove { .. } => "$remove",
+            EngageOp::Union { .. } => "$union",
+            EngageOp::Unset { .. } => "$unset",
+            EngageOp::Delete { .. } => "$delete",
+        }
+    }
+
+    /// Build the `/engage` payload for the project identified by `token`
+    pub fn to_payload(&self, token: &str) -> Value {
+        let value = match self {
+            EngageOp::Set { properties, .. }
+            | EngageOp::SetOnce { properties, .. }
+            | EngageOp::Append { properties, .. }
+            | EngageOp::Remove { properties, .. }
+            | EngageOp::Union { properties, .. } => serde_json::json!(properties),
+            EngageOp::Add { properties, .. } => serde_json::json!(properties),
+            EngageOp::Unset { properties, .. } => serde_json::json!(properties),
+            EngageOp::Delete { .. } => Value::String(String::new()),
+        };
+
+        serde_json::json!({
+            "$token": token,
+            "$distinct_id": self.distinct_id(),
+            self.action(): value
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_to_payload() {
+        let props = HashMap::from([("plan".to_string(), json!("pro"))]);
+        let id = || "user".to_string();
+        let ops = [
+            (
+                EngageOp::Set {
+                    distinct_id: id(),
+                    properties: props.clone(),
+                },
+                json!({"$set": {"plan": "pro"}}),
+            ),
+            (
+                EngageOp::SetOnce {
+                    distinct_id: id(),
+                    properties: props.clone(),
+                },
+                json!({"$set_once": {"plan": "pro"}}),
+            ),
+            (
+                EngageOp::Add {
+                    distinct_id: id(),
+                    properties: HashMap::from([("logins".to_string(), Number::from(1))]),
+                },
+                json!({"$add": {"logins": 1}}),
+            ),
+            (
+                EngageOp::Append {
+                    distinct_id: id(),
+                    properties: props.clone(),
+                },
+                json!({"$append": {"plan": "pro"}}),
+            ),
+            (
+                EngageOp::Remove {
+                    distinct_id: id(),
+                    properties: props.clone(),
+                },
+                json!({"$remove": {"plan": "pro"}}),
+            ),
+            (
+                EngageOp::Union {
+                    distinct_id: id(),
+                    properties: props,
+                },
+                json!({"$union": {"plan": "pro"}}),
+            ),
+            (
+                EngageOp::Unset {
+                    distinct_id: id(),
+                    properties: vec!["plan".to_string()],
+                },
+                json!({"$unset": ["plan"]}),
+            ),
+            (
+                EngageOp::Delete { distinct_id: id() },
+                json!({"$delete": ""}),
+            ),
+        ];
+
+        for (op, mut expected) in ops {
+            expected["$token"] = json!("token");
+            expected["$distinct_id"] = json!("user");
+            assert_eq!(op.to_payload("token"), expected, "{}", op.action());
+        }
+    }
+
+    #[test]
+    fn test_serialize_roundtrip() {
+        let op = EngageOp::Unset {
+            distinct_id: "user".to_string(),
+            properties: vec!["plan".to_string()],
+        };
+        let value = serde_json::to_value(&op).unwrap();
+        assert_eq!(value["op"], "unset");
+        assert_eq!(serde_json::from_value::<EngageOp>(value).unwrap(), op);
+    }
+}
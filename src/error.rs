@@ -12,6 +12,24 @@ pub enum Error {
     #[error("JSON serialization error: {0}")]
     JsonError(#[from] serde_json::Error),
 
+    #[error("Failed to serialize {payload} for {endpoint}: {source}")]
+    Serialization {
+        /// Endpoint the payload was meant for, e.g. `/track`
+        endpoint: String,
+        /// Type name of the payload that failed to serialize
+        payload: String,
+        source: serde_json::Error,
+    },
+
+    #[error("Stream ended mid-record after {rows} complete rows")]
+    TruncatedStream {
+        /// Rows parsed before the truncated one, to resume an export from
+        rows: usize,
+    },
+
+    #[error("Could not resolve host {0}")]
+    HostResolution(String),
+
     #[error("Mixpanel API server error (HTTP {0})")]
     ApiServerError(u16),
 
@@ -30,10 +48,47 @@ pub enum Error {
     #[error("Mixpanel API unexpected response: {0}")]
     ApiUnexpectedResponse(String),
 
+    #[error(
+        "Unexpected {content_type} response, likely from a proxy or captive portal: {snippet}"
+    )]
+    UnexpectedContentType {
+        /// `Content-Type` of the response, empty if it had none
+        content_type: String,
+        /// Start of the response body
+        snippet: String,
+    },
+
     #[error("Time conversion error")]
     TimeError,
 
     #[error("Max retries reached: {0}")]
     MaxRetriesReached(String),
+
+    #[error("Invalid alias: {0}")]
+    InvalidAlias(String),
+
+    #[error("Invalid properties: {0}")]
+    InvalidProperties(String),
+
+    #[error("Invalid property '{key}': {reason}")]
+    InvalidProperty { key: String, reason: String },
+
+    #[error("Invalid event: {0}")]
+    InvalidEvent(String),
+
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+
+    #[error("Failed to build the HTTP client: {0}")]
+    ClientBuild(String),
+
+    #[error("Missing credentials: {0} requires config.secret")]
+    MissingCredentials(String),
+
+    #[error("Missing project id: {0} requires config.project_id with service account credentials")]
+    MissingProjectId(String),
+
+    #[error("Client is shut down")]
+    ClientShutdown,
 }
 
@@ -1,7 +1,14 @@
-use crate::{Mixpanel, Modifiers, Result};
+use crate::{Error, Mixpanel, Modifiers, PropertyInput, RequestOptions, Result};
+use serde::Serialize;
 use serde_json::Value;
 use std::collections::HashMap;
 
+/// Group profile updates, sent to `/groups`
+///
+/// Group ids can be strings or numbers. Events are matched to a group profile by the value
+/// of the group key property (e.g. `"company": 42`), so the id must have the same type on
+/// the profile and on every event: a profile created with `42` doesn't match events sending
+/// `"42"`.
 #[derive(Debug, Clone, Default)]
 pub struct MixpanelGroups {
     pub(crate) mixpanel: Option<Box<Mixpanel>>,
@@ -9,14 +16,55 @@ pub struct MixpanelGroups {
 
 impl MixpanelGroups {
     /// Set properties on a group profile
-    pub async fn set<S: Into<String>>(
+    pub async fn set<S: Into<String>, G: Into<Value>, P: Into<PropertyInput>>(
         &self,
         group_key: S,
-        group_id: S,
-        properties: HashMap<String, Value>,
+        group_id: G,
+        properties: P,
+        modifiers: Option<Modifiers>,
+    ) -> Result<()> {
+        self.set_returning_id(group_key, group_id, properties, modifiers)
+            .await
+            .map(|_| ())
+    }
+
+    /// Set properties on a group profile from any value serializing to a JSON object
+    pub async fn set_struct<S: Into<String>, G: Into<Value>, T: Serialize + ?Sized>(
+        &self,
+        group_key: S,
+        group_id: G,
+        properties: &T,
+        modifiers: Option<Modifiers>,
+    ) -> Result<()> {
+        let properties = crate::utils::to_properties(properties)?;
+        self.set(group_key, group_id, properties, modifiers).await
+    }
+
+    /// Set properties on a group profile only if they haven't been set before
+    pub async fn set_once<S: Into<String>, G: Into<Value>, P: Into<PropertyInput>>(
+        &self,
+        group_key: S,
+        group_id: G,
+        properties: P,
         modifiers: Option<Modifiers>,
     ) -> Result<()> {
+        self.set_once_returning_id(group_key, group_id, properties, modifiers)
+            .await
+            .map(|_| ())
+    }
+
+    /// Like [`set`](Self::set), returning the profile's group key and id
+    pub async fn set_returning_id<S: Into<String>, G: Into<Value>, P: Into<PropertyInput>>(
+        &self,
+        group_key: S,
+        group_id: G,
+        properties: P,
+        modifiers: Option<Modifiers>,
+    ) -> Result<(String, Value)> {
+        let properties = properties.into().into_properties()?;
+        let token = self.token();
         self._set(
+            &token,
             group_key.into(),
             group_id.into(),
             properties,
@@ -26,15 +74,18 @@ impl MixpanelGroups {
         .await
     }
 
-    /// Set properties on a group profile only if they haven't been set before
-    pub async fn set_once<S: Into<String>>(
+    /// Like [`set_once`](Self::set_once), returning the profile's group key and id
+    pub async fn set_once_returning_id<S: Into<String>, G: Into<Value>, P: Into<PropertyInput>>(
         &self,
         group_key: S,
-        group_id: S,
-        properties: HashMap<String, Value>,
+        group_id: G,
+        properties: P,
         modifiers: Option<Modifiers>,
-    ) -> Result<()> {
+    ) -> Result<(String, Value)> {
+        let properties = properties.into().into_properties()?;
+        let token = self.token();
         self._set(
+            &token,
             group_key.into(),
             group_id.into(),
             properties,
@@ -44,142 +95,300 @@ impl MixpanelGroups {
         .await
     }
 
-    /// Delete a group profile
-    pub async fn delete_group<S: Into<String>>(
+    /// Set properties on a group profile in the project identified by `token`
+    pub async fn set_for_token<S: Into<String>, G: Into<Value>, P: Into<PropertyInput>>(
         &self,
+        token: &str,
         group_key: S,
-        group_id: S,
+        group_id: G,
+        properties: P,
         modifiers: Option<Modifiers>,
     ) -> Result<()> {
-        let mut data = serde_json::json!({
-            "$token": self.mixpanel.as_ref().unwrap().token,
-            "$group_key": group_key.into(),
-            "$group_id": group_id.into(),
-            "$delete": ""
-        });
+        let properties = properties.into().into_properties()?;
+        self._set(
+            token,
+            group_key.into(),
+            group_id.into(),
+            properties,
+            modifiers,
+            false,
+        )
+        .await
+        .map(|_| ())
+    }
 
-        if let Some(modifiers) = modifiers {
-            data = crate::utils::merge_modifiers(data, Some(modifiers));
-        }
+    /// Set properties on a group profile only if they haven't been set before, in the
+    /// project identified by `token`
+    pub async fn set_once_for_token<S: Into<String>, G: Into<Value>, P: Into<PropertyInput>>(
+        &self,
+        token: &str,
+        group_key: S,
+        group_id: G,
+        properties: P,
+        modifiers: Option<Modifiers>,
+    ) -> Result<()> {
+        let properties = properties.into().into_properties()?;
+        self._set(
+            token,
+            group_key.into(),
+            group_id.into(),
+            properties,
+            modifiers,
+            true,
+        )
+        .await
+        .map(|_| ())
+    }
 
-        self.mixpanel
-            .as_ref()
-            .unwrap()
-            .send_request("GET", "/groups", &data)
+    /// Delete a group profile
+    pub async fn delete_group<S: Into<String>, G: Into<Value>>(
+        &self,
+        group_key: S,
+        group_id: G,
+        modifiers: Option<Modifiers>,
+    ) -> Result<()> {
+        let token = self.token();
+        self.delete_group_for_token(&token, group_key, group_id, modifiers)
             .await
     }
 
-    /// Remove a value from a list-valued group profile property
-    pub async fn remove<S: Into<String>>(
+    /// Delete a group profile in the project identified by `token`
+    pub async fn delete_group_for_token<S: Into<String>, G: Into<Value>>(
         &self,
+        token: &str,
         group_key: S,
-        group_id: S,
-        properties: HashMap<String, Value>,
+        group_id: G,
         modifiers: Option<Modifiers>,
     ) -> Result<()> {
-        let mut data = serde_json::json!({
-            "$token": self.mixpanel.as_ref().unwrap().token,
-            "$group_key": group_key.into(),
-            "$group_id": group_id.into(),
-            "$remove": properties
-        });
-
-        if let Some(modifiers) = modifiers {
-            data = crate::utils::merge_modifiers(data, Some(modifiers));
-        }
+        self.send_op(
+            token,
+            group_key.into(),
+            group_id.into(),
+            "$delete",
+            "".into(),
+            modifiers,
+        )
+        .await
+        .map(|_| ())
+    }
 
-        self.mixpanel
-            .as_ref()
-            .unwrap()
-            .send_request("GET", "/groups", &data)
+    /// Remove a value from a list-valued group profile property
+    pub async fn remove<S: Into<String>, G: Into<Value>, P: Into<PropertyInput>>(
+        &self,
+        group_key: S,
+        group_id: G,
+        properties: P,
+        modifiers: Option<Modifiers>,
+    ) -> Result<()> {
+        let token = self.token();
+        self.remove_for_token(&token, group_key, group_id, properties, modifiers)
             .await
     }
 
+    /// Remove a value from a list-valued group profile property, in the project identified
+    /// by `token`
+    pub async fn remove_for_token<S: Into<String>, G: Into<Value>, P: Into<PropertyInput>>(
+        &self,
+        token: &str,
+        group_key: S,
+        group_id: G,
+        properties: P,
+        modifiers: Option<Modifiers>,
+    ) -> Result<()> {
+        let properties = properties.into().into_properties()?;
+        self.send_op(
+            token,
+            group_key.into(),
+            group_id.into(),
+            "$remove",
+            serde_json::json!(properties),
+            modifiers,
+        )
+        .await
+        .map(|_| ())
+    }
+
     /// Union a value to a list-valued group profile property
-    pub async fn union<S: Into<String>>(
+    pub async fn union<S: Into<String>, G: Into<Value>, P: Into<PropertyInput>>(
         &self,
         group_key: S,
-        group_id: S,
-        properties: HashMap<String, Value>,
+        group_id: G,
+        properties: P,
         modifiers: Option<Modifiers>,
     ) -> Result<()> {
-        let mut data = serde_json::json!({
-            "$token": self.mixpanel.as_ref().unwrap().token,
-            "$group_key": group_key.into(),
-            "$group_id": group_id.into(),
-            "$union": properties
-        });
+        let token = self.token();
+        self.union_for_token(&token, group_key, group_id, properties, modifiers)
+            .await
+    }
 
-        if let Some(modifiers) = modifiers {
-            data = crate::utils::merge_modifiers(data, Some(modifiers));
-        }
+    /// Union a value to a list-valued group profile property, in the project identified by
+    /// `token`
+    pub async fn union_for_token<S: Into<String>, G: Into<Value>, P: Into<PropertyInput>>(
+        &self,
+        token: &str,
+        group_key: S,
+        group_id: G,
+        properties: P,
+        modifiers: Option<Modifiers>,
+    ) -> Result<()> {
+        let properties = properties.into().into_properties()?;
+        self.send_op(
+            token,
+            group_key.into(),
+            group_id.into(),
+            "$union",
+            serde_json::json!(properties),
+            modifiers,
+        )
+        .await
+        .map(|_| ())
+    }
 
-        self.mixpanel
-            .as_ref()
-            .unwrap()
-            .send_request("GET", "/groups", &data)
+    /// Increment numeric properties on a group profile, e.g. per-company usage counters
+    pub async fn increment<S: Into<String>, G: Into<Value>>(
+        &self,
+        group_key: S,
+        group_id: G,
+        properties: HashMap<String, f64>,
+        modifiers: Option<Modifiers>,
+    ) -> Result<()> {
+        let token = self.token();
+        self.increment_for_token(&token, group_key, group_id, properties, modifiers)
             .await
     }
 
-    /// Unset properties on a group profile
-    pub async fn unset<S: Into<String>>(
+    /// Increment numeric properties on a group profile in the project identified by `token`
+    pub async fn increment_for_token<S: Into<String>, G: Into<Value>>(
         &self,
+        token: &str,
         group_key: S,
-        group_id: S,
-        properties: Vec<String>,
+        group_id: G,
+        properties: HashMap<String, f64>,
         modifiers: Option<Modifiers>,
     ) -> Result<()> {
-        let mut data = serde_json::json!({
-            "$token": self.mixpanel.as_ref().unwrap().token,
-            "$group_key": group_key.into(),
-            "$group_id": group_id.into(),
-            "$unset": properties
-        });
-
-        if let Some(modifiers) = modifiers {
-            data = crate::utils::merge_modifiers(data, Some(modifiers));
-        }
+        self.send_op(
+            token,
+            group_key.into(),
+            group_id.into(),
+            "$add",
+            serde_json::json!(properties),
+            modifiers,
+        )
+        .await
+        .map(|_| ())
+    }
 
-        self.mixpanel
-            .as_ref()
-            .unwrap()
-            .send_request("GET", "/groups", &data)
+    /// Unset properties on a group profile
+    pub async fn unset<S: Into<String>, G: Into<Value>, P: Into<PropertyInput>>(
+        &self,
+        group_key: S,
+        group_id: G,
+        properties: P,
+        modifiers: Option<Modifiers>,
+    ) -> Result<()> {
+        let token = self.token();
+        self.unset_for_token(&token, group_key, group_id, properties, modifiers)
             .await
     }
 
+    /// Unset properties on a group profile in the project identified by `token`
+    pub async fn unset_for_token<S: Into<String>, G: Into<Value>, P: Into<PropertyInput>>(
+        &self,
+        token: &str,
+        group_key: S,
+        group_id: G,
+        properties: P,
+        modifiers: Option<Modifiers>,
+    ) -> Result<()> {
+        let properties = properties.into().into_keys()?;
+        self.send_op(
+            token,
+            group_key.into(),
+            group_id.into(),
+            "$unset",
+            serde_json::json!(properties),
+            modifiers,
+        )
+        .await
+        .map(|_| ())
+    }
+
     // Internal helper for set and set_once
     async fn _set(
         &self,
+        token: &str,
         group_key: String,
-        group_id: String,
+        group_id: Value,
         properties: HashMap<String, Value>,
         modifiers: Option<Modifiers>,
         set_once: bool,
-    ) -> Result<()> {
+    ) -> Result<(String, Value)> {
         let operation = if set_once { "$set_once" } else { "$set" };
+        self.send_op(
+            token,
+            group_key,
+            group_id,
+            operation,
+            serde_json::json!(properties),
+            modifiers,
+        )
+        .await
+    }
 
-        let mut data = serde_json::json!({
-            "$token": self.mixpanel.as_ref().unwrap().token,
+    // Build a group profile update for the project identified by `token` and send it, with
+    // the call's modifiers merged over `Config::default_modifiers` and
+    // `Modifiers::geolocate` and `Modifiers::test` overriding the config for this request.
+    // Returns the profile's group key and id.
+    async fn send_op(
+        &self,
+        token: &str,
+        group_key: String,
+        group_id: Value,
+        operation: &str,
+        value: Value,
+        modifiers: Option<Modifiers>,
+    ) -> Result<(String, Value)> {
+        let group_id = group_id_value(group_id)?;
+
+        let data = serde_json::json!({
+            "$token": token,
             "$group_key": group_key,
             "$group_id": group_id,
-            operation: properties
+            operation: value
         });
 
-        if let Some(modifiers) = modifiers {
-            data = crate::utils::merge_modifiers(data, Some(modifiers));
-        }
+        let mixpanel = self.mixpanel.as_ref().unwrap();
+        let modifiers = mixpanel.config.modifiers_for(modifiers);
+        let options = RequestOptions::with_modifiers(&mixpanel.config, modifiers.as_ref(), None);
+        let data = crate::utils::merge_modifiers(data, modifiers);
 
-        self.mixpanel
-            .as_ref()
-            .unwrap()
-            .send_request("GET", "/groups", &data)
-            .await
+        mixpanel
+            .send_request_with("GET", "/groups", &data, options)
+            .await?;
+        Ok((group_key, group_id))
+    }
+
+    // The client's own project token
+    fn token(&self) -> String {
+        self.mixpanel.as_ref().unwrap().token.clone()
+    }
+}
+
+// Mixpanel only accepts string and numeric group ids
+fn group_id_value(group_id: Value) -> Result<Value> {
+    match group_id {
+        Value::String(_) | Value::Number(_) => Ok(group_id),
+        other => Err(Error::InvalidProperties(format!(
+            "group id must be a string or a number, got {}",
+            other
+        ))),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock_server::MockServer;
 
     #[tokio::test]
     async fn test_set() {
@@ -201,6 +410,116 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_set_for_token() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        let props = HashMap::from([("plan".to_string(), serde_json::json!("pro"))]);
+
+        mp.groups
+            .set_for_token("tenant_token", "company", 42, props.clone(), None)
+            .await
+            .unwrap();
+        mp.groups
+            .set_once_for_token("tenant_token", "company", 42, props, None)
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].path, "/groups");
+        assert_eq!(requests[0].data()["$token"], "tenant_token");
+        assert_eq!(requests[0].data()["$set"]["plan"], "pro");
+        assert_eq!(requests[1].data()["$token"], "tenant_token");
+        assert_eq!(requests[1].data()["$set_once"]["plan"], "pro");
+    }
+
+    #[tokio::test]
+    async fn test_ops_for_token() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        let products = HashMap::from([("products".to_string(), serde_json::json!(["anvil"]))]);
+
+        mp.groups
+            .remove_for_token("tenant_token", "company", 42, products.clone(), None)
+            .await
+            .unwrap();
+        mp.groups
+            .union_for_token("tenant_token", "company", 42, products, None)
+            .await
+            .unwrap();
+        mp.groups
+            .increment_for_token(
+                "tenant_token",
+                "company",
+                42,
+                HashMap::from([("api_calls".to_string(), 1.0)]),
+                None,
+            )
+            .await
+            .unwrap();
+        mp.groups
+            .unset_for_token("tenant_token", "company", 42, "products", None)
+            .await
+            .unwrap();
+        mp.groups
+            .delete_group_for_token("tenant_token", "company", 42, None)
+            .await
+            .unwrap();
+        mp.groups.delete_group("company", 42, None).await.unwrap();
+
+        let requests = server.requests();
+        let operations = ["$remove", "$union", "$add", "$unset", "$delete"];
+        for (request, operation) in requests.iter().zip(operations) {
+            let data = request.data();
+            assert_eq!(data["$token"], "tenant_token");
+            assert_eq!(data["$group_id"], 42);
+            assert!(data.get(operation).is_some(), "missing {}", operation);
+        }
+        assert_eq!(requests[5].data()["$token"], "test_token");
+    }
+
+    #[tokio::test]
+    async fn test_property_input_shapes() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+
+        let map = HashMap::from([("plan".to_string(), serde_json::json!("pro"))]);
+        mp.groups.set("company", 42, map, None).await.unwrap();
+        mp.groups
+            .set_once("company", 42, serde_json::json!({"founded": 2020}), None)
+            .await
+            .unwrap();
+        mp.groups.unset("company", 42, "plan", None).await.unwrap();
+        mp.groups
+            .unset("company", 42, vec!["plan", "seats"], None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            mp.groups.set("company", 42, "plan", None).await,
+            Err(Error::InvalidProperties(_))
+        ));
+        assert!(matches!(
+            mp.groups.remove("company", 42, vec!["plan"], None).await,
+            Err(Error::InvalidProperties(_))
+        ));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(
+            requests[0].data()["$set"],
+            serde_json::json!({"plan": "pro"})
+        );
+        assert_eq!(
+            requests[1].data()["$set_once"],
+            serde_json::json!({"founded": 2020})
+        );
+        assert_eq!(requests[2].data()["$unset"], serde_json::json!(["plan"]));
+        assert_eq!(
+            requests[3].data()["$unset"],
+            serde_json::json!(["plan", "seats"])
+        );
+    }
+
     #[tokio::test]
     async fn test_delete_group() {
         let mp = Mixpanel::init("test_token", None);
@@ -268,6 +587,48 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_increment() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        let props = HashMap::from([("api_calls".to_string(), 3.0), ("seats".to_string(), -1.5)]);
+
+        mp.groups
+            .increment("company", 42, props, None)
+            .await
+            .unwrap();
+
+        let modifiers = Modifiers {
+            test: Some(true),
+            geolocate: Some(false),
+            ..Default::default()
+        };
+        mp.groups
+            .increment(
+                "company",
+                42,
+                HashMap::from([("api_calls".to_string(), 1.0)]),
+                Some(modifiers),
+            )
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].path, "/groups");
+        assert_eq!(
+            requests[0].data(),
+            serde_json::json!({
+                "$token": "test_token",
+                "$group_key": "company",
+                "$group_id": 42,
+                "$add": {"api_calls": 3.0, "seats": -1.5}
+            })
+        );
+        assert!(!requests[0].query.contains_key("test"));
+        assert_eq!(requests[1].query["test"], "1");
+        assert_eq!(requests[1].query["ip"], "0");
+    }
+
     #[tokio::test]
     async fn test_unset() {
         let mp = Mixpanel::init("test_token", None);
@@ -288,6 +649,94 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_numeric_group_id() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        let mut props = HashMap::new();
+        props.insert("name".to_string(), "Acme Inc".into());
+
+        let (_, group_id) = mp
+            .groups
+            .set_returning_id("company", 42, props, None)
+            .await
+            .unwrap();
+        assert_eq!(group_id, 42);
+        mp.groups
+            .delete_group("company", 42u64, None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            mp.groups.delete_group("company", true, None).await,
+            Err(Error::InvalidProperties(_))
+        ));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].data()["$group_id"], 42);
+        assert_eq!(requests[1].data()["$group_id"], 42);
+    }
+
+    #[tokio::test]
+    async fn test_set_struct() {
+        #[derive(Serialize)]
+        struct Company {
+            industry: &'static str,
+            employees: u32,
+        }
+
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        let company = Company {
+            industry: "manufacturing",
+            employees: 120,
+        };
+
+        mp.groups
+            .set_struct("company", "Acme Inc", &company, None)
+            .await
+            .unwrap();
+
+        let data = server.requests()[0].data();
+        assert_eq!(data["$group_key"], "company");
+        assert_eq!(data["$group_id"], "Acme Inc");
+        assert_eq!(
+            data["$set"],
+            serde_json::json!({"industry": "manufacturing", "employees": 120})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_default_modifiers() {
+        let server = MockServer::start().await;
+        let config = crate::Config {
+            default_modifiers: Some(Modifiers {
+                ip: Some("10.0.0.1".to_string()),
+                ..Default::default()
+            }),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let props = HashMap::from([("plan".to_string(), serde_json::json!("pro"))]);
+        mp.groups
+            .set("company", "Acme Inc", props.clone(), None)
+            .await
+            .unwrap();
+        let modifiers = Modifiers {
+            ip: Some("1.2.3.4".to_string()),
+            ..Default::default()
+        };
+        mp.groups
+            .set("company", "Acme Inc", props, Some(modifiers))
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].data()["$ip"], "10.0.0.1");
+        assert_eq!(requests[1].data()["$ip"], "1.2.3.4");
+    }
+
     #[tokio::test]
     async fn test_with_modifiers() {
         let mp = Mixpanel::init("test_token", None);
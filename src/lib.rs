@@ -2,40 +2,465 @@
 //
 // Inspired by the Node.js library (https://github.com/mixpanel/mixpanel-node)
 
-use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use base64::engine::general_purpose::{STANDARD as BASE64, URL_SAFE};
+use base64::Engine as _;
 use groups::MixpanelGroups;
 use people::MixpanelPeople;
-use reqwest::{Client, Url};
+use reqwest::{Client, ClientBuilder, Proxy, Url};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::time;
-use error::Error;
 
+pub use audit::{AuditRecord, AuditSink};
+pub use backoff::{BackoffStrategy, ExponentialBackoff};
+pub use buffered::{BufferedClient, EnqueueOutcome, ShutdownReport};
+pub use clock::{Clock, SystemClock};
+pub use engage::EngageOp;
+pub use error::Error;
+pub use property_input::PropertyInput;
+pub use stats::{ClientStats, Ingested, OperationStats, RequestHook, RequestMetrics, SendReport};
+
+pub mod audit;
+pub mod backoff;
+pub mod buffered;
+pub mod clock;
+pub mod engage;
 pub mod error;
 pub mod groups;
 pub mod people;
+pub mod prelude;
+pub mod property_input;
+mod resolver;
+pub mod stats;
 mod utils;
 
+#[cfg(test)]
+mod arbitrary;
+#[cfg(any(test, feature = "test-util"))]
+#[doc(hidden)]
+pub mod mock_server;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Predicate deciding whether an event is sent; events it returns `false` for are dropped
+pub type EventFilter = Arc<dyn Fn(&Event) -> bool + Send + Sync>;
+
+/// Callback mutating every event in place before it's sent
+pub type EventTransform = Arc<dyn Fn(&mut Event) + Send + Sync>;
+
+/// Callback receiving every event the client drops instead of sending, and why
+pub type DropHook = Arc<dyn Fn(&Event, DropReason) + Send + Sync>;
+
+/// Callback receiving every warning the client reports, see `Config::warning_hook`
+pub type WarningHook = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Why an event was dropped before being sent, see `Config::drop_hook`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum DropReason {
+    /// Rejected by `Config::event_filter`
+    Filtered,
+    /// Left out by `Config::sample_rate`
+    Sampled,
+    /// A repeat within `Config::dedup_window_ms`
+    Duplicate,
+    /// Tracked while a [`BufferedClient`]'s queue was full
+    BufferFull,
+}
+
+/// Mixpanel accepts a maximum of 50 events per request
+pub(crate) const MAX_BATCH_SIZE: usize = 50;
+
+/// `/track` drops events whose `time` is more than 5 days old
+const TRACK_MAX_AGE_SECS: u64 = 5 * 24 * 60 * 60;
+
+/// `/import` rejects events timed before 1971-01-01
+const IMPORT_EARLIEST_TIME: u64 = 365 * 24 * 60 * 60;
+
+/// How far in the future an imported event may be timed, allowing for clock skew
+const IMPORT_MAX_FUTURE_SECS: u64 = 60 * 60;
+
+/// Identity events, which carry only their own identity fields plus the token and library
+/// metadata: super properties or a default `distinct_id` could conflict with the ids
+/// being linked
+const IDENTITY_EVENTS: &[&str] = &["$identify", "$create_alias", "$merge"];
+
+/// Event properties set by the client that super properties can't override
+const MANAGED_PROPERTIES: &[&str] = &["token", "mp_lib", "$lib_version", "$insert_id"];
+
+/// Headers set by the client that `Config::custom_headers` can't replace
+const RESERVED_HEADERS: &[&str] = &["authorization", "content-type", "content-encoding"];
+
+/// Characters of an unexpected response body kept in [`Error::UnexpectedContentType`]
+const RESPONSE_SNIPPET_CHARS: usize = 200;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub test: bool,
     pub debug: bool,
     pub verbose: bool,
+    /// Ingestion host with an optional port, e.g. `api.mixpanel.com` or `localhost:8080`.
+    /// Trailing slashes are stripped, and a leading `http://` or `https://` replaces
+    /// `protocol`.
     pub host: String,
     pub protocol: String,
     pub path: String,
+    /// Project API secret, or service account credentials as `username:secret`, which
+    /// also need `project_id` for the query and export APIs
     pub secret: Option<String>,
+    /// Legacy project API key, sent as the `api_key` query parameter when no `secret` is set.
+    /// Setting it prints a deprecation warning, once when the client is created.
+    #[deprecated(note = "Mixpanel no longer issues API keys; use `secret` instead")]
     pub api_key: Option<String>,
     pub geolocate: bool,
     pub max_retries: u32,
     pub retry_base_delay_ms: u64,
     pub retry_max_delay_ms: u64,
+    /// distinct_id used by `track`/`track_batch` when the event properties don't include one
+    pub default_distinct_id: Option<String>,
+    /// Flatten nested object properties into dot-delimited keys (`{"address": {"city": ..}}`
+    /// becomes `{"address.city": ..}`) for events and profile updates. Arrays are left intact.
+    ///
+    /// Flattening is not reversible: a key that already contains a dot is indistinguishable
+    /// from a nested one, and if both `"a.b"` and `{"a": {"b": ..}}` are present only one survives.
+    pub flatten_nested: bool,
+    /// Host serving the query API used by `people.get`, normalized like `host`. Both hosts
+    /// share `protocol`, so their schemes, if given, must match.
+    pub query_host: String,
+    /// Project id sent with query API requests, required when authenticating with a service account
+    pub project_id: Option<String>,
+    /// Called with an audit record after every request Mixpanel confirmed. Not invoked for
+    /// failed requests, so it can back a persisted audit trail of exactly what was sent.
+    #[serde(skip)]
+    pub audit_sink: Option<AuditSink>,
+    /// Omit the `mp_lib`/`$lib_version` library metadata from events, sending only `token`
+    /// alongside the caller's properties
+    pub minimal_payload: bool,
+    /// Maximum distance in days between an event's `time` and now; events outside the window
+    /// are handled according to `clock_skew_policy`. `None` disables the check.
+    pub max_event_age_days: Option<u64>,
+    /// What to do with events outside `max_event_age_days`
+    pub clock_skew_policy: ClockSkewPolicy,
+    /// Longest URL sent as a GET request; larger payloads are sent with POST instead
+    pub max_get_url_length: usize,
+    /// Proxy for all requests, e.g. `http://proxy:8080` or `socks5://proxy:1080`
+    /// (SOCKS requires the `socks` feature)
+    pub proxy: Option<String>,
+    /// Called with every event before it's sent; events it returns `false` for are
+    /// silently dropped and counted in `ClientStats::events_filtered`
+    #[serde(skip)]
+    pub event_filter: Option<EventFilter>,
+    /// Called with every event once super properties and the client's own properties are
+    /// merged in, before `event_filter` runs, e.g. to scrub PII centrally. Keys it removes
+    /// aren't sent; removing `token` makes Mixpanel reject the event.
+    #[serde(skip)]
+    pub event_transform: Option<EventTransform>,
+    /// Called with size metrics before every request is sent
+    #[serde(skip)]
+    pub request_hook: Option<RequestHook>,
+    /// Size in bytes above which a single event or profile update is flagged as oversized,
+    /// defaulting to half of Mixpanel's 1 MB limit. Oversized items are reported through
+    /// `warning_hook`, once per send however often it's retried.
+    pub item_size_warning_bytes: usize,
+    /// Region the project's data is stored in, used to pick the export host
+    /// (`host` still sets where events are sent, `Config::for_residency` sets both)
+    pub residency: Residency,
+    /// Properties added to every event, e.g. `$git_sha` or `$build_id`, to correlate
+    /// events with releases. Super properties and per-call properties override them.
+    pub build_metadata: HashMap<String, String>,
+    /// Reject events with integer properties beyond `2^53`, which Mixpanel can't store
+    /// exactly (e.g. snowflake ids), instead of only warning about them in debug mode
+    pub strict_numbers: bool,
+    /// Time allowed to establish a connection to Mixpanel, separate from how long the
+    /// response may take. Connect timeouts are retried like other connection errors.
+    pub connect_timeout_ms: Option<u64>,
+    /// Most time a single ingestion request may take, from connecting until the response
+    /// body is read, so a hung connection can't block a call indefinitely. Timeouts are
+    /// retried like connection errors. `None` lets requests wait forever. Query and export
+    /// requests, which can take minutes for large date ranges, aren't limited.
+    pub request_timeout_ms: Option<u64>,
+    /// Retry failed requests inside the client, up to `max_retries` times. Disable this when
+    /// retries are handled by an outer layer (e.g. `tower` or `backoff`), so attempts don't
+    /// multiply: every call then makes exactly one attempt and returns the original error,
+    /// such as `ApiRateLimitError` with its retry-after, instead of `MaxRetriesReached`.
+    pub internal_retries_enabled: bool,
+    /// Send POST payloads as a raw JSON body with `Content-Type: application/json`
+    /// instead of the legacy base64-encoded `data=` form field. GET requests are unaffected.
+    pub use_json_body: bool,
+    /// Gzip-compress POST bodies and send them with `Content-Encoding: gzip`, which cuts
+    /// the size of large `track_batch` and `import_batch` requests. Request metrics still
+    /// report the uncompressed size.
+    pub compress: bool,
+    /// Skip `alias` calls repeating a mapping this client (or a clone) already created, so
+    /// retry and reconnect logic doesn't send duplicate `$create_alias` events. Best-effort:
+    /// the mappings are only remembered in memory for the lifetime of the client.
+    pub dedupe_aliases: bool,
+    /// Drop an event identical to one tracked less than this many milliseconds ago, e.g.
+    /// two "Button Clicked" events from a double-click. Events are compared by name and
+    /// properties, ignoring `time` and `$insert_id`. Dropped events are counted in
+    /// `ClientStats::events_deduplicated`.
+    pub dedup_window_ms: Option<u64>,
+    /// Keep profiles fetched with `people.get` in memory for this many milliseconds, so
+    /// repeated lookups of the same profile don't query Engage again. Profile updates sent
+    /// through the client drop the cached copy. At most 1000 profiles are kept, evicting
+    /// the least recently used.
+    pub profile_cache_ttl_ms: Option<u64>,
+    /// Most bytes sent in one batch request, measured as they go on the wire: the
+    /// form-encoded base64 `data` parameter, or the JSON body with `use_json_body`. Batches
+    /// are split once they reach 50 events or this size, whichever comes first, defaulting
+    /// to Mixpanel's 1 MB limit.
+    pub max_batch_bytes: usize,
+    /// Identity merge API of the project, see [`IdMergeMode`]
+    pub identity_merge: IdMergeMode,
+    /// Delay between retries, defaulting to an [`ExponentialBackoff`] from
+    /// `retry_base_delay_ms` to `retry_max_delay_ms`
+    #[serde(skip)]
+    pub backoff: Option<Arc<dyn BackoffStrategy>>,
+    /// Reject event and profile properties holding lists Mixpanel would truncate or drop:
+    /// more than 255 elements, or lists and objects nested more than 3 levels deep.
+    /// Without it they're only reported in debug mode.
+    pub strict_list_properties: bool,
+    /// Build and report ingestion requests (`request_hook`, debug output) without sending
+    /// them, answering as if Mixpanel accepted each one. Useful for running examples and
+    /// tests without a project. They aren't counted in `ClientStats::requests_sent`.
+    /// Query and export requests are still sent.
+    pub dry_run: bool,
+    /// Most time a call may spend on retries, counted from its first attempt. A retry
+    /// whose backoff would end past the budget isn't made, and the last error is returned
+    /// as-is, so latency-sensitive callers get a predictable upper bound.
+    pub retry_budget_total_ms: Option<u64>,
+    /// Encode the `data` query parameter of GET requests with the URL-safe base64 alphabet
+    /// (`-` and `_` instead of `+` and `/`), for gateways that mangle those characters in
+    /// URLs. Mixpanel accepts both; POST bodies always use standard base64.
+    pub url_safe_base64: bool,
+    /// Fraction of events to send, from 0.0 to 1.0. Events are kept or dropped by a stable
+    /// hash of their `distinct_id`, so a user's events are sampled together, across restarts
+    /// and processes; events without one are sampled by content. Dropped events are counted in `ClientStats::events_sampled_out`.
+    pub sample_rate: Option<f64>,
+    /// Called with every event dropped by `event_filter`, `sample_rate`,
+    /// `dedup_window_ms` or a full [`BufferedClient`] queue, e.g. to count attempted events when reconstructing true totals
+    #[serde(skip)]
+    pub drop_hook: Option<DropHook>,
+    /// Called with every warning the client reports, e.g. from a [`GuardPolicy::Warn`]
+    /// guard, whether or not `debug` is set. Warnings are also printed in debug mode.
+    #[serde(skip)]
+    pub warning_hook: Option<WarningHook>,
+    /// How often the task started by `Mixpanel::spawn_keep_alive` calls `warmup`, keeping
+    /// a pooled connection open for sporadic tracking. Idle connections are closed after
+    /// 90 seconds, so longer intervals don't keep one warm.
+    pub keep_alive_interval_ms: Option<u64>,
+    /// Time source for the client's own timestamps (event age checks, audit records),
+    /// defaulting to [`SystemClock`]
+    #[serde(skip)]
+    pub clock: Option<Arc<dyn Clock>>,
+    /// Guard for `$set` updates overwriting system profile fields (`$created`, `$email`,
+    /// `$last_seen`, `$name`). `$unset` of `$distinct_id` is always refused.
+    pub system_fields: GuardPolicy,
+    /// Modifiers applied to every `people` and `groups` update, e.g. a fixed `$ip` or
+    /// `$ignore_time`. Fields set on a call's own modifiers take precedence, one by one.
+    pub default_modifiers: Option<Modifiers>,
+    /// Guard for `identify` and `alias` linking a user to a `$device:` id, which names an
+    /// anonymous device and can't be identified as a user
+    pub device_ids: GuardPolicy,
+    /// Rename event property keys to one casing, e.g. `userId` and `UserID` to `user_id`,
+    /// so the same property isn't split across names in reports. Keys starting with `$`
+    /// or `mp_`, and `token`, `distinct_id` and `time`, are left as is. If two keys
+    /// normalize to the same name only one survives: the one already in that case, else
+    /// the first in sorted order. The others are dropped, with a warning in debug mode.
+    pub property_case: Option<PropertyCase>,
+    /// Extra headers sent with every request, e.g. an API gateway key. They can't replace
+    /// the `Authorization`, `Content-Type` and `Content-Encoding` headers the client sets;
+    /// such entries are skipped, with a warning in debug mode.
+    pub custom_headers: HashMap<String, String>,
+    /// Sent as `$source` on every event, with the ingestion host as `mp_api_endpoint`,
+    /// so reports can tell server-side events from client-side ones, e.g. `"server"`.
+    /// Super properties and per-call properties override both; `minimal_payload` leaves
+    /// them out. Off by default.
+    pub event_source: Option<String>,
+}
+
+/// Data residency region a Mixpanel project is stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Residency {
+    #[default]
+    Us,
+    Eu,
+    India,
+}
+
+impl Residency {
+    /// Host ingesting events and profile updates for this region
+    pub fn ingestion_host(&self) -> &'static str {
+        match self {
+            Residency::Us => "api.mixpanel.com",
+            Residency::Eu => "api-eu.mixpanel.com",
+            Residency::India => "api-in.mixpanel.com",
+        }
+    }
+
+    /// Host serving the query API for this region
+    pub fn query_host(&self) -> &'static str {
+        match self {
+            Residency::Us => "mixpanel.com",
+            Residency::Eu => "eu.mixpanel.com",
+            Residency::India => "in.mixpanel.com",
+        }
+    }
+
+    /// Host serving the raw event export API for this region
+    pub fn export_host(&self) -> &'static str {
+        match self {
+            Residency::Us => "data.mixpanel.com",
+            Residency::Eu => "data-eu.mixpanel.com",
+            Residency::India => "data-in.mixpanel.com",
+        }
+    }
+}
+
+/// Identity merge API a Mixpanel project uses, which decides the events sent by
+/// `Mixpanel::identify`, `alias` and `merge`
+///
+/// A project's mode is shown under Project Settings > Identity Merge; newer projects
+/// default to Simplified. Events shaped for the other mode are accepted but don't merge
+/// any identities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum IdMergeMode {
+    /// Original ID Merge: `$identify` with `$anon_id`, `$create_alias` and `$merge` events
+    #[default]
+    Original,
+    /// Simplified ID Merge: events carrying both `$device_id` and `$user_id` link them
+    Simplified,
+}
+
+/// What a guard does with a call it catches: `Config::system_fields` for a `people.set`
+/// overwriting one of the profile fields Mixpanel maintains itself, such as `$last_seen`,
+/// and `Config::device_ids` for an `identify` or `alias` linking a `$device:` id, see
+/// [`Mixpanel::is_device_id`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum GuardPolicy {
+    /// Send the call as is
+    Allow,
+    /// Send the call, reporting it through `Config::warning_hook`
+    #[default]
+    Warn,
+    /// Refuse the call with `Error::InvalidProperty`
+    Reject,
+}
+
+/// Casing event property keys are normalized to, see `Config::property_case`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PropertyCase {
+    /// `user_id`
+    SnakeCase,
+    /// `userId`
+    CamelCase,
+    /// `UserId`
+    PascalCase,
+}
+
+/// Handling of events whose `time` is outside `Config::max_event_age_days`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ClockSkewPolicy {
+    /// Fail the call with `Error::InvalidEvent`
+    #[default]
+    Reject,
+    /// Move the event's `time` to the nearest edge of the accepted window
+    Clamp,
+}
+
+impl fmt::Debug for Config {
+    #[allow(deprecated)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("test", &self.test)
+            .field("debug", &self.debug)
+            .field("verbose", &self.verbose)
+            .field("host", &self.host)
+            .field("protocol", &self.protocol)
+            .field("path", &self.path)
+            .field("secret", &self.secret)
+            .field("api_key", &self.api_key)
+            .field("geolocate", &self.geolocate)
+            .field("max_retries", &self.max_retries)
+            .field("retry_base_delay_ms", &self.retry_base_delay_ms)
+            .field("retry_max_delay_ms", &self.retry_max_delay_ms)
+            .field("default_distinct_id", &self.default_distinct_id)
+            .field("flatten_nested", &self.flatten_nested)
+            .field("query_host", &self.query_host)
+            .field("project_id", &self.project_id)
+            .field(
+                "audit_sink",
+                &self.audit_sink.as_ref().map(|_| "Fn(&AuditRecord)"),
+            )
+            .field("minimal_payload", &self.minimal_payload)
+            .field("max_event_age_days", &self.max_event_age_days)
+            .field("clock_skew_policy", &self.clock_skew_policy)
+            .field("max_get_url_length", &self.max_get_url_length)
+            .field("proxy", &self.proxy)
+            .field(
+                "event_filter",
+                &self.event_filter.as_ref().map(|_| "Fn(&Event) -> bool"),
+            )
+            .field(
+                "event_transform",
+                &self.event_transform.as_ref().map(|_| "Fn(&mut Event)"),
+            )
+            .field(
+                "request_hook",
+                &self.request_hook.as_ref().map(|_| "Fn(&RequestMetrics)"),
+            )
+            .field("item_size_warning_bytes", &self.item_size_warning_bytes)
+            .field("residency", &self.residency)
+            .field("build_metadata", &self.build_metadata)
+            .field("strict_numbers", &self.strict_numbers)
+            .field("strict_list_properties", &self.strict_list_properties)
+            .field("dry_run", &self.dry_run)
+            .field("retry_budget_total_ms", &self.retry_budget_total_ms)
+            .field("url_safe_base64", &self.url_safe_base64)
+            .field("sample_rate", &self.sample_rate)
+            .field(
+                "drop_hook",
+                &self.drop_hook.as_ref().map(|_| "Fn(&Event, DropReason)"),
+            )
+            .field(
+                "warning_hook",
+                &self.warning_hook.as_ref().map(|_| "Fn(&str)"),
+            )
+            .field("keep_alive_interval_ms", &self.keep_alive_interval_ms)
+            .field("clock", &self.clock.as_ref().map(|_| "dyn Clock"))
+            .field("system_fields", &self.system_fields)
+            .field("default_modifiers", &self.default_modifiers)
+            .field("device_ids", &self.device_ids)
+            .field("property_case", &self.property_case)
+            .field("event_source", &self.event_source)
+            .field(
+                "custom_headers",
+                &self.custom_headers.keys().collect::<Vec<_>>(),
+            )
+            .field("connect_timeout_ms", &self.connect_timeout_ms)
+            .field("request_timeout_ms", &self.request_timeout_ms)
+            .field("internal_retries_enabled", &self.internal_retries_enabled)
+            .field("use_json_body", &self.use_json_body)
+            .field("compress", &self.compress)
+            .field("dedupe_aliases", &self.dedupe_aliases)
+            .field("dedup_window_ms", &self.dedup_window_ms)
+            .field("profile_cache_ttl_ms", &self.profile_cache_ttl_ms)
+            .field("max_batch_bytes", &self.max_batch_bytes)
+            .field("identity_merge", &self.identity_merge)
+            .field(
+                "backoff",
+                &self.backoff.as_ref().map(|_| "dyn BackoffStrategy"),
+            )
+            .finish()
+    }
 }
 
 impl Default for Config {
+    #[allow(deprecated)]
     fn default() -> Self {
         Self {
             test: false,
@@ -50,11 +475,297 @@ impl Default for Config {
             max_retries: 3,
             retry_base_delay_ms: 1000,
             retry_max_delay_ms: 10000,
+            default_distinct_id: None,
+            flatten_nested: false,
+            query_host: "mixpanel.com".to_string(),
+            project_id: None,
+            audit_sink: None,
+            minimal_payload: false,
+            max_event_age_days: None,
+            clock_skew_policy: ClockSkewPolicy::Reject,
+            max_get_url_length: 4096,
+            proxy: None,
+            event_filter: None,
+            event_transform: None,
+            request_hook: None,
+            item_size_warning_bytes: 512 * 1024,
+            residency: Residency::Us,
+            build_metadata: HashMap::new(),
+            strict_numbers: false,
+            connect_timeout_ms: None,
+            request_timeout_ms: Some(30_000),
+            internal_retries_enabled: true,
+            use_json_body: false,
+            compress: false,
+            dedupe_aliases: false,
+            dedup_window_ms: None,
+            profile_cache_ttl_ms: None,
+            max_batch_bytes: 1024 * 1024,
+            identity_merge: IdMergeMode::Original,
+            backoff: None,
+            strict_list_properties: false,
+            dry_run: false,
+            retry_budget_total_ms: None,
+            url_safe_base64: false,
+            sample_rate: None,
+            drop_hook: None,
+            warning_hook: None,
+            keep_alive_interval_ms: None,
+            clock: None,
+            system_fields: GuardPolicy::default(),
+            default_modifiers: None,
+            device_ids: GuardPolicy::default(),
+            property_case: None,
+            custom_headers: HashMap::new(),
+            event_source: None,
+        }
+    }
+}
+
+impl Config {
+    /// A call's modifiers, falling back to `default_modifiers` field by field
+    pub(crate) fn modifiers_for(&self, modifiers: Option<Modifiers>) -> Option<Modifiers> {
+        let Some(defaults) = self.default_modifiers.clone() else {
+            return modifiers;
+        };
+        let Some(modifiers) = modifiers else {
+            return Some(defaults);
+        };
+        Some(Modifiers {
+            ip: modifiers.ip.or(defaults.ip),
+            ignore_time: modifiers.ignore_time.or(defaults.ignore_time),
+            time: modifiers.time.or(defaults.time),
+            ignore_alias: modifiers.ignore_alias.or(defaults.ignore_alias),
+            latitude: modifiers.latitude.or(defaults.latitude),
+            longitude: modifiers.longitude.or(defaults.longitude),
+            geolocate: modifiers.geolocate.or(defaults.geolocate),
+            test: modifiers.test.or(defaults.test),
+        })
+    }
+
+    /// The default config for a project stored in `residency`, with `host` and
+    /// `query_host` pointing at that region
+    pub fn for_residency(residency: Residency) -> Self {
+        Self {
+            host: residency.ingestion_host().to_string(),
+            query_host: residency.query_host().to_string(),
+            residency,
+            ..Self::default()
+        }
+    }
+
+    /// Start building a config from the defaults, see [`ConfigBuilder`]
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Check the config for values that would make requests fail
+    pub fn validate(&self) -> Result<()> {
+        if !matches!(self.protocol.as_str(), "http" | "https") {
+            return Err(Error::InvalidConfig(format!(
+                "protocol '{}' must be either http or https",
+                self.protocol
+            )));
+        }
+        if let (Some(scheme), Some(query_scheme)) = (
+            utils::split_scheme(&self.host).0,
+            utils::split_scheme(&self.query_host).0,
+        ) {
+            if scheme != query_scheme {
+                return Err(Error::InvalidConfig(format!(
+                    "host '{}' and query_host '{}' use different schemes",
+                    self.host, self.query_host
+                )));
+            }
+        }
+        for (field, host) in [("host", &self.host), ("query_host", &self.query_host)] {
+            let normalized = utils::normalize_host(host);
+            let parsed = Url::parse(&format!("{}://{}", self.protocol, normalized));
+            let valid =
+                matches!(parsed, Ok(ref url) if url.host_str().is_some() && url.path() == "/");
+            if normalized.contains('/') || !valid {
+                return Err(Error::InvalidConfig(format!(
+                    "{} '{}' must be a host name with an optional port, e.g. api.mixpanel.com",
+                    field, host
+                )));
+            }
+        }
+        if let Some(ref proxy) = self.proxy {
+            let scheme = proxy.split_once("://").map(|(scheme, _)| scheme);
+            if !matches!(scheme, Some("http" | "https" | "socks5" | "socks5h")) {
+                return Err(Error::InvalidConfig(format!(
+                    "proxy '{}' must be an http://, https:// or socks5:// URL",
+                    proxy
+                )));
+            }
+            Proxy::all(proxy).map_err(|e| {
+                Error::InvalidConfig(format!("proxy '{}' is invalid: {}", proxy, e))
+            })?;
+        }
+        if let Some(rate) = self.sample_rate {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(Error::InvalidConfig(format!(
+                    "sample_rate {} must be between 0.0 and 1.0",
+                    rate
+                )));
+            }
+        }
+        for (name, value) in &self.custom_headers {
+            let valid = reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_ok()
+                && reqwest::header::HeaderValue::from_str(value).is_ok();
+            if !valid {
+                return Err(Error::InvalidConfig(format!(
+                    "custom header '{}' has an invalid name or value",
+                    name
+                )));
+            }
         }
+        Ok(())
+    }
+}
+
+/// Chainable construction of a [`Config`], starting from the defaults
+///
+/// ```
+/// use mixpanel_rs::Config;
+///
+/// let config = Config::builder()
+///     .secret("api_secret")
+///     .max_retries(5)
+///     .build();
+/// assert_eq!(config.host, "api.mixpanel.com");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    pub fn test(mut self, test: bool) -> Self {
+        self.config.test = test;
+        self
+    }
+
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.config.debug = debug;
+        self
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.config.verbose = verbose;
+        self
+    }
+
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.config.host = host.into();
+        self
+    }
+
+    /// Either `http` or `https`
+    pub fn protocol(mut self, protocol: impl Into<String>) -> Self {
+        self.config.protocol = protocol.into();
+        self
+    }
+
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.config.path = path.into();
+        self
+    }
+
+    pub fn secret(mut self, secret: impl Into<String>) -> Self {
+        self.config.secret = Some(secret.into());
+        self
+    }
+
+    pub fn project_id(mut self, project_id: impl Into<String>) -> Self {
+        self.config.project_id = Some(project_id.into());
+        self
+    }
+
+    pub fn query_host(mut self, query_host: impl Into<String>) -> Self {
+        self.config.query_host = query_host.into();
+        self
+    }
+
+    /// Region the project is stored in, also setting `host` and `query_host` to that
+    /// region's; call `host` or `query_host` afterwards to override them
+    pub fn residency(mut self, residency: Residency) -> Self {
+        self.config.residency = residency;
+        self.config.host = residency.ingestion_host().to_string();
+        self.config.query_host = residency.query_host().to_string();
+        self
+    }
+
+    pub fn geolocate(mut self, geolocate: bool) -> Self {
+        self.config.geolocate = geolocate;
+        self
+    }
+
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.config.max_retries = max_retries;
+        self
+    }
+
+    pub fn retry_base_delay_ms(mut self, delay_ms: u64) -> Self {
+        self.config.retry_base_delay_ms = delay_ms;
+        self
+    }
+
+    pub fn retry_max_delay_ms(mut self, delay_ms: u64) -> Self {
+        self.config.retry_max_delay_ms = delay_ms;
+        self
+    }
+
+    pub fn default_distinct_id(mut self, distinct_id: impl Into<String>) -> Self {
+        self.config.default_distinct_id = Some(distinct_id.into());
+        self
+    }
+
+    pub fn proxy(mut self, proxy: impl Into<String>) -> Self {
+        self.config.proxy = Some(proxy.into());
+        self
+    }
+
+    pub fn connect_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.config.connect_timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    pub fn request_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.config.request_timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.config.dry_run = dry_run;
+        self
+    }
+
+    /// Build the config, checking it with [`Config::validate`]
+    pub fn try_build(self) -> Result<Config> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+
+    /// Build the config
+    ///
+    /// Panics if the config is invalid, e.g. `protocol` isn't `http` or `https`;
+    /// use `try_build` to handle that instead.
+    pub fn build(self) -> Config {
+        self.try_build().expect("Invalid Mixpanel config")
     }
 }
 
+/// Modifiers of a `people` or `groups` update
+///
+/// More modifiers may be added, so build them from `Default`:
+///
+/// ```
+/// let mut modifiers = mixpanel_rs::Modifiers::default();
+/// modifiers.ip = Some("1.2.3.4".to_string());
+/// ```
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[non_exhaustive]
 pub struct Modifiers {
     #[serde(rename = "$ip", skip_serializing_if = "Option::is_none")]
     pub ip: Option<String>,
@@ -62,6 +773,10 @@ pub struct Modifiers {
     #[serde(rename = "$ignore_time", skip_serializing_if = "Option::is_none")]
     pub ignore_time: Option<bool>,
 
+    /// Time of the profile update, in seconds since the epoch as `/engage` and
+    /// `/groups` expect. Millisecond timestamps are converted to seconds.
+    ///
+    /// Events are timed by their `time` property instead, also in seconds for `/track`.
     #[serde(rename = "$time", skip_serializing_if = "Option::is_none")]
     pub time: Option<u64>,
 
@@ -73,6 +788,94 @@ pub struct Modifiers {
 
     #[serde(rename = "$longitude", skip_serializing_if = "Option::is_none")]
     pub longitude: Option<f64>,
+
+    /// Override `config.geolocate` for a single profile update
+    ///
+    /// `config.geolocate` decides whether Mixpanel geolocates events and profiles from the
+    /// request IP, which is the sending server's IP unless `ip` is set. `Some(false)` sends
+    /// this update with `ip=0`, so the profile location isn't replaced by the server's.
+    #[serde(skip)]
+    pub geolocate: Option<bool>,
+
+    /// Override `config.test` for a single profile update, adding or leaving out `test=1`
+    #[serde(skip)]
+    pub test: Option<bool>,
+}
+
+/// Per-call options for [`Mixpanel::track_with_options`] and
+/// [`Mixpanel::import_batch_with_options`]
+///
+/// More options may be added, so build them from `Default`:
+///
+/// ```
+/// let mut options = mixpanel_rs::TrackOptions::default();
+/// options.test = Some(true);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TrackOptions {
+    /// Override `config.test` for this call, adding or leaving out `test=1`
+    pub test: Option<bool>,
+    /// Override `config.verbose` for this call, so Mixpanel explains why it rejected
+    /// the payload
+    pub verbose: Option<bool>,
+}
+
+/// Query parameters that can be overridden for a single request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RequestOptions {
+    pub geolocate: bool,
+    pub test: bool,
+    pub verbose: bool,
+}
+
+impl RequestOptions {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            geolocate: config.geolocate,
+            test: config.test,
+            verbose: config.verbose,
+        }
+    }
+
+    /// Apply the `geolocate` and `test` overrides of a profile update's modifiers, and
+    /// `verbose`, to the config's defaults
+    pub fn with_modifiers(
+        config: &Config,
+        modifiers: Option<&Modifiers>,
+        verbose: Option<bool>,
+    ) -> Self {
+        Self {
+            geolocate: modifiers
+                .and_then(|m| m.geolocate)
+                .unwrap_or(config.geolocate),
+            test: modifiers.and_then(|m| m.test).unwrap_or(config.test),
+            verbose: verbose.unwrap_or(config.verbose),
+        }
+    }
+
+    /// Apply the per-call overrides of `options` to the config's defaults
+    fn with_track_options(config: &Config, options: TrackOptions) -> Self {
+        Self {
+            test: options.test.unwrap_or(config.test),
+            verbose: options.verbose.unwrap_or(config.verbose),
+            ..Self::from_config(config)
+        }
+    }
+}
+
+/// What an endpoint answers with on success
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResponseKind {
+    /// Ingestion endpoints (`/track`, `/engage`, `/groups`) answer `1`, or `{"status": 1}`
+    /// when the request was sent with `verbose=1`
+    Ingestion { verbose: bool },
+    /// `/import` answers `{"code": 200, "num_records_imported": N, "status": "OK"}`
+    Import,
+    /// Query endpoints answer with JSON returned to the caller
+    Query,
+    /// The export endpoint answers with one JSON event per line
+    Export,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -81,6 +884,17 @@ pub struct Event {
     pub properties: HashMap<String, serde_json::Value>,
 }
 
+/// How [`Mixpanel::track_or_import`] routed a batch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RouteReport {
+    /// Events sent through `/track`
+    pub tracked: usize,
+    /// Historical events sent through `/import`
+    pub imported: usize,
+    /// Historical events not sent because no secret is configured for `/import`
+    pub skipped: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct Mixpanel {
     pub token: String,
@@ -88,122 +902,985 @@ pub struct Mixpanel {
     pub people: MixpanelPeople,
     pub groups: MixpanelGroups,
     http_client: Client,
+    stats: Arc<stats::StatsCounters>,
+    super_properties: Arc<RwLock<HashMap<String, serde_json::Value>>>,
+    /// `(existing_id, new_alias)` pairs already sent, see `Config::dedupe_aliases`
+    aliases: Arc<RwLock<HashSet<(String, String)>>>,
+    /// When recently tracked events were last seen, by fingerprint, see
+    /// `Config::dedup_window_ms`
+    recent_events: Arc<Mutex<HashMap<u64, Instant>>>,
+    /// Profiles fetched by `people.get`, see `Config::profile_cache_ttl_ms`
+    profile_cache: Arc<Mutex<people::ProfileCache>>,
+}
+
+/// The background task started by [`Mixpanel::spawn_keep_alive`], stopped when this
+/// handle is dropped
+#[derive(Debug)]
+#[must_use = "the keep-alive task stops as soon as its handle is dropped"]
+pub struct KeepAlive(tokio::task::JoinHandle<()>);
+
+impl Drop for KeepAlive {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
 }
 
 impl Mixpanel {
     /// Initialize a new Mixpanel client with the given token and optional config
+    ///
+    /// Panics if `config` is invalid or the HTTP client can't be built, e.g. when no
+    /// TLS backend is usable; prefer [`try_init`](Self::try_init) to handle that.
     pub fn init(token: &str, config: Option<Config>) -> Self {
+        Self::try_init(token, config)
+            .unwrap_or_else(|err| panic!("Failed to create Mixpanel client: {}", err))
+    }
+
+    /// Initialize a new Mixpanel client with the given token and optional config,
+    /// returning `Error::InvalidConfig` for a config that fails [`Config::validate`] and
+    /// `Error::ClientBuild` if the HTTP client can't be built
+    pub fn try_init(token: &str, config: Option<Config>) -> Result<Self> {
         let config = config.unwrap_or_default();
-        let http_client = Client::builder()
-            .build()
-            .expect("Failed to create HTTP client");
+        config.validate()?;
+        let http_client = Self::build_client(Self::client_builder(&config)?)?;
+        Ok(Self::init_with_client(token, Some(config), http_client))
+    }
+
+    /// Rebuild a client from its token and a `Config`, e.g. one deserialized by each
+    /// worker of a pool from a shared descriptor. Same as `init`.
+    ///
+    /// The token isn't part of `Config`, so it's never serialized along with it; pass it
+    /// separately. Callbacks (`event_filter`, `audit_sink`, `clock`, ...) aren't
+    /// serialized either and must be set again on the deserialized config. Fields missing
+    /// from the descriptor, e.g. ones added after it was written, take their defaults.
+    pub fn from_parts(token: String, config: Config) -> Self {
+        Self::init(&token, Some(config))
+    }
+
+    /// Initialize a new Mixpanel client sending requests through `client`, e.g. to share
+    /// its connection pool with the rest of the application or pick its TLS backend
+    ///
+    /// `config.proxy` and `config.connect_timeout_ms` are not applied, set them on the client
+    /// instead. Hosts `client` fails to resolve are retried like other connection errors
+    /// rather than reported as `Error::HostResolution`.
+    pub fn init_with_client(token: &str, config: Option<Config>, client: Client) -> Self {
+        let mut config = config.unwrap_or_default();
+        let (scheme, host) = utils::split_scheme(&config.host);
+        let (query_scheme, query_host) = utils::split_scheme(&config.query_host);
+        if let Some(scheme) = scheme.or(query_scheme) {
+            config.protocol = scheme;
+        }
+        config.host = host;
+        config.query_host = query_host;
 
         let mut instance = Self {
             token: token.to_string(),
             config,
             people: MixpanelPeople::default(),
             groups: MixpanelGroups::default(),
-            http_client,
+            http_client: client,
+            stats: Arc::default(),
+            super_properties: Arc::default(),
+            aliases: Arc::default(),
+            recent_events: Arc::default(),
+            profile_cache: Arc::default(),
         };
 
+        #[allow(deprecated)]
+        if instance.config.api_key.is_some() {
+            // Printed outside debug mode too, so users notice before the field goes away
+            let message = "`api_key` is deprecated, use `secret` instead";
+            instance.warn(message);
+            if !instance.config.debug {
+                eprintln!("Mixpanel: {}", message);
+            }
+        }
+
         instance.people.mixpanel = Some(Box::new(instance.clone()));
         instance.groups.mixpanel = Some(Box::new(instance.clone()));
 
         instance
     }
 
+    fn client_builder(config: &Config) -> Result<ClientBuilder> {
+        let mut builder = Client::builder().dns_resolver(Arc::new(resolver::Resolver));
+        if let Some(ref proxy) = config.proxy {
+            let proxy = Proxy::all(proxy).map_err(|e| {
+                Error::InvalidConfig(format!("proxy '{}' is invalid: {}", proxy, e))
+            })?;
+            builder = builder.proxy(proxy);
+        }
+        if let Some(timeout) = config.connect_timeout_ms {
+            builder = builder.connect_timeout(Duration::from_millis(timeout));
+        }
+        Ok(builder)
+    }
+
+    /// Build the HTTP client, which fails when e.g. the TLS backend can't be initialized
+    fn build_client(builder: ClientBuilder) -> Result<Client> {
+        builder
+            .build()
+            .map_err(|e| Error::ClientBuild(e.to_string()))
+    }
+
+    /// Open a connection to the ingestion host ahead of time, so the next event doesn't
+    /// pay for the TCP and TLS handshakes
+    ///
+    /// Sends a single `HEAD` request; any HTTP answer means the connection is pooled and
+    /// ready. Does nothing with `config.dry_run`.
+    pub async fn warmup(&self) -> Result<()> {
+        if self.config.dry_run {
+            return Ok(());
+        }
+        let url = Url::parse(&format!(
+            "{}://{}{}",
+            self.config.protocol, self.config.host, self.config.path
+        ))?;
+        let started = Instant::now();
+        self.with_headers(self.with_auth(self.http_client.head(url)))
+            .send()
+            .await
+            .map_err(Self::error_from_send)?;
+        if self.config.debug {
+            println!("Mixpanel: connection warmed up in {:?}", started.elapsed());
+        }
+        Ok(())
+    }
+
+    /// Call `warmup` every `config.keep_alive_interval_ms` in a background task, until
+    /// the returned handle is dropped. Returns `None` when no interval is configured.
+    ///
+    /// Must be called from within a Tokio runtime. Failed pings are only reported in
+    /// debug output.
+    pub fn spawn_keep_alive(&self) -> Option<KeepAlive> {
+        let interval = Duration::from_millis(self.config.keep_alive_interval_ms?);
+        let mixpanel = self.clone();
+        Some(KeepAlive(tokio::spawn(async move {
+            let mut ticks = time::interval(interval);
+            loop {
+                ticks.tick().await;
+                if let Err(err) = mixpanel.warmup().await {
+                    if mixpanel.config.debug {
+                        println!("Mixpanel: keep-alive ping failed: {}", err);
+                    }
+                }
+            }
+        })))
+    }
+
     /// Track an event with optional properties
+    ///
+    /// `Ok` means Mixpanel received the event. Unless `config.verbose` is set it isn't
+    /// validated yet; [`track_with_report`](Self::track_with_report) tells which.
     pub async fn track<S: Into<String>>(
         &self,
         event: S,
         properties: Option<HashMap<String, serde_json::Value>>,
     ) -> Result<()> {
+        self.track_as(&self.token, event, properties).await
+    }
+
+    /// Track an event and `$set` properties on the profile of the user who sent it
+    ///
+    /// The profile is the event's `distinct_id`, or `config.default_distinct_id`. Mixpanel
+    /// ingests events and profile updates through separate endpoints, so this sends two
+    /// requests, concurrently. Other profile operations (`$add`, `$union`, ...) go through
+    /// [`people`](Self::people) as usual.
+    pub async fn track_and_set<S: Into<String>>(
+        &self,
+        event: S,
+        properties: Option<HashMap<String, serde_json::Value>>,
+        profile_properties: HashMap<String, serde_json::Value>,
+    ) -> Result<()> {
+        let distinct_id = properties
+            .as_ref()
+            .and_then(|props| props.get("distinct_id"))
+            .and_then(|id| match id {
+                serde_json::Value::String(id) => Some(id.clone()),
+                // Mixpanel stores numeric ids as their decimal string
+                serde_json::Value::Number(id) => Some(id.to_string()),
+                _ => None,
+            })
+            .or_else(|| self.config.default_distinct_id.clone())
+            .ok_or_else(|| {
+                Error::InvalidProperties(
+                    "track_and_set needs a distinct_id to update the profile".to_string(),
+                )
+            })?;
+
+        let (tracked, set) = tokio::join!(
+            self.track(event, properties),
+            self.people.set(distinct_id, profile_properties, None)
+        );
+        tracked.and(set)
+    }
+
+    /// Track an event with per-call options, e.g. to mark only this event as test traffic
+    pub async fn track_with_options<S: Into<String>>(
+        &self,
+        event: S,
+        properties: Option<HashMap<String, serde_json::Value>>,
+        options: TrackOptions,
+    ) -> Result<()> {
+        self.track_event(&self.token, event, properties, options)
+            .await
+            .map(|_| ())
+    }
+
+    /// Track an event, reporting how many bytes were sent, the response status and
+    /// whether Mixpanel validated the event (see [`Ingested`])
+    ///
+    /// Returns `None` when the event was dropped by `config.event_filter`,
+    /// `config.sample_rate` or `config.dedup_window_ms` instead of being sent.
+    pub async fn track_with_report<S: Into<String>>(
+        &self,
+        event: S,
+        properties: Option<HashMap<String, serde_json::Value>>,
+    ) -> Result<Option<SendReport>> {
+        self.track_event(&self.token, event, properties, TrackOptions::default())
+            .await
+    }
+
+    /// Track an event under another project token, reusing this client's HTTP connection pool
+    pub async fn track_as<S: Into<String>>(
+        &self,
+        token: &str,
+        event: S,
+        properties: Option<HashMap<String, serde_json::Value>>,
+    ) -> Result<()> {
+        self.track_event(token, event, properties, TrackOptions::default())
+            .await
+            .map(|_| ())
+    }
+
+    async fn track_event<S: Into<String>>(
+        &self,
+        token: &str,
+        event: S,
+        properties: Option<HashMap<String, serde_json::Value>>,
+        options: TrackOptions,
+    ) -> Result<Option<SendReport>> {
+        let event = event.into();
         let mut props = properties.unwrap_or_default();
-        props.insert("token".to_string(), self.token.clone().into());
-        props.insert("mp_lib".to_string(), "rust".into());
-        props.insert("$lib_version".to_string(), env!("CARGO_PKG_VERSION").into());
-
-        // Handle time property if it exists
-        if let Some(time_value) = props.get("time") {
-            if let Some(time_num) = time_value.as_u64() {
-                props.insert("time".to_string(), time_num.into());
-            } else if let Some(time_str) = time_value.as_str() {
-                // Try to parse as ISO string - simplified for now
-                if let Ok(time_num) = time_str.parse::<u64>() {
-                    props.insert("time".to_string(), time_num.into());
-                }
-            }
-        }
+        self.add_event_properties(token, &event, &mut props);
+        Self::normalize_time(&mut props);
+        self.check_event_time(&event, &mut props)?;
+        self.check_numbers(&event, &props)?;
+        self.check_lists(&event, &props)?;
 
-        let data = Event {
-            event: event.into(),
+        let mut data = Event {
+            event,
             properties: props,
         };
+        self.transform_event(&mut data);
+        if !self.keep_event(&data) || self.is_sampled_out(&data) || self.is_duplicate(&data) {
+            return Ok(None);
+        }
 
         if self.config.debug {
             println!("Sending event to Mixpanel: {:?}", &data);
         }
 
-        self.send_request("GET", "/track", &data).await
+        let request = RequestOptions::with_track_options(&self.config, options);
+        let result = self
+            .send_request_with("GET", "/track", &data, request)
+            .await;
+        if result.is_err() {
+            self.forget_events(std::slice::from_ref(&data));
+        }
+        result.map(Some)
     }
 
     /// Track multiple events in a single request (batch)
     pub async fn track_batch(&self, events: Vec<Event>) -> Result<()> {
+        self.send_batch("/track", events, TrackOptions::default())
+            .await
+    }
+
+    /// Import historical events through `/import`, in batches of 50
+    ///
+    /// Unlike `/track`, which drops events more than 5 days old, `/import` accepts
+    /// events of any age. It requires `config.secret`, and `config.project_id` with
+    /// service account credentials.
+    ///
+    /// Mixpanel rejects individual events it can't import, so the batch is checked
+    /// first: every event needs a `time` after 1971-01-01 and at most an hour in the
+    /// future, or nothing is sent and `Error::InvalidProperty` names the event.
+    pub async fn import_batch(&self, events: Vec<Event>) -> Result<()> {
+        self.import_batch_with_options(events, TrackOptions::default())
+            .await
+    }
+
+    /// Like `import_batch`, with per-call options, e.g. `verbose` for detailed errors on
+    /// imports while other traffic stays terse
+    pub async fn import_batch_with_options(
+        &self,
+        events: Vec<Event>,
+        options: TrackOptions,
+    ) -> Result<()> {
+        #[allow(deprecated)]
+        if self.config.secret.is_none() && self.config.api_key.is_none() {
+            return Err(Error::InvalidConfig(
+                "import_batch requires config.secret to be set".to_string(),
+            ));
+        }
+        let service_account = self
+            .config
+            .secret
+            .as_deref()
+            .is_some_and(|s| s.contains(':'));
+        if service_account && self.config.project_id.is_none() {
+            return Err(Error::MissingProjectId("/import".to_string()));
+        }
+        let now = self.current_time();
+        for event in &events {
+            Self::check_import_time(event, now)?;
+        }
+        self.send_batch("/import", events, options).await
+    }
+
+    /// Check that `/import` will accept the event's `time`. Identity events such as
+    /// `$merge` are timed by Mixpanel and don't need one.
+    fn check_import_time(event: &Event, now: u64) -> Result<()> {
+        if IDENTITY_EVENTS.contains(&event.event.as_str()) {
+            return Ok(());
+        }
+        let time =
+            utils::ensure_timestamp(event.properties.get("time").and_then(utils::parse_time));
+        let reason = match time {
+            None => {
+                "is required by /import, in seconds or milliseconds since the epoch".to_string()
+            }
+            Some(time) if time < IMPORT_EARLIEST_TIME => {
+                "is before 1971-01-01, which /import rejects".to_string()
+            }
+            Some(time) if time > now + IMPORT_MAX_FUTURE_SECS => {
+                format!("is {} seconds in the future", time - now)
+            }
+            Some(_) => return Ok(()),
+        };
+        Err(Error::InvalidProperty {
+            key: "time".to_string(),
+            reason: format!("event '{}' {}", event.event, reason),
+        })
+    }
+
+    /// Send each event through `/track` or `/import` depending on its age
+    ///
+    /// Events with a `time` older than `/track` accepts go to `/import` when `config.secret`
+    /// is set. Without a secret they can't be delivered, and are skipped with a warning
+    /// instead of being silently dropped by `/track`.
+    pub async fn track_or_import(&self, events: Vec<Event>) -> Result<RouteReport> {
+        #[allow(deprecated)]
+        let can_import = self.config.secret.is_some() || self.config.api_key.is_some();
+        let oldest_tracked = self.current_time().saturating_sub(TRACK_MAX_AGE_SECS);
+
+        let (historical, recent): (Vec<Event>, Vec<Event>) =
+            events.into_iter().partition(|event| {
+                let time = event.properties.get("time").and_then(utils::parse_time);
+                utils::ensure_timestamp(time).is_some_and(|time| time < oldest_tracked)
+            });
+
+        let mut report = RouteReport {
+            tracked: recent.len(),
+            ..Default::default()
+        };
+        if can_import {
+            report.imported = historical.len();
+        } else {
+            report.skipped = historical.len();
+            if report.skipped > 0 {
+                self.warn(&format!(
+                    "skipping {} events older than 5 days, set `secret` to send them through /import",
+                    report.skipped
+                ));
+            }
+        }
+
+        if !recent.is_empty() {
+            self.track_batch(recent).await?;
+        }
+        if can_import && !historical.is_empty() {
+            self.import_batch(historical).await?;
+        }
+
+        Ok(report)
+    }
+
+    /// Add the common properties to each event and send them in chunks to `endpoint`
+    async fn send_batch(
+        &self,
+        endpoint: &str,
+        events: Vec<Event>,
+        options: TrackOptions,
+    ) -> Result<()> {
         // Process each event to ensure it has the required properties
         let events: Vec<Event> = events
             .into_iter()
             .map(|event| {
                 let mut props = event.properties;
-                props.insert("token".to_string(), self.token.clone().into());
-                props.insert("mp_lib".to_string(), "rust".into());
-                props.insert("$lib_version".to_string(), env!("CARGO_PKG_VERSION").into());
+                self.add_event_properties(&self.token, &event.event, &mut props);
+                Self::normalize_time(&mut props);
+                self.check_event_time(&event.event, &mut props)?;
+                self.check_numbers(&event.event, &props)?;
+                self.check_lists(&event.event, &props)?;
 
-                Event {
+                let mut event = Event {
                     event: event.event,
                     properties: props,
-                }
+                };
+                self.transform_event(&mut event);
+                Ok(event)
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .filter(|event| {
+                self.keep_event(event) && !self.is_sampled_out(event) && !self.is_duplicate(event)
             })
             .collect();
+        if events.is_empty() {
+            return Ok(());
+        }
 
         if self.config.debug {
-            println!("Sending batch of {} events to Mixpanel", events.len());
+            println!(
+                "Sending batch of {} events to Mixpanel {}",
+                events.len(),
+                endpoint
+            );
         }
 
-        // Mixpanel accepts a maximum of 50 events per request
-        const MAX_BATCH_SIZE: usize = 50;
-
-        for chunk in events.chunks(MAX_BATCH_SIZE) {
-            self.send_request("POST", "/track", chunk).await?;
+        let request = RequestOptions::with_track_options(&self.config, options);
+        let mut sent = 0;
+        let chunks = if self.config.use_json_body {
+            utils::chunk_by_size(
+                &events,
+                MAX_BATCH_SIZE,
+                self.config.max_batch_bytes,
+                str::len,
+            )
+        } else {
+            utils::chunk_by_size(
+                &events,
+                MAX_BATCH_SIZE,
+                self.config.max_batch_bytes,
+                utils::form_body_size,
+            )
+        }
+        .map_err(|source| Error::Serialization {
+            endpoint: endpoint.to_string(),
+            payload: std::any::type_name::<Vec<Event>>().to_string(),
+            source,
+        })?;
+        for chunk in chunks {
+            if let Err(err) = self
+                .send_request_with("POST", endpoint, chunk, request)
+                .await
+            {
+                self.forget_events(&events[sent..]);
+                return Err(err);
+            }
+            sent += chunk.len();
         }
 
         Ok(())
     }
 
-    /// Create an alias for a distinct_id
-    pub async fn alias<S: Into<String>>(&self, distinct_id: S, alias: S) -> Result<()> {
-        let mut properties = HashMap::new();
-        properties.insert("distinct_id".to_string(), distinct_id.into().into());
-        properties.insert("alias".to_string(), alias.into().into());
-
-        self.track("$create_alias", Some(properties)).await
+    fn transform_event(&self, event: &mut Event) {
+        if let Some(ref transform) = self.config.event_transform {
+            transform(event);
+        }
     }
 
-    /// Send a request to the Mixpanel API with automatic retries for certain error types
+    /// Run `config.event_filter`, counting the events it drops
+    fn keep_event(&self, event: &Event) -> bool {
+        match self.config.event_filter {
+            Some(ref filter) if !filter(event) => {
+                self.stats.events_filtered.fetch_add(1, Ordering::Relaxed);
+                if self.config.debug {
+                    println!("Mixpanel: event '{}' dropped by event_filter", event.event);
+                }
+                self.report_drop(event, DropReason::Filtered);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Whether `config.sample_rate` leaves `event` out, counting it if so
+    fn is_sampled_out(&self, event: &Event) -> bool {
+        match self.config.sample_rate {
+            Some(rate) if !utils::in_sample(event, rate) => {
+                self.stats
+                    .events_sampled_out
+                    .fetch_add(1, Ordering::Relaxed);
+                if self.config.debug {
+                    println!("Mixpanel: event '{}' sampled out", event.event);
+                }
+                self.report_drop(event, DropReason::Sampled);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn report_drop(&self, event: &Event, reason: DropReason) {
+        if let Some(ref hook) = self.config.drop_hook {
+            hook(event, reason);
+        }
+    }
+
+    /// Report a warning through `config.warning_hook`, and print it in debug mode
+    pub(crate) fn warn(&self, message: &str) {
+        if let Some(ref hook) = self.config.warning_hook {
+            hook(message);
+        }
+        if self.config.debug {
+            println!("Mixpanel: {}", message);
+        }
+    }
+
+    /// Whether `event` repeats one seen within `config.dedup_window_ms`, counting it if so
+    fn is_duplicate(&self, event: &Event) -> bool {
+        let Some(window) = self.config.dedup_window_ms else {
+            return false;
+        };
+        let window = Duration::from_millis(window);
+        let fingerprint = utils::event_fingerprint(event);
+        let now = Instant::now();
+
+        let mut recent = self.recent_events.lock().unwrap();
+        recent.retain(|_, seen| now.duration_since(*seen) < window);
+        if recent.contains_key(&fingerprint) {
+            drop(recent);
+            self.stats
+                .events_deduplicated
+                .fetch_add(1, Ordering::Relaxed);
+            if self.config.debug {
+                println!("Mixpanel: dropping duplicate event '{}'", event.event);
+            }
+            self.report_drop(event, DropReason::Duplicate);
+            return true;
+        }
+        recent.insert(fingerprint, now);
+        false
+    }
+
+    /// Drop `events` from the dedup window after they failed to send, so a retry isn't
+    /// mistaken for a duplicate
+    fn forget_events(&self, events: &[Event]) {
+        if self.config.dedup_window_ms.is_none() {
+            return;
+        }
+        let mut recent = self.recent_events.lock().unwrap();
+        for event in events {
+            recent.remove(&utils::event_fingerprint(event));
+        }
+    }
+
+    /// Counters for the requests and events handled by this client and its clones
+    pub fn stats(&self) -> ClientStats {
+        self.stats.snapshot()
+    }
+
+    /// Read the counters and zero them, for exporters reporting per-interval deltas
+    ///
+    /// Each counter is swapped atomically, so an increment racing with the call is
+    /// counted in exactly one snapshot, though not necessarily the same one for every field.
+    pub fn snapshot_and_reset_stats(&self) -> ClientStats {
+        self.stats.snapshot_and_reset()
+    }
+
+    /// Accepted requests by endpoint and profile updates by operation, kept apart from
+    /// [`stats`](Self::stats) so `ClientStats` stays `Copy`
+    pub fn operation_stats(&self) -> OperationStats {
+        self.stats.operations()
+    }
+
+    /// Read the per-endpoint and per-operation counts and clear them, like
+    /// [`snapshot_and_reset_stats`](Self::snapshot_and_reset_stats)
+    pub fn snapshot_and_reset_operation_stats(&self) -> OperationStats {
+        self.stats.operations_and_reset()
+    }
+
+    /// Register properties sent with every subsequent event, shared with clones of this client
+    ///
+    /// Event properties are resolved in this order, later entries winning:
+    ///
+    /// 1. `config.default_distinct_id`, as `distinct_id`
+    /// 2. `config.build_metadata`
+    /// 3. super properties
+    /// 4. properties passed to `track`/`track_batch`
+    /// 5. `token`, `mp_lib` and `$lib_version`, which are always set by the client
+    ///
+    /// Super properties named `token`, `mp_lib`, `$lib_version` or `$insert_id` are never
+    /// sent, so they can't override the managed values or deduplicate every event into one.
+    pub fn register(&self, properties: HashMap<String, serde_json::Value>) {
+        self.super_properties.write().unwrap().extend(properties);
+    }
+
+    /// Remove a super property
+    pub fn unregister(&self, name: &str) {
+        self.super_properties.write().unwrap().remove(name);
+    }
+
+    /// Currently registered super properties
+    pub fn super_properties(&self) -> HashMap<String, serde_json::Value> {
+        self.super_properties.read().unwrap().clone()
+    }
+
+    /// Register the app version and environment as super properties, typically at startup
+    ///
+    /// Events then carry `$app_version` and `environment`, alongside the `mp_lib` and
+    /// `$lib_version` properties every event already has.
+    pub fn register_defaults<V: Into<String>, E: Into<String>>(
+        &self,
+        app_version: V,
+        environment: E,
+    ) {
+        let mut properties = HashMap::new();
+        properties.insert("$app_version".to_string(), app_version.into().into());
+        properties.insert("environment".to_string(), environment.into().into());
+        self.register(properties);
+    }
+
+    /// Register the server's runtime context as super properties, typically at startup
+    ///
+    /// Events then carry `$server_hostname` (when it can be determined), `$os`, `$arch`
+    /// and `$process_id`. The `mixpanel-rs` version is already sent as `$lib_version`.
+    pub fn register_runtime_context(&self) {
+        let mut properties = HashMap::new();
+        if let Some(hostname) = utils::hostname() {
+            properties.insert("$server_hostname".to_string(), hostname.into());
+        }
+        properties.insert("$os".to_string(), std::env::consts::OS.into());
+        properties.insert("$arch".to_string(), std::env::consts::ARCH.into());
+        properties.insert("$process_id".to_string(), std::process::id().into());
+        self.register(properties);
+    }
+
+    /// Add the properties every tracked event carries
+    fn add_event_properties(
+        &self,
+        token: &str,
+        event: &str,
+        props: &mut HashMap<String, serde_json::Value>,
+    ) {
+        if IDENTITY_EVENTS.contains(&event) {
+            self.add_library_properties(token, props);
+            return;
+        }
+
+        for (key, value) in self.super_properties.read().unwrap().iter() {
+            if !MANAGED_PROPERTIES.contains(&key.as_str()) {
+                props.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+        for (key, value) in &self.config.build_metadata {
+            props
+                .entry(key.clone())
+                .or_insert_with(|| value.clone().into());
+        }
+        if let Some(ref source) = self.config.event_source {
+            if !self.config.minimal_payload {
+                props
+                    .entry("$source".to_string())
+                    .or_insert_with(|| source.clone().into());
+                props
+                    .entry("mp_api_endpoint".to_string())
+                    .or_insert_with(|| self.config.host.clone().into());
+            }
+        }
+
+        #[cfg(feature = "otel")]
+        utils::add_trace_context(props);
+
+        if self.config.flatten_nested {
+            *props = utils::flatten_properties(std::mem::take(props));
+        }
+        if let Some(case) = self.config.property_case {
+            let (converted, dropped) = utils::convert_keys(std::mem::take(props), case);
+            *props = converted;
+            if !dropped.is_empty() && self.config.debug {
+                println!(
+                    "Mixpanel: '{}' has properties colliding under {:?}, dropping {:?}",
+                    event, case, dropped
+                );
+            }
+        }
+
+        self.add_library_properties(token, props);
+        if let Some(ref distinct_id) = self.config.default_distinct_id {
+            props
+                .entry("distinct_id".to_string())
+                .or_insert_with(|| distinct_id.clone().into());
+        }
+    }
+
+    fn add_library_properties(&self, token: &str, props: &mut HashMap<String, serde_json::Value>) {
+        props.insert("token".to_string(), token.into());
+        if !self.config.minimal_payload {
+            props.insert("mp_lib".to_string(), "rust".into());
+            props.insert("$lib_version".to_string(), env!("CARGO_PKG_VERSION").into());
+        }
+    }
+
+    /// Rewrite a numeric string or ISO-8601 `time` as the number Mixpanel expects
+    fn normalize_time(props: &mut HashMap<String, serde_json::Value>) {
+        if let Some(time) = props.get("time").and_then(utils::parse_time) {
+            props.insert("time".to_string(), time.into());
+        }
+    }
+
+    /// Reject or clamp an event whose `time` is further from now than `max_event_age_days`
+    fn check_event_time(
+        &self,
+        event: &str,
+        props: &mut HashMap<String, serde_json::Value>,
+    ) -> Result<()> {
+        let Some(max_age_days) = self.config.max_event_age_days else {
+            return Ok(());
+        };
+        let Some(time) = utils::ensure_timestamp(props.get("time").and_then(|t| t.as_u64())) else {
+            return Ok(());
+        };
+
+        let now = self.current_time();
+        let tolerance = max_age_days.saturating_mul(24 * 60 * 60);
+        let (earliest, latest) = (now.saturating_sub(tolerance), now.saturating_add(tolerance));
+        if (earliest..=latest).contains(&time) {
+            return Ok(());
+        }
+
+        match self.config.clock_skew_policy {
+            ClockSkewPolicy::Reject => Err(Error::InvalidEvent(format!(
+                "'{}' has time {} more than {} days away from now ({})",
+                event, time, max_age_days, now
+            ))),
+            ClockSkewPolicy::Clamp => {
+                props.insert("time".to_string(), time.clamp(earliest, latest).into());
+                Ok(())
+            }
+        }
+    }
+
+    /// Flag integer properties that would lose precision in Mixpanel, warning in debug
+    /// mode or failing under `config.strict_numbers`
+    fn check_numbers(&self, event: &str, props: &HashMap<String, serde_json::Value>) -> Result<()> {
+        let keys = utils::unsafe_integer_properties(props);
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        if self.config.strict_numbers {
+            return Err(Error::InvalidProperties(format!(
+                "'{}' has integers beyond 2^53 in {:?}, send them as strings",
+                event, keys
+            )));
+        }
+        if self.config.debug {
+            println!(
+                "Mixpanel: '{}' has integers beyond 2^53 in {:?}, which lose precision; send them as strings",
+                event, keys
+            );
+        }
+        Ok(())
+    }
+
+    /// Flag list properties Mixpanel would truncate or drop, warning in debug mode or
+    /// failing under `config.strict_list_properties`. `context` names the event or profile.
+    pub(crate) fn check_lists(
+        &self,
+        context: &str,
+        props: &HashMap<String, serde_json::Value>,
+    ) -> Result<()> {
+        let Some((key, reason)) = utils::invalid_list_property(props) else {
+            return Ok(());
+        };
+
+        if self.config.strict_list_properties {
+            return Err(Error::InvalidProperty { key, reason });
+        }
+        if self.config.debug {
+            println!(
+                "Mixpanel: '{}' property '{}' {}, Mixpanel may truncate or drop it",
+                context, key, reason
+            );
+        }
+        Ok(())
+    }
+
+    /// Whether `distinct_id` is an anonymous device id, e.g. `$device:<machine id>` as
+    /// assigned by the Tauri plugin
+    pub fn is_device_id(distinct_id: &str) -> bool {
+        distinct_id.starts_with("$device:")
+    }
+
+    /// Apply `config.device_ids` to the user id an identity event links to
+    fn check_identified_id(&self, event: &str, user_id: &str) -> Result<()> {
+        if !Self::is_device_id(user_id) || self.config.device_ids == GuardPolicy::Allow {
+            return Ok(());
+        }
+        if self.config.device_ids == GuardPolicy::Reject {
+            return Err(Error::InvalidProperty {
+                key: "distinct_id".to_string(),
+                reason: format!("{} can't identify the device id {}", event, user_id),
+            });
+        }
+        self.warn(&format!(
+            "{} identifies the device id {} as a user",
+            event, user_id
+        ));
+        Ok(())
+    }
+
+    /// Link an anonymous id to the id of the user who just signed in
+    ///
+    /// Sends `$identify` shaped for `config.identity_merge`: with `$anon_id` and
+    /// `$identified_id` under Original ID Merge, or `$device_id` and `$user_id` under
+    /// Simplified ID Merge.
+    pub async fn identify<S: Into<String>>(&self, anon_id: S, user_id: S) -> Result<()> {
+        let anon_id = anon_id.into();
+        let user_id = user_id.into();
+        self.check_identified_id("$identify", &user_id)?;
+        let (anon_key, user_key) = match self.config.identity_merge {
+            IdMergeMode::Original => ("$anon_id", "$identified_id"),
+            IdMergeMode::Simplified => ("$device_id", "$user_id"),
+        };
+
+        let mut properties = HashMap::new();
+        properties.insert("distinct_id".to_string(), user_id.clone().into());
+        properties.insert(anon_key.to_string(), anon_id.into());
+        properties.insert(user_key.to_string(), user_id.into());
+        self.track("$identify", Some(properties)).await
+    }
+
+    /// Create an alias so that `new_alias` resolves to the user currently known as `existing_id`
+    ///
+    /// The `$create_alias` event is sent with `distinct_id` set to the existing id and `alias`
+    /// set to the new one, matching Mixpanel's alias direction. Simplified ID Merge has no
+    /// aliases, so the ids are linked with [`identify`](Self::identify) instead.
+    pub async fn alias<S: Into<String>>(&self, existing_id: S, new_alias: S) -> Result<()> {
+        let existing_id = existing_id.into();
+        let new_alias = new_alias.into();
+        if existing_id == new_alias {
+            return Err(Error::InvalidAlias(format!(
+                "alias must differ from the existing distinct_id ({})",
+                existing_id
+            )));
+        }
+        self.check_identified_id("$create_alias", &new_alias)?;
+
+        let mapping = (existing_id, new_alias);
+        if self.config.dedupe_aliases && self.aliases.read().unwrap().contains(&mapping) {
+            if self.config.debug {
+                println!(
+                    "Mixpanel: alias {} -> {} already created, skipping",
+                    mapping.0, mapping.1
+                );
+            }
+            return Ok(());
+        }
+
+        let mut properties = HashMap::new();
+        properties.insert("distinct_id".to_string(), mapping.0.clone().into());
+        properties.insert("alias".to_string(), mapping.1.clone().into());
+
+        match self.config.identity_merge {
+            IdMergeMode::Original => self.track("$create_alias", Some(properties)).await?,
+            IdMergeMode::Simplified => self.identify(mapping.0.clone(), mapping.1.clone()).await?,
+        }
+        if self.config.dedupe_aliases {
+            self.aliases.write().unwrap().insert(mapping);
+        }
+        Ok(())
+    }
+
+    /// Merge two distinct ids into one identity cluster with a `$merge` event
+    ///
+    /// Only Original ID Merge supports `$merge`, which is sent through `/import` and
+    /// requires `config.secret`. Under Simplified ID Merge this returns
+    /// `Error::InvalidConfig`; link ids with [`identify`](Self::identify) instead.
+    pub async fn merge<S: Into<String>>(&self, distinct_id_1: S, distinct_id_2: S) -> Result<()> {
+        if self.config.identity_merge == IdMergeMode::Simplified {
+            return Err(Error::InvalidConfig(
+                "$merge is not supported by Simplified ID Merge, use identify".to_string(),
+            ));
+        }
+
+        let ids = vec![distinct_id_1.into(), distinct_id_2.into()];
+        let mut properties = HashMap::new();
+        properties.insert("$distinct_ids".to_string(), serde_json::json!(ids));
+        self.import_batch(vec![Event {
+            event: "$merge".to_string(),
+            properties,
+        }])
+        .await
+    }
+
+    /// Send a request to the Mixpanel API with automatic retries for certain error types
     pub async fn send_request<T: Serialize + ?Sized>(
         &self,
         method: &str,
         endpoint: &str,
         data: &T,
     ) -> Result<()> {
+        self.send_request_with(
+            method,
+            endpoint,
+            data,
+            RequestOptions::from_config(&self.config),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Like `send_request`, overriding `config.geolocate` and `config.test` for this request
+    pub(crate) async fn send_request_with<T: Serialize + ?Sized>(
+        &self,
+        method: &str,
+        endpoint: &str,
+        data: &T,
+        options: RequestOptions,
+    ) -> Result<SendReport> {
         let mut retries = 0;
         let max_retries = self.config.max_retries;
+        let budget = self.config.retry_budget_total_ms.map(Duration::from_millis);
+        let started = Instant::now();
+        // Profile updates are counted by operation in the stats, and audited payloads are
+        // recorded, so serialize them up front rather than fail after Mixpanel has
+        // accepted them
+        let payload = match endpoint {
+            "/engage" | "/groups" => Some(Self::serialize_payload(endpoint, data)?),
+            _ if self.config.audit_sink.is_some() => Some(Self::serialize_payload(endpoint, data)?),
+            _ => None,
+        };
         
         loop {
-            match self.do_send_request(method, endpoint, data).await {
-                Ok(result) => return Ok(result),
+            match self
+                .do_send_request(method, endpoint, data, options, retries)
+                .await
+            {
+                Ok(result) => {
+                    if let Some(ref sink) = self.config.audit_sink {
+                        if let Some(ref payload) = payload {
+                            sink(&AuditRecord::new(endpoint, payload, self.current_time()));
+                        }
+                    }
+                    // Nothing was sent in a dry run, so it isn't counted
+                    if !self.config.dry_run {
+                        self.stats.record_sent(endpoint, payload.as_ref());
+                    }
+                    return Ok(result);
+                }
                 
                 Err(err) => {
+                    if !self.config.internal_retries_enabled {
+                        self.stats.requests_failed.fetch_add(1, Ordering::Relaxed);
+                        return Err(err);
+                    }
+
                     if retries >= max_retries {
+                        self.stats.requests_failed.fetch_add(1, Ordering::Relaxed);
                         return Err(Error::MaxRetriesReached(format!(
                             "Failed after {} retries. Last error: {}", 
                             retries, err
@@ -218,22 +1895,34 @@ impl Mixpanel {
                     };
                     
                     if !should_retry {
+                        self.stats.requests_failed.fetch_add(1, Ordering::Relaxed);
                         return Err(err);
                     }
                     
-                    let base_delay = self.config.retry_base_delay_ms;
-                    let max_delay = self.config.retry_max_delay_ms;
-                    
                     let wait_time = match &err {
                         Error::ApiRateLimitError(Some(retry_after)) => {
                             Duration::from_secs(*retry_after)
                         },
-                        _ => {
-                            let delay = base_delay * (1 << retries);
-                            let capped_delay = std::cmp::min(delay, max_delay);
-                            Duration::from_millis(capped_delay)
-                        }
+                        _ => match self.config.backoff {
+                            Some(ref backoff) => backoff.next_delay(retries),
+                            None => ExponentialBackoff {
+                                base_delay_ms: self.config.retry_base_delay_ms,
+                                max_delay_ms: self.config.retry_max_delay_ms,
+                            }
+                            .next_delay(retries),
+                        },
                     };
+
+                    if budget.is_some_and(|budget| started.elapsed() + wait_time > budget) {
+                        if self.config.debug {
+                            println!(
+                                "Mixpanel: retry budget exhausted after {} retries, giving up: {}",
+                                retries, err
+                            );
+                        }
+                        self.stats.requests_failed.fetch_add(1, Ordering::Relaxed);
+                        return Err(err);
+                    }
                     
                     if self.config.debug {
                         println!("Retrying request after error: {}. Retry {} of {}. Waiting {:?}", 
@@ -242,65 +1931,125 @@ impl Mixpanel {
                     
                     time::sleep(wait_time).await;
                     retries += 1;
+                    self.stats.retries.fetch_add(1, Ordering::Relaxed);
                 }
             }
         }
     }
 
-    /// Internal method to send a request without retries
+    fn serialize_payload<T: Serialize + ?Sized>(
+        endpoint: &str,
+        data: &T,
+    ) -> Result<serde_json::Value> {
+        serde_json::to_value(data).map_err(|source| Error::Serialization {
+            endpoint: endpoint.to_string(),
+            payload: std::any::type_name::<T>().to_string(),
+            source,
+        })
+    }
+
+    /// Internal method to send a request without retries, `retry` being how many times it
+    /// was sent before
     async fn do_send_request<T: Serialize + ?Sized>(
         &self,
         method: &str,
         endpoint: &str,
         data: &T,
-    ) -> Result<()> {
-        let data_json = serde_json::to_string(data)?;
-        let encoded_data = BASE64.encode(data_json.as_bytes());
+        options: RequestOptions,
+        retry: u32,
+    ) -> Result<SendReport> {
+        // Sort keys so the same properties always encode to the same payload,
+        // regardless of `HashMap` iteration order
+        let payload = utils::sort_keys(Self::serialize_payload(endpoint, data)?);
+        let (data_json, encoded_data) = utils::encode_payload(&payload);
 
         let mut url = Url::parse(&format!(
             "{}://{}{}",
             self.config.protocol, self.config.host, self.config.path
         ))?;
 
-        let endpoint = if endpoint.starts_with('/') {
-            &endpoint[1..]
-        } else {
-            endpoint
-        };
-        url.set_path(&format!("{}{}", url.path(), endpoint));
+        let path = endpoint.strip_prefix('/').unwrap_or(endpoint);
+        url.set_path(&format!("{}{}", url.path(), path));
 
         {
             let mut query_pairs = url.query_pairs_mut();
 
-            if self.config.geolocate {
+            if options.geolocate {
                 query_pairs.append_pair("ip", "1");
             } else {
                 query_pairs.append_pair("ip", "0");
             }
 
-            if self.config.verbose {
+            if options.verbose {
                 query_pairs.append_pair("verbose", "1");
             } else {
                 query_pairs.append_pair("verbose", "0");
             }
 
-            if method.to_uppercase() == "GET" {
-                query_pairs.append_pair("data", &encoded_data);
+            if options.test {
+                query_pairs.append_pair("test", "1");
             }
 
-            if self.config.test {
-                query_pairs.append_pair("test", "1");
+            if let (Some(project_id), "import") = (&self.config.project_id, path) {
+                query_pairs.append_pair("project_id", project_id);
+            }
+
+            #[allow(deprecated)]
+            if let (None, Some(api_key)) = (&self.config.secret, &self.config.api_key) {
+                query_pairs.append_pair("api_key", api_key);
             }
         }
 
-        let mut request_builder = match method.to_uppercase().as_str() {
-            "GET" => self.http_client.get(url),
-            "POST" => {
-                let mut builder = self.http_client.post(url);
-                builder = builder.header("Content-Type", "application/x-www-form-urlencoded");
-                builder = builder.body(format!("data={}", encoded_data));
-                builder
+        let mut method = method.to_uppercase();
+        if method == "GET" {
+            let mut get_url = url.clone();
+            if self.config.url_safe_base64 {
+                let url_safe = URL_SAFE.encode(data_json.as_bytes());
+                get_url.query_pairs_mut().append_pair("data", &url_safe);
+            } else {
+                get_url.query_pairs_mut().append_pair("data", &encoded_data);
+            }
+            // Large payloads would hit proxy URL length limits, send them in the body instead
+            if get_url.as_str().len() > self.config.max_get_url_length {
+                method = "POST".to_string();
+            } else {
+                url = get_url;
+            }
+        }
+
+        let json_body = self.config.use_json_body && method == "POST";
+        let payload_bytes = if json_body {
+            data_json.len()
+        } else {
+            encoded_data.len()
+        };
+        self.report_payload_size(endpoint, &payload, data_json.len(), payload_bytes, retry);
+
+        if self.config.dry_run {
+            if self.config.debug {
+                println!(
+                    "Mixpanel: dry run, not sending {} {}: {}",
+                    method, endpoint, data_json
+                );
             }
+            return Ok(SendReport {
+                bytes_sent: payload_bytes,
+                status: 200,
+                ingested: Ingested::AcceptedUnverified,
+            });
+        }
+
+        let mut request_builder = match method.as_str() {
+            "GET" => self.http_client.get(url),
+            "POST" if json_body => self.post_request(url, "application/json", data_json),
+            // Standard base64 contains `+`, which form decoding would turn into a space
+            "POST" => self.post_request(
+                url,
+                "application/x-www-form-urlencoded",
+                url::form_urlencoded::Serializer::new(String::new())
+                    .append_pair("data", &encoded_data)
+                    .finish(),
+            ),
             _ => {
                 return Err(Error::ApiClientError(
                     0,
@@ -309,103 +2058,2767 @@ impl Mixpanel {
             }
         };
 
-        if let Some(ref secret) = self.config.secret {
-            let auth_header = format!("Basic {}", BASE64.encode(format!("{}:", secret).as_bytes()));
-            request_builder = request_builder.header("Authorization", auth_header);
+        request_builder = self.with_headers(self.with_auth(request_builder));
+        if let Some(timeout) = self.config.request_timeout_ms {
+            request_builder = request_builder.timeout(Duration::from_millis(timeout));
         }
 
-        let response = request_builder.send().await?;
-        let status = response.status();
-        let status_code = status.as_u16();
-
-        if status.is_success() {
-            let body = response.text().await?;
-            if self.config.verbose {
-                match serde_json::from_str::<serde_json::Value>(&body) {
-                    Ok(json) => {
-                        if let Some(api_status) = json.get("status").and_then(|s| s.as_u64()) {
-                            if api_status != 1 {
-                                if let Some(error_msg) = json.get("error").and_then(|e| e.as_str())
-                                {
-                                    return Err(Error::ApiClientError(
-                                        status_code,
-                                        error_msg.to_string(),
-                                    ));
-                                } else {
-                                    return Err(Error::ApiUnexpectedResponse(format!(
-                                        "Response status was not 1: {}",
-                                        body
-                                    )));
-                                }
-                            }
-                            Ok(())
-                        } else {
-                            Err(Error::ApiUnexpectedResponse(format!(
-                                "Response missing status: {}",
-                                body
-                            )))
-                        }
-                    }
-                    Err(e) => Err(Error::JsonError(e)),
-                }
-            } else if body != "1" {
-                Err(Error::ApiUnexpectedResponse(body))
-            } else {
-                Ok(())
-            }
+        let response = request_builder
+            .send()
+            .await
+            .map_err(Self::error_from_send)?;
+        let status = response.status().as_u16();
+        let kind = if endpoint == "/import" {
+            ResponseKind::Import
         } else {
-            match status_code {
-                413 => Err(Error::ApiPayloadTooLarge),
-                429 => {
-                    let retry_after = response
-                        .headers()
-                        .get("Retry-After")
-                        .and_then(|v| v.to_str().ok())
-                        .and_then(|s| s.parse::<u64>().ok());
-                    Err(Error::ApiRateLimitError(retry_after))
-                }
-                s if s >= 500 => Err(Error::ApiServerError(s)),
-                s if s >= 400 => {
-                    let body = response.text().await.unwrap_or_else(|e| e.to_string());
-                    Err(Error::ApiClientError(s, body))
-                }
-                _ => {
-                    let body = response.text().await.unwrap_or_else(|e| e.to_string());
-                    Err(Error::ApiHttpError(status_code, body))
-                }
+            ResponseKind::Ingestion {
+                verbose: options.verbose,
             }
+        };
+        let body = self.read_response(response, kind).await?;
+        let ingested = if body.is_null() {
+            Ingested::AcceptedUnverified
+        } else {
+            // `/import` reports how many records it stored, other endpoints only a status
+            let records = body
+                .get("num_records_imported")
+                .and_then(|n| n.as_u64())
+                .map(|n| n as usize)
+                .unwrap_or(match payload {
+                    serde_json::Value::Array(ref items) => items.len(),
+                    _ => 1,
+                });
+            Ingested::Validated { records }
+        };
+        Ok(SendReport {
+            bytes_sent: payload_bytes,
+            status,
+            ingested,
+        })
+    }
+
+    /// A POST request carrying `body`, gzip-compressed with `config.compress`
+    fn post_request(&self, url: Url, content_type: &str, body: String) -> reqwest::RequestBuilder {
+        let builder = self
+            .http_client
+            .post(url)
+            .header("Content-Type", content_type);
+        if self.config.compress {
+            builder
+                .header("Content-Encoding", "gzip")
+                .body(utils::gzip(body.as_bytes()))
+        } else {
+            builder.body(body)
         }
     }
 
-    pub fn now() -> u64 {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_secs()
+    /// Pass size metrics to `config.request_hook`, warning about oversized items on the
+    /// first attempt only
+    fn report_payload_size(
+        &self,
+        endpoint: &str,
+        payload: &serde_json::Value,
+        json_bytes: usize,
+        payload_bytes: usize,
+        retry: u32,
+    ) {
+        let largest_item_bytes = match payload {
+            serde_json::Value::Array(items) => items
+                .iter()
+                .map(|item| item.to_string().len())
+                .max()
+                .unwrap_or(0),
+            _ => json_bytes,
+        };
+        let oversized = largest_item_bytes > self.config.item_size_warning_bytes;
+
+        if oversized && retry == 0 {
+            self.warn(&format!(
+                "{} payload item is {} bytes, over the {} byte warning threshold",
+                endpoint, largest_item_bytes, self.config.item_size_warning_bytes
+            ));
+        }
+
+        if let Some(ref hook) = self.config.request_hook {
+            hook(&RequestMetrics {
+                endpoint: endpoint.to_string(),
+                payload_bytes,
+                largest_item_bytes,
+                oversized,
+            });
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Send a request to the Mixpanel query API and return the parsed JSON response
+    pub(crate) async fn send_query(
+        &self,
+        endpoint: &str,
+        params: &[(&str, String)],
+    ) -> Result<serde_json::Value> {
+        self.check_query_auth(endpoint)?;
+        let mut url = Url::parse(&format!(
+            "{}://{}",
+            self.config.protocol, self.config.query_host
+        ))?;
+        url.set_path(endpoint);
 
-    #[test]
-    fn test_init() {
-        let mp = Mixpanel::init("test_token", None);
-        assert_eq!(mp.token, "test_token");
-        assert_eq!(mp.config.host, "api.mixpanel.com");
+        if let Some(ref project_id) = self.config.project_id {
+            url.query_pairs_mut().append_pair("project_id", project_id);
+        }
+
+        let request_builder =
+            self.with_headers(self.with_auth(self.http_client.post(url).form(params)));
+
+        if self.config.debug {
+            println!("Sending query to Mixpanel: {} {:?}", endpoint, params);
+        }
+
+        let response = request_builder
+            .send()
+            .await
+            .map_err(Self::error_from_send)?;
+        self.read_response(response, ResponseKind::Query).await
     }
 
-    #[test]
-    fn test_custom_config() {
-        let config = Config {
-            host: "custom.example.com".to_string(),
-            test: true,
-            ..Default::default()
-        };
+    /// Export raw events between two dates (`YYYY-MM-DD`, inclusive), optionally limited
+    /// to some event names
+    ///
+    /// Requires `config.secret` (or a service account secret with `config.project_id`).
+    /// The export host is picked from `config.residency`.
+    pub async fn export(
+        &self,
+        from_date: &str,
+        to_date: &str,
+        events: Option<Vec<String>>,
+    ) -> Result<Vec<Event>> {
+        self.check_query_auth("/api/2.0/export")?;
+        let mut url = Url::parse(&format!(
+            "{}://{}/api/2.0/export",
+            self.config.protocol,
+            self.config.residency.export_host()
+        ))?;
+        {
+            let mut query_pairs = url.query_pairs_mut();
+            query_pairs.append_pair("from_date", from_date);
+            query_pairs.append_pair("to_date", to_date);
+            if let Some(ref events) = events {
+                query_pairs.append_pair("event", &serde_json::to_string(events)?);
+            }
+            if let Some(ref project_id) = self.config.project_id {
+                query_pairs.append_pair("project_id", project_id);
+            }
+        }
 
-        let mp = Mixpanel::init("test_token", Some(config));
-        assert_eq!(mp.config.host, "custom.example.com");
-        assert!(mp.config.test);
+        if self.config.debug {
+            println!("Exporting events from Mixpanel: {}", url);
+        }
+
+        let response = self
+            .with_headers(self.with_auth(self.http_client.get(url)))
+            .send()
+            .await
+            .map_err(Self::error_from_send)?;
+        let rows = self.read_response(response, ResponseKind::Export).await?;
+        Ok(serde_json::from_value(rows)?)
+    }
+
+    /// Fail before sending a query or export request the server would reject for missing
+    /// credentials or a missing `project_id`
+    fn check_query_auth(&self, endpoint: &str) -> Result<()> {
+        match self.config.secret {
+            None => Err(Error::MissingCredentials(endpoint.to_string())),
+            Some(ref secret) if secret.contains(':') && self.config.project_id.is_none() => {
+                Err(Error::MissingProjectId(endpoint.to_string()))
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Authenticate a request with `config.secret`, if set
+    ///
+    /// A project secret is sent as the basic auth username, service account credentials
+    /// (`username:secret`) as they are.
+    fn with_auth(&self, request_builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self.config.secret {
+            Some(ref secret) => {
+                let credentials = if secret.contains(':') {
+                    secret.clone()
+                } else {
+                    format!("{}:", secret)
+                };
+                let auth_header = format!("Basic {}", BASE64.encode(credentials.as_bytes()));
+                request_builder.header("Authorization", auth_header)
+            }
+            None => request_builder,
+        }
+    }
+
+    /// Add `config.custom_headers`, leaving out the ones the client sets itself
+    fn with_headers(
+        &self,
+        mut request_builder: reqwest::RequestBuilder,
+    ) -> reqwest::RequestBuilder {
+        for (name, value) in &self.config.custom_headers {
+            if RESERVED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+                if self.config.debug {
+                    println!(
+                        "Mixpanel: custom header {} is set by the client, skipping it",
+                        name
+                    );
+                }
+                continue;
+            }
+            request_builder = request_builder.header(name, value);
+        }
+        request_builder
+    }
+
+    /// Validate a response against what the endpoint returns on success
+    async fn read_response(
+        &self,
+        response: reqwest::Response,
+        kind: ResponseKind,
+    ) -> Result<serde_json::Value> {
+        let status = response.status();
+        if !status.is_success() {
+            return Err(Self::error_from_response(response).await);
+        }
+
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let body = response.text().await?;
+        match kind {
+            // Proxies in front of Mixpanel may answer 204 or an empty 200 on success
+            ResponseKind::Ingestion { verbose: false } if body == "1" || body.trim().is_empty() => {
+                Ok(serde_json::Value::Null)
+            }
+            ResponseKind::Import if body.trim().is_empty() => Ok(serde_json::Value::Null),
+            ResponseKind::Ingestion { .. } | ResponseKind::Import
+                if utils::is_html_response(&content_type, &body) =>
+            {
+                Err(Error::UnexpectedContentType {
+                    content_type,
+                    snippet: utils::snippet(&body, RESPONSE_SNIPPET_CHARS),
+                })
+            }
+            ResponseKind::Ingestion { verbose: true } => {
+                let json: serde_json::Value = serde_json::from_str(&body)?;
+                match json.get("status").and_then(|s| s.as_u64()) {
+                    Some(1) => Ok(json),
+                    Some(_) => match json.get("error").and_then(|e| e.as_str()) {
+                        Some(error_msg) => Err(Error::ApiClientError(
+                            status.as_u16(),
+                            error_msg.to_string(),
+                        )),
+                        None => Err(Error::ApiUnexpectedResponse(format!(
+                            "Response status was not 1: {}",
+                            body
+                        ))),
+                    },
+                    None => Err(Error::ApiUnexpectedResponse(format!(
+                        "Response missing status: {}",
+                        body
+                    ))),
+                }
+            }
+            ResponseKind::Ingestion { .. } => Err(Error::ApiUnexpectedResponse(body)),
+            ResponseKind::Import => {
+                let json: serde_json::Value = serde_json::from_str(&body).map_err(|_| {
+                    Error::ApiUnexpectedResponse(format!("Response is not JSON: {}", body))
+                })?;
+                let code = json.get("code").and_then(|c| c.as_u64());
+                let status_ok = json.get("status").and_then(|s| s.as_str()) == Some("OK");
+                if code == Some(200) || status_ok {
+                    return Ok(json);
+                }
+                let status_code = code
+                    .and_then(|c| u16::try_from(c).ok())
+                    .unwrap_or(status.as_u16());
+                match json.get("error").and_then(|e| e.as_str()) {
+                    Some(error_msg) => {
+                        Err(Error::ApiClientError(status_code, error_msg.to_string()))
+                    }
+                    None => Err(Error::ApiUnexpectedResponse(format!(
+                        "Response status was not OK: {}",
+                        body
+                    ))),
+                }
+            }
+            ResponseKind::Export => utils::parse_ndjson(&body).map(serde_json::Value::Array),
+            ResponseKind::Query => serde_json::from_str(&body).map_err(|_| {
+                Error::ApiUnexpectedResponse(format!("Response is not JSON: {}", body))
+            }),
+        }
+    }
+
+    /// Map a failed send, singling out hosts that don't resolve so they aren't retried
+    fn error_from_send(err: reqwest::Error) -> Error {
+        let mut source = std::error::Error::source(&err);
+        while let Some(cause) = source {
+            if let Some(resolve_error) = cause.downcast_ref::<resolver::ResolveError>() {
+                if resolve_error.is_transient() {
+                    break;
+                }
+                return Error::HostResolution(resolve_error.to_string());
+            }
+            source = cause.source();
+        }
+        Error::HttpError(err)
+    }
+
+    /// Map a non-success HTTP response to an error
+    async fn error_from_response(response: reqwest::Response) -> Error {
+        let status_code = response.status().as_u16();
+        match status_code {
+            413 => Error::ApiPayloadTooLarge,
+            429 => {
+                let retry_after = response
+                    .headers()
+                    .get("Retry-After")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|s| s.parse::<u64>().ok());
+                Error::ApiRateLimitError(retry_after)
+            }
+            s if s >= 500 => Error::ApiServerError(s),
+            s if s >= 400 => {
+                let body = response.text().await.unwrap_or_else(|e| e.to_string());
+                Error::ApiClientError(s, body)
+            }
+            _ => {
+                let body = response.text().await.unwrap_or_else(|e| e.to_string());
+                Error::ApiHttpError(status_code, body)
+            }
+        }
+    }
+
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs()
+    }
+
+    /// Unix timestamp (seconds) from `config.clock`
+    fn current_time(&self) -> u64 {
+        let now = match self.config.clock {
+            Some(ref clock) => clock.now(),
+            None => SystemClock.now(),
+        };
+        now.duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{CapturedRequest, MockResponse, MockServer};
+    use std::sync::{Arc, Mutex};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    // Fails to compile if a new field (e.g. a callback) makes a public type unusable
+    // across tasks
+    #[test]
+    fn test_public_types_are_send_sync() {
+        assert_send_sync::<Mixpanel>();
+        assert_send_sync::<Config>();
+        assert_send_sync::<Error>();
+        assert_send_sync::<BufferedClient>();
+        assert_send_sync::<people::MixpanelPeople>();
+        assert_send_sync::<groups::MixpanelGroups>();
+        assert_send_sync::<Event>();
+        assert_send_sync::<EngageOp>();
+        assert_send_sync::<AuditSink>();
+        assert_send_sync::<EventFilter>();
+        assert_send_sync::<EventTransform>();
+        assert_send_sync::<RequestHook>();
+        assert_send_sync::<DropHook>();
+        assert_send_sync::<Arc<dyn Clock>>();
+        assert_send_sync::<Arc<dyn BackoffStrategy>>();
+
+        // The futures must be `Send` to be spawned on a multi-threaded runtime
+        let mp = Mixpanel::init("test_token", None);
+        assert_send(&mp.track("Event", None));
+        assert_send(&mp.people.set("user", HashMap::new(), None));
+        assert_send(&mp.groups.set("company", "id", HashMap::new(), None));
+    }
+
+    #[test]
+    fn test_init() {
+        let mp = Mixpanel::init("test_token", None);
+        assert_eq!(mp.token, "test_token");
+        assert_eq!(mp.config.host, "api.mixpanel.com");
+    }
+
+    #[test]
+    fn test_custom_config() {
+        let config = Config {
+            host: "custom.example.com".to_string(),
+            test: true,
+            ..Default::default()
+        };
+
+        let mp = Mixpanel::init("test_token", Some(config));
+        assert_eq!(mp.config.host, "custom.example.com");
+        assert!(mp.config.test);
+    }
+
+    #[tokio::test]
+    async fn test_init_with_client() {
+        let server = MockServer::start().await;
+        let client = Client::builder()
+            .user_agent("shared-client")
+            .build()
+            .unwrap();
+        let mp = Mixpanel::init_with_client("test_token", Some(server.config()), client);
+
+        mp.track("Event", None).await.unwrap();
+        mp.people
+            .set(
+                "user",
+                HashMap::from([("plan".to_string(), "pro".into())]),
+                None,
+            )
+            .await
+            .unwrap();
+        mp.groups
+            .set("company", "acme", HashMap::new(), None)
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        for request in requests {
+            assert_eq!(request.header("user-agent"), Some("shared-client"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_from_parts() {
+        let server = MockServer::start().await;
+        let config = Config {
+            project_id: Some("12345".to_string()),
+            max_retries: 7,
+            ..server.config()
+        };
+        let serialized = serde_json::to_string(&config).unwrap();
+        assert!(!serialized.contains("test_token"));
+
+        let config: Config = serde_json::from_str(&serialized).unwrap();
+        let mp = Mixpanel::from_parts("test_token".to_string(), config);
+        assert_eq!(mp.config.max_retries, 7);
+        assert_eq!(mp.config.project_id.as_deref(), Some("12345"));
+
+        mp.track("Rebuilt", None).await.unwrap();
+        let requests = server.requests();
+        assert_eq!(requests[0].data()["properties"]["token"], "test_token");
+
+        let partial = serde_json::json!({
+            "protocol": "http",
+            "host": server.address().to_string(),
+            "max_retries": 2,
+        });
+        let config: Config = serde_json::from_value(partial).unwrap();
+        let mp = Mixpanel::from_parts("test_token".to_string(), config);
+        assert_eq!(mp.config.max_retries, 2);
+        assert_eq!(mp.config.query_host, Config::default().query_host);
+        mp.track("Partial", None).await.unwrap();
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_default_distinct_id() {
+        let server = MockServer::start().await;
+        let config = Config {
+            default_distinct_id: Some("service".to_string()),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        mp.track("No Id", None).await.unwrap();
+
+        let mut props = HashMap::new();
+        props.insert("distinct_id".to_string(), "caller".into());
+        mp.track("With Id", Some(props)).await.unwrap();
+
+        mp.track_batch(vec![Event {
+            event: "Batched".to_string(),
+            properties: HashMap::new(),
+        }])
+        .await
+        .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].data()["properties"]["distinct_id"], "service");
+        assert_eq!(requests[1].data()["properties"]["distinct_id"], "caller");
+        assert_eq!(
+            requests[2].data()[0]["properties"]["distinct_id"],
+            "service"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_track_as() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+
+        mp.track_as("tenant_token", "Tenant Event", None)
+            .await
+            .unwrap();
+        mp.track("Default Event", None).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].data()["properties"]["token"], "tenant_token");
+        assert_eq!(requests[1].data()["properties"]["token"], "test_token");
+    }
+
+    #[tokio::test]
+    async fn test_per_call_test_mode() {
+        let server = MockServer::start().await;
+        let config = Config {
+            test: true,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let real = TrackOptions {
+            test: Some(false),
+            ..Default::default()
+        };
+        mp.track_with_options("Real Event", None, real)
+            .await
+            .unwrap();
+        mp.track("Test Event", None).await.unwrap();
+        let real_update = Modifiers {
+            test: Some(false),
+            ..Default::default()
+        };
+        let props = HashMap::from([("plan".to_string(), serde_json::json!("pro"))]);
+        mp.people
+            .set("test_user", props, Some(real_update))
+            .await
+            .unwrap();
+
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        let qa = TrackOptions {
+            test: Some(true),
+            ..Default::default()
+        };
+        mp.track_with_options("QA Event", None, qa).await.unwrap();
+
+        let requests = server.requests();
+        assert!(!requests[0].query.contains_key("test"));
+        assert_eq!(requests[1].query["test"], "1");
+        assert!(!requests[2].query.contains_key("test"));
+        assert_eq!(requests[3].query["test"], "1");
+    }
+
+    #[tokio::test]
+    async fn test_track_with_report() {
+        let server = MockServer::start().await;
+        let config = Config {
+            event_filter: Some(Arc::new(|event: &Event| event.event != "Dropped")),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let report = mp.track_with_report("Sent", None).await.unwrap().unwrap();
+        assert_eq!(report.status, 200);
+        assert_eq!(report.bytes_sent, server.requests()[0].query["data"].len());
+        assert_eq!(mp.track_with_report("Dropped", None).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_track_with_report_ingested() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        let report = mp.track_with_report("Sent", None).await.unwrap().unwrap();
+        assert_eq!(report.ingested, Ingested::AcceptedUnverified);
+
+        let server = MockServer::with_responses(vec![MockResponse::new(
+            200,
+            r#"{"status": 1, "error": null}"#,
+        )])
+        .await;
+        let config = Config {
+            verbose: true,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        let report = mp.track_with_report("Sent", None).await.unwrap().unwrap();
+        assert_eq!(report.ingested, Ingested::Validated { records: 1 });
+
+        let server = MockServer::with_responses(vec![MockResponse::imported(2)]).await;
+        let config = Config {
+            secret: Some("secret".to_string()),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        let event = Event {
+            event: "Imported".to_string(),
+            properties: HashMap::from([("time".to_string(), Mixpanel::now().into())]),
+        };
+        let verbose = TrackOptions {
+            verbose: Some(true),
+            ..Default::default()
+        };
+        let report = mp
+            .send_request_with(
+                "POST",
+                "/import",
+                &vec![event.clone(), event],
+                RequestOptions::with_track_options(&mp.config, verbose),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.ingested, Ingested::Validated { records: 2 });
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let server = MockServer::start().await;
+        let metrics = Arc::new(Mutex::new(Vec::new()));
+        let captured = Arc::clone(&metrics);
+        let config = Config {
+            dry_run: true,
+            request_hook: Some(Arc::new(move |m: &RequestMetrics| {
+                captured.lock().unwrap().push(m.endpoint.clone())
+            })),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        mp.track("Event", None).await.unwrap();
+        mp.people.set("user", HashMap::new(), None).await.unwrap();
+
+        assert!(server.requests().is_empty());
+        assert_eq!(*metrics.lock().unwrap(), vec!["/track", "/engage"]);
+        assert_eq!(mp.stats(), ClientStats::default());
+        assert!(mp.operation_stats().requests_by_endpoint.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_track_and_set() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+
+        let event = HashMap::from([("distinct_id".to_string(), serde_json::json!("user"))]);
+        let profile = HashMap::from([("plan".to_string(), serde_json::json!("pro"))]);
+        mp.track_and_set("Upgraded", Some(event), profile.clone())
+            .await
+            .unwrap();
+
+        let mut requests = server.requests();
+        requests.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].path, "/engage");
+        assert_eq!(requests[0].data()["$distinct_id"], "user");
+        assert_eq!(requests[0].data()["$set"]["plan"], "pro");
+        assert_eq!(requests[1].data()["event"], "Upgraded");
+
+        assert!(matches!(
+            mp.track_and_set("Upgraded", None, profile.clone()).await,
+            Err(Error::InvalidProperties(_))
+        ));
+        assert_eq!(server.requests().len(), 2);
+
+        let event = HashMap::from([("distinct_id".to_string(), serde_json::json!(42))]);
+        mp.track_and_set("Upgraded", Some(event), profile)
+            .await
+            .unwrap();
+        let mut requests = server.requests().split_off(2);
+        requests.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(requests[0].data()["$distinct_id"], "42");
+        assert_eq!(requests[1].data()["properties"]["distinct_id"], 42);
+    }
+
+    #[tokio::test]
+    async fn test_flatten_nested() {
+        let server = MockServer::start().await;
+        let config = Config {
+            flatten_nested: true,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let mut props = HashMap::new();
+        props.insert("a".to_string(), serde_json::json!({"b": 1}));
+        mp.track("Nested", Some(props)).await.unwrap();
+
+        let data = server.requests()[0].data();
+        assert_eq!(data["properties"]["a.b"], 1);
+        assert!(data["properties"].get("a").is_none());
+    }
+
+    #[tokio::test]
+    #[allow(deprecated)]
+    async fn test_legacy_api_key() {
+        let server = MockServer::start().await;
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let recorded = warnings.clone();
+        let config = Config {
+            api_key: Some("legacy_key".to_string()),
+            warning_hook: Some(Arc::new(move |message: &str| {
+                recorded.lock().unwrap().push(message.to_string())
+            })),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        mp.track("Legacy", None).await.unwrap();
+        mp.track("Legacy", None).await.unwrap();
+        assert_eq!(
+            *warnings.lock().unwrap(),
+            vec!["`api_key` is deprecated, use `secret` instead"]
+        );
+
+        let config = Config {
+            secret: Some("secret".to_string()),
+            api_key: Some("legacy_key".to_string()),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        mp.track("Secret", None).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].query.get("api_key").unwrap(), "legacy_key");
+        assert!(requests[0].header("authorization").is_none());
+        assert!(!requests[2].query.contains_key("api_key"));
+        assert!(requests[2].header("authorization").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_audit_sink() {
+        let server = MockServer::with_responses(vec![
+            MockResponse::ok(),
+            MockResponse::new(400, "bad request"),
+        ])
+        .await;
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let sink_records = Arc::clone(&records);
+        let config = Config {
+            audit_sink: Some(Arc::new(move |record: &AuditRecord| {
+                sink_records.lock().unwrap().push(record.clone());
+            })),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let mut props = HashMap::new();
+        props.insert("distinct_id".to_string(), "user_1".into());
+        props.insert("email".to_string(), "user@example.com".into());
+        mp.track("Accepted", Some(props)).await.unwrap();
+        assert!(mp.track("Rejected", None).await.is_err());
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].endpoint, "/track");
+        assert_eq!(records[0].distinct_ids, vec!["user_1"]);
+        assert!(records[0].summary.starts_with("Accepted"));
+        assert!(!records[0].summary.contains("user@example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_clock() {
+        struct FixedClock;
+        impl Clock for FixedClock {
+            fn now(&self) -> SystemTime {
+                UNIX_EPOCH + Duration::from_secs(1_600_000_000)
+            }
+        }
+
+        let server = MockServer::start().await;
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let sink_records = Arc::clone(&records);
+        let config = Config {
+            clock: Some(Arc::new(FixedClock)),
+            audit_sink: Some(Arc::new(move |record: &AuditRecord| {
+                sink_records.lock().unwrap().push(record.clone());
+            })),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        mp.track("Event", None).await.unwrap();
+        assert_eq!(records.lock().unwrap()[0].timestamp, 1_600_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_alias_direction() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+
+        mp.alias("existing_id", "new_alias").await.unwrap();
+        assert!(matches!(
+            mp.alias("same_id", "same_id").await,
+            Err(Error::InvalidAlias(_))
+        ));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        let data = requests[0].data();
+        assert_eq!(data["event"], "$create_alias");
+        assert_eq!(data["properties"]["distinct_id"], "existing_id");
+        assert_eq!(data["properties"]["alias"], "new_alias");
+    }
+
+    #[tokio::test]
+    async fn test_identity_merge_original() {
+        let server = MockServer::with_responses(vec![
+            MockResponse::ok(),
+            MockResponse::ok(),
+            MockResponse::imported(1),
+        ])
+        .await;
+        let config = Config {
+            secret: Some("secret".to_string()),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        mp.identify("anon", "user").await.unwrap();
+        mp.alias("anon", "user").await.unwrap();
+        mp.merge("anon", "user").await.unwrap();
+
+        let requests = server.requests();
+        let identify = requests[0].data();
+        assert_eq!(identify["event"], "$identify");
+        assert_eq!(identify["properties"]["distinct_id"], "user");
+        assert_eq!(identify["properties"]["$anon_id"], "anon");
+        assert_eq!(identify["properties"]["$identified_id"], "user");
+        assert!(identify["properties"].get("$device_id").is_none());
+        assert_eq!(requests[1].data()["event"], "$create_alias");
+
+        assert_eq!(requests[2].path, "/import");
+        let merge = &requests[2].data()[0];
+        assert_eq!(merge["event"], "$merge");
+        assert_eq!(
+            merge["properties"]["$distinct_ids"],
+            serde_json::json!(["anon", "user"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_identity_merge_simplified() {
+        let server = MockServer::start().await;
+        let config = Config {
+            secret: Some("secret".to_string()),
+            identity_merge: IdMergeMode::Simplified,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        mp.identify("anon", "user").await.unwrap();
+        mp.alias("anon", "user").await.unwrap();
+        assert!(matches!(
+            mp.merge("anon", "user").await,
+            Err(Error::InvalidConfig(_))
+        ));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        for request in &requests {
+            let data = request.data();
+            assert_eq!(data["event"], "$identify");
+            assert_eq!(data["properties"]["distinct_id"], "user");
+            assert_eq!(data["properties"]["$device_id"], "anon");
+            assert_eq!(data["properties"]["$user_id"], "user");
+            assert!(data["properties"].get("$anon_id").is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_device_id_policy() {
+        assert!(Mixpanel::is_device_id("$device:abc"));
+        assert!(!Mixpanel::is_device_id("user"));
+
+        let server = MockServer::start().await;
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let recorded = warnings.clone();
+        let config = Config {
+            warning_hook: Some(Arc::new(move |message: &str| {
+                recorded.lock().unwrap().push(message.to_string())
+            })),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        mp.identify("anon", "$device:abc").await.unwrap();
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(
+            *warnings.lock().unwrap(),
+            vec!["$identify identifies the device id $device:abc as a user"]
+        );
+
+        let config = Config {
+            device_ids: GuardPolicy::Reject,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        for result in [
+            mp.identify("anon", "$device:abc").await,
+            mp.alias("user", "$device:abc").await,
+        ] {
+            assert!(matches!(
+                result,
+                Err(Error::InvalidProperty { key, .. }) if key == "distinct_id"
+            ));
+        }
+        // The device id is fine as the anonymous side
+        mp.identify("$device:abc", "user").await.unwrap();
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_identity_events_skip_super_properties() {
+        let server = MockServer::start().await;
+        let config = Config {
+            default_distinct_id: Some("service".to_string()),
+            build_metadata: HashMap::from([("$git_sha".to_string(), "abc123".to_string())]),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        mp.register(HashMap::from([
+            ("plan".to_string(), serde_json::json!("pro")),
+            ("$anon_id".to_string(), serde_json::json!("stale")),
+        ]));
+
+        mp.identify("anon", "user").await.unwrap();
+        mp.track("Event", None).await.unwrap();
+
+        let requests = server.requests();
+        let identify = &requests[0].data()["properties"];
+        assert_eq!(identify["$anon_id"], "anon");
+        assert_eq!(identify["token"], "test_token");
+        assert_eq!(identify["mp_lib"], "rust");
+        assert!(identify.get("plan").is_none());
+        assert!(identify.get("$git_sha").is_none());
+
+        let event = &requests[1].data()["properties"];
+        assert_eq!(event["plan"], "pro");
+        assert_eq!(event["distinct_id"], "service");
+    }
+
+    #[tokio::test]
+    async fn test_dedupe_aliases() {
+        let server = MockServer::start().await;
+        let config = Config {
+            dedupe_aliases: true,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        mp.alias("existing_id", "new_alias").await.unwrap();
+        mp.clone().alias("existing_id", "new_alias").await.unwrap();
+        mp.alias("existing_id", "other_alias").await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].data()["properties"]["alias"], "other_alias");
+    }
+
+    #[tokio::test]
+    async fn test_empty_properties() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+
+        mp.track("No Properties", None).await.unwrap();
+        mp.track("No Properties", Some(HashMap::new()))
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        let (none, empty) = (requests[0].data(), requests[1].data());
+        assert_eq!(none, empty);
+        assert_eq!(none["event"], "No Properties");
+        assert_eq!(none["properties"]["token"], "test_token");
+        assert_eq!(none["properties"]["mp_lib"], "rust");
+        assert_eq!(
+            none["properties"]["$lib_version"],
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_minimal_payload() {
+        let server = MockServer::start().await;
+        let config = Config {
+            minimal_payload: true,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        mp.track("Minimal", None).await.unwrap();
+        mp.track("Minimal", Some(HashMap::new())).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].data(), requests[1].data());
+        let properties = requests[0].data()["properties"].clone();
+        assert_eq!(properties, serde_json::json!({"token": "test_token"}));
+    }
+
+    #[tokio::test]
+    async fn test_max_event_age_reject() {
+        let server = MockServer::start().await;
+        let config = Config {
+            max_event_age_days: Some(30),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        let day = 24 * 60 * 60;
+
+        for time in [Mixpanel::now() - 365 * day, Mixpanel::now() + 365 * day] {
+            let mut props = HashMap::new();
+            props.insert("time".to_string(), time.into());
+            assert!(matches!(
+                mp.track("Skewed", Some(props)).await,
+                Err(Error::InvalidEvent(_))
+            ));
+        }
+
+        let mut props = HashMap::new();
+        props.insert("time".to_string(), (Mixpanel::now() - day).into());
+        mp.track("Recent", Some(props)).await.unwrap();
+
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_max_event_age_unbounded() {
+        let server = MockServer::start().await;
+        let config = Config {
+            max_event_age_days: Some(u64::MAX),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let mut props = HashMap::new();
+        props.insert("time".to_string(), Mixpanel::now().into());
+        mp.track("Recent", Some(props)).await.unwrap();
+
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_max_event_age_clamp() {
+        let server = MockServer::start().await;
+        let config = Config {
+            max_event_age_days: Some(1),
+            clock_skew_policy: ClockSkewPolicy::Clamp,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        let day = 24 * 60 * 60;
+
+        let events = [Mixpanel::now() - 365 * day, Mixpanel::now() + 365 * day]
+            .into_iter()
+            .map(|time| {
+                let mut properties = HashMap::new();
+                properties.insert("time".to_string(), time.into());
+                Event {
+                    event: "Skewed".to_string(),
+                    properties,
+                }
+            })
+            .collect();
+        mp.track_batch(events).await.unwrap();
+
+        let data = server.requests()[0].data();
+        let past = data[0]["properties"]["time"].as_u64().unwrap();
+        let future = data[1]["properties"]["time"].as_u64().unwrap();
+        assert!(Mixpanel::now() - past <= day + 1);
+        assert!(future - Mixpanel::now() <= day);
+    }
+
+    #[tokio::test]
+    async fn test_batch_byte_limit() {
+        let server = MockServer::start().await;
+        let config = Config {
+            max_batch_bytes: 4096,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let event = |padding: usize| Event {
+            event: "Sized".to_string(),
+            properties: HashMap::from([(
+                "padding".to_string(),
+                serde_json::json!("x".repeat(padding)),
+            )]),
+        };
+        let mut events = vec![event(1000), event(1000), event(1000), event(5000)];
+        events.extend((0..10).map(|_| event(10)));
+        mp.track_batch(events).await.unwrap();
+
+        let chunks: Vec<usize> = server
+            .requests()
+            .iter()
+            .map(|request| request.data().as_array().unwrap().len())
+            .collect();
+        assert_eq!(chunks, vec![2, 1, 1, 10]);
+    }
+
+    #[tokio::test]
+    async fn test_large_event_uses_post() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+
+        mp.track("Small", None).await.unwrap();
+
+        let mut props = HashMap::new();
+        props.insert("blob".to_string(), "x".repeat(8 * 1024).into());
+        mp.track("Large", Some(props)).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].data()["event"], "Small");
+        assert_eq!(requests[1].method, "POST");
+        assert!(!requests[1].query.contains_key("data"));
+        assert_eq!(requests[1].data()["event"], "Large");
+    }
+
+    #[tokio::test]
+    async fn test_proxy() {
+        let proxy = MockServer::start().await;
+        let config = Config {
+            protocol: "http".to_string(),
+            host: "api.mixpanel.invalid".to_string(),
+            proxy: Some(format!("http://{}", proxy.address())),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let mp = Mixpanel::init("test_token", Some(config));
+        mp.track("Proxied", None).await.unwrap();
+
+        let requests = proxy.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].header("host"), Some("api.mixpanel.invalid"));
+        assert_eq!(requests[0].path, "/track");
+    }
+
+    #[test]
+    fn test_connect_timeout() {
+        let config = Config {
+            connect_timeout_ms: Some(50),
+            ..Default::default()
+        };
+        let builder = format!("{:?}", Mixpanel::client_builder(&config).unwrap());
+        assert!(builder.contains("connect_timeout: 50ms"));
+
+        let builder = format!(
+            "{:?}",
+            Mixpanel::client_builder(&Config::default()).unwrap()
+        );
+        assert!(!builder.contains("connect_timeout"));
+    }
+
+    #[test]
+    fn test_config_builder() {
+        let config = Config::builder()
+            .test(true)
+            .host("eu.example.com")
+            .secret("api_secret")
+            .max_retries(5)
+            .build();
+        assert!(config.test);
+        assert_eq!(config.host, "eu.example.com");
+        assert_eq!(config.secret.as_deref(), Some("api_secret"));
+        assert_eq!(config.max_retries, 5);
+
+        let defaults = Config::default();
+        assert_eq!(config.protocol, defaults.protocol);
+        assert_eq!(config.query_host, defaults.query_host);
+        assert_eq!(config.retry_base_delay_ms, defaults.retry_base_delay_ms);
+        assert_eq!(config.max_batch_bytes, defaults.max_batch_bytes);
+        assert!(!config.debug);
+        assert!(config.internal_retries_enabled);
+    }
+
+    #[test]
+    fn test_config_for_residency() {
+        for (residency, host, query_host) in [
+            (Residency::Us, "api.mixpanel.com", "mixpanel.com"),
+            (Residency::Eu, "api-eu.mixpanel.com", "eu.mixpanel.com"),
+            (Residency::India, "api-in.mixpanel.com", "in.mixpanel.com"),
+        ] {
+            let config = Config::for_residency(residency);
+            assert_eq!(config.host, host);
+            assert_eq!(config.query_host, query_host);
+            assert_eq!(config.residency, residency);
+            assert_eq!(config.max_retries, Config::default().max_retries);
+
+            let built = Config::builder().residency(residency).build();
+            assert_eq!(built.host, host);
+            assert_eq!(built.query_host, query_host);
+        }
+        assert_eq!(
+            Config::for_residency(Residency::Us).host,
+            Config::default().host
+        );
+    }
+
+    #[test]
+    fn test_config_builder_validation() {
+        for protocol in ["http", "https"] {
+            assert!(Config::builder().protocol(protocol).try_build().is_ok());
+        }
+        for protocol in ["ftp", "HTTPS", ""] {
+            assert!(
+                matches!(
+                    Config::builder().protocol(protocol).try_build(),
+                    Err(Error::InvalidConfig(_))
+                ),
+                "{} should be rejected",
+                protocol
+            );
+        }
+        assert!(matches!(
+            Config::builder().host("api.mixpanel.com/track").try_build(),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        let result = std::panic::catch_unwind(|| Config::builder().protocol("ftp").build());
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_property_case() {
+        let server = MockServer::start().await;
+        let config = Config {
+            property_case: Some(PropertyCase::SnakeCase),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        mp.register(HashMap::from([("appVersion".to_string(), "1.2".into())]));
+
+        let props = HashMap::from([
+            ("distinct_id".to_string(), "user".into()),
+            ("UserID".to_string(), 42.into()),
+            ("planName".to_string(), "pro".into()),
+            ("$browserVersion".to_string(), "1".into()),
+            ("mp_processingTime".to_string(), 5.into()),
+        ]);
+        mp.track("Signed Up", Some(props)).await.unwrap();
+
+        let data = server.requests()[0].data();
+        let properties = data["properties"].as_object().unwrap();
+        assert_eq!(properties["user_id"], 42);
+        assert_eq!(properties["plan_name"], "pro");
+        assert_eq!(properties["app_version"], "1.2");
+        assert_eq!(properties["distinct_id"], "user");
+        assert_eq!(properties["$browserVersion"], "1");
+        assert_eq!(properties["mp_processingTime"], 5);
+        assert_eq!(properties["token"], "test_token");
+        assert!(!properties.contains_key("UserID"));
+    }
+
+    #[tokio::test]
+    async fn test_post_body_form_encoded() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        // "~~~" encodes to "fn5+", so the base64 payload has a `+` whatever its alignment
+        let props = HashMap::from([("note".to_string(), "~~~~~~~~~".into())]);
+
+        mp.track_batch(vec![Event {
+            event: "Batched".to_string(),
+            properties: props,
+        }])
+        .await
+        .unwrap();
+
+        let request = &server.requests()[0];
+        let body = String::from_utf8(request.body.clone()).unwrap();
+        assert!(body.contains("%2B"));
+        assert!(!body.contains('+'));
+        assert_eq!(request.data()[0]["properties"]["note"], "~~~~~~~~~");
+    }
+
+    #[tokio::test]
+    async fn test_compress() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let server = MockServer::with_responses(vec![
+            MockResponse::ok(),
+            MockResponse::new(413, "Payload too large"),
+        ])
+        .await;
+        let config = Config {
+            compress: true,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        let events: Vec<Event> = (0..50)
+            .map(|i| Event {
+                event: "Batched".to_string(),
+                properties: HashMap::from([("index".to_string(), i.into())]),
+            })
+            .collect();
+
+        mp.track_batch(events.clone()).await.unwrap();
+        let request = &server.requests()[0];
+        assert_eq!(request.header("content-encoding"), Some("gzip"));
+
+        let mut body = Vec::new();
+        GzDecoder::new(request.body.as_slice())
+            .read_to_end(&mut body)
+            .unwrap();
+        let decompressed = CapturedRequest {
+            body,
+            ..request.clone()
+        };
+        let data = decompressed.data();
+        assert_eq!(data.as_array().unwrap().len(), 50);
+        assert_eq!(data[49]["properties"]["index"], 49);
+        assert!(request.body.len() < decompressed.body.len());
+
+        assert!(matches!(
+            mp.track_batch(events).await,
+            Err(Error::ApiPayloadTooLarge)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_custom_headers() {
+        let server = MockServer::start().await;
+        let config = Config {
+            secret: Some("secret".to_string()),
+            custom_headers: HashMap::from([
+                ("X-Api-Gateway-Key".to_string(), "gateway".to_string()),
+                ("authorization".to_string(), "Bearer other".to_string()),
+            ]),
+            ..server.config()
+        };
+        assert!(config.validate().is_ok());
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        mp.track("Event", None).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("x-api-gateway-key"), Some("gateway"));
+        assert_eq!(
+            requests[0].header("authorization"),
+            Some("Basic c2VjcmV0Og==")
+        );
+
+        let invalid = Config {
+            custom_headers: HashMap::from([("X-Key".to_string(), "line\nbreak".to_string())]),
+            ..Default::default()
+        };
+        assert!(matches!(invalid.validate(), Err(Error::InvalidConfig(_))));
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout() {
+        let slow = MockResponse::ok().delay(Duration::from_secs(5));
+        let server = MockServer::with_responses(vec![slow]).await;
+        let config = Config {
+            request_timeout_ms: Some(50),
+            internal_retries_enabled: false,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let started = Instant::now();
+        let err = mp.track("Slow", None).await.unwrap_err();
+        assert!(
+            matches!(err, Error::HttpError(ref e) if e.is_timeout()),
+            "{:?}",
+            err
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_skips_export() {
+        let rows = r#"{"event": "Signed Up", "properties": {"distinct_id": "a", "time": 1}}"#;
+        let slow = MockResponse::new(200, rows).delay(Duration::from_millis(300));
+        let proxy = MockServer::with_responses(vec![slow]).await;
+        let config = Config {
+            protocol: "http".to_string(),
+            proxy: Some(format!("http://{}", proxy.address())),
+            secret: Some("secret".to_string()),
+            request_timeout_ms: Some(50),
+            ..Default::default()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let events = mp.export("2024-01-01", "2024-01-31", None).await.unwrap();
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn test_try_init() {
+        assert!(Mixpanel::try_init("test_token", None).is_ok());
+
+        let config = Config {
+            proxy: Some("http://".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            Mixpanel::try_init("test_token", Some(config)),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        let config = Config {
+            host: "api.mixpanel.com/track".to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            Mixpanel::try_init("test_token", Some(config)),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        // native-tls, the default TLS backend, can't require TLS 1.3
+        let builder = Client::builder().min_tls_version(reqwest::tls::Version::TLS_1_3);
+        assert!(matches!(
+            Mixpanel::build_client(builder),
+            Err(Error::ClientBuild(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate_proxy() {
+        for proxy in ["proxy.local:8080", "ftp://proxy.local", "http://"] {
+            let config = Config {
+                proxy: Some(proxy.to_string()),
+                ..Default::default()
+            };
+            assert!(
+                matches!(config.validate(), Err(Error::InvalidConfig(_))),
+                "{} should be rejected",
+                proxy
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_sample_rate() {
+        for rate in [0.0, 0.5, 1.0] {
+            let config = Config {
+                sample_rate: Some(rate),
+                ..Default::default()
+            };
+            assert!(config.validate().is_ok(), "{} should be accepted", rate);
+        }
+        for rate in [-0.1, 1.5, f64::NAN] {
+            let config = Config {
+                sample_rate: Some(rate),
+                ..Default::default()
+            };
+            assert!(
+                matches!(config.validate(), Err(Error::InvalidConfig(_))),
+                "{} should be rejected",
+                rate
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_host() {
+        for host in [
+            "api.mixpanel.com",
+            "https://api.mixpanel.com/",
+            "http://localhost:8080",
+            "localhost:8080/",
+        ] {
+            let config = Config {
+                host: host.to_string(),
+                ..Default::default()
+            };
+            assert!(config.validate().is_ok(), "{} should be accepted", host);
+        }
+
+        for host in [
+            "",
+            "https://",
+            "api.mixpanel.com/track",
+            "api.mixpanel.com:port",
+        ] {
+            let config = Config {
+                host: host.to_string(),
+                ..Default::default()
+            };
+            assert!(
+                matches!(config.validate(), Err(Error::InvalidConfig(_))),
+                "{} should be rejected",
+                host
+            );
+        }
+
+        let config = Config {
+            host: "http://localhost:8080".to_string(),
+            query_host: "https://localhost:8081".to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(config.validate(), Err(Error::InvalidConfig(_))));
+    }
+
+    #[tokio::test]
+    async fn test_host_with_scheme() {
+        let server = MockServer::start().await;
+        let config = Config {
+            protocol: "https".to_string(),
+            host: format!("http://{}/", server.address()),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        assert_eq!(mp.config.host, server.address().to_string());
+        assert_eq!(mp.config.protocol, "http");
+
+        mp.track("Event", None).await.unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/track");
+    }
+
+    #[tokio::test]
+    async fn test_response_kinds() {
+        let rows = r#"[{"$distinct_id": "a"}]"#;
+        let server = MockServer::with_responses(vec![MockResponse::new(200, rows)]).await;
+        let mp = Mixpanel::init("test_token", Some(server.query_config()));
+
+        // A JSON body is a valid query answer but not a valid ingestion answer
+        let result = mp.send_query("/api/query/engage", &[]).await.unwrap();
+        assert_eq!(result, serde_json::json!([{"$distinct_id": "a"}]));
+        assert!(matches!(
+            mp.track("Event", None).await,
+            Err(Error::ApiUnexpectedResponse(_))
+        ));
+
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        mp.track("Event", None).await.unwrap();
+
+        // Query answers must be JSON
+        let server = MockServer::with_responses(vec![MockResponse::new(200, "ok")]).await;
+        let mp = Mixpanel::init("test_token", Some(server.query_config()));
+        assert!(matches!(
+            mp.send_query("/api/query/engage", &[]).await,
+            Err(Error::ApiUnexpectedResponse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_html_response() {
+        let page = format!(
+            "<!DOCTYPE html><html><body>{}</body></html>",
+            "x".repeat(500)
+        );
+        let server = MockServer::with_responses(vec![
+            MockResponse::new(200, &page).header("Content-Type", "text/html")
+        ])
+        .await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+
+        match mp.track("Event", None).await {
+            Err(Error::UnexpectedContentType {
+                content_type,
+                snippet,
+            }) => {
+                assert_eq!(content_type, "text/html");
+                assert!(snippet.starts_with("<!DOCTYPE html>"));
+                assert!(snippet.len() < page.len());
+            }
+            other => panic!("expected UnexpectedContentType, got {:?}", other),
+        }
+
+        // Verbose answers are checked before being parsed as JSON
+        let server =
+            MockServer::with_responses(vec![MockResponse::new(200, "<html>Sign in</html>")]).await;
+        let config = Config {
+            verbose: true,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        assert!(matches!(
+            mp.track("Event", None).await,
+            Err(Error::UnexpectedContentType { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_query_prerequisites() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        assert!(matches!(
+            mp.people.get("user").await,
+            Err(Error::MissingCredentials(_))
+        ));
+        assert!(matches!(
+            mp.export("2024-01-01", "2024-01-31", None).await,
+            Err(Error::MissingCredentials(_))
+        ));
+
+        let service_account = Config {
+            secret: Some("analytics.abc123.mp-service-account:s3cret".to_string()),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(service_account.clone()));
+        assert!(matches!(
+            mp.people.get("user").await,
+            Err(Error::MissingProjectId(_))
+        ));
+        assert!(matches!(
+            mp.export("2024-01-01", "2024-01-31", None).await,
+            Err(Error::MissingProjectId(_))
+        ));
+        assert!(server.requests().is_empty());
+
+        let config = Config {
+            project_id: Some("12345".to_string()),
+            ..service_account
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        let _ = mp.people.get("user").await;
+        assert_eq!(server.requests()[0].query["project_id"], "12345");
+    }
+
+    #[cfg(feature = "otel")]
+    #[tokio::test]
+    async fn test_trace_context() {
+        use opentelemetry::trace::{
+            SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+        };
+
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        mp.track("Untraced", None).await.unwrap();
+
+        let span = SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let _guard = opentelemetry::Context::current()
+            .with_remote_span_context(span)
+            .attach();
+        mp.track("Traced", None).await.unwrap();
+
+        let requests = server.requests();
+        let untraced = &requests[0].data()["properties"];
+        assert!(untraced.get("$trace_id").is_none());
+        let traced = &requests[1].data()["properties"];
+        assert_eq!(traced["$trace_id"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(traced["$span_id"], "00f067aa0ba902b7");
+    }
+
+    #[tokio::test]
+    async fn test_serialization_error_context() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+
+        // JSON object keys must be strings
+        let data = HashMap::from([(vec![1u8], 1)]);
+        let err = mp.send_request("POST", "/track", &data).await.unwrap_err();
+
+        assert!(matches!(err, Error::Serialization { .. }));
+        let message = err.to_string();
+        assert!(message.contains("/track"), "{}", message);
+        assert!(message.contains("HashMap"), "{}", message);
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_event_transform() {
+        let server = MockServer::start().await;
+        let transform: EventTransform = Arc::new(|event: &mut Event| {
+            event.properties.remove("email");
+        });
+        let config = Config {
+            event_transform: Some(transform),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let mut props = HashMap::new();
+        props.insert("email".to_string(), "user@example.com".into());
+        props.insert("plan".to_string(), "pro".into());
+        mp.track("Signed Up", Some(props.clone())).await.unwrap();
+        mp.track_batch(vec![Event {
+            event: "Signed Up".to_string(),
+            properties: props,
+        }])
+        .await
+        .unwrap();
+
+        let requests = server.requests();
+        let tracked = &requests[0].data()["properties"];
+        assert!(tracked.get("email").is_none());
+        assert_eq!(tracked["plan"], "pro");
+        assert!(requests[1].data()[0]["properties"].get("email").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_event_filter() {
+        let server = MockServer::start().await;
+        let filter: EventFilter = Arc::new(|event: &Event| {
+            event.properties.get("internal") != Some(&serde_json::Value::Bool(true))
+        });
+        let config = Config {
+            event_filter: Some(filter),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let mut internal = HashMap::new();
+        internal.insert("internal".to_string(), true.into());
+        mp.track("Dropped", Some(internal.clone())).await.unwrap();
+        mp.track_batch(vec![Event {
+            event: "Dropped".to_string(),
+            properties: internal.clone(),
+        }])
+        .await
+        .unwrap();
+        assert!(server.requests().is_empty());
+
+        mp.track_batch(vec![
+            Event {
+                event: "Dropped".to_string(),
+                properties: internal,
+            },
+            Event {
+                event: "Kept".to_string(),
+                properties: HashMap::new(),
+            },
+        ])
+        .await
+        .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].data().as_array().unwrap().len(), 1);
+        assert_eq!(requests[0].data()[0]["event"], "Kept");
+
+        let stats = mp.stats();
+        assert_eq!(stats.events_filtered, 3);
+        assert_eq!(stats.requests_sent, 1);
+    }
+
+    #[tokio::test]
+    async fn test_sample_rate() {
+        let server = MockServer::start().await;
+        let dropped = Arc::new(Mutex::new(Vec::new()));
+        let captured = Arc::clone(&dropped);
+        let config = Config {
+            sample_rate: Some(0.5),
+            drop_hook: Some(Arc::new(move |event: &Event, reason| {
+                captured.lock().unwrap().push((event.event.clone(), reason));
+            })),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let user = |id: usize| {
+            HashMap::from([(
+                "distinct_id".to_string(),
+                serde_json::json!(format!("user_{}", id)),
+            )])
+        };
+        for id in 0..100 {
+            mp.track("Visit", Some(user(id))).await.unwrap();
+            mp.track("Visit Again", Some(user(id))).await.unwrap();
+        }
+
+        let sent = server.requests().len();
+        let sampled_out = mp.stats().events_sampled_out as usize;
+        assert_eq!(sent + sampled_out, 200);
+        assert!(sent > 0 && sampled_out > 0);
+        // Both events of a sampled-out user are dropped together
+        assert_eq!(sampled_out % 2, 0);
+
+        let dropped = dropped.lock().unwrap();
+        assert_eq!(dropped.len(), sampled_out);
+        assert!(dropped
+            .iter()
+            .all(|(_, reason)| *reason == DropReason::Sampled));
+    }
+
+    #[tokio::test]
+    async fn test_dedup_window() {
+        let server = MockServer::start().await;
+        let config = Config {
+            dedup_window_ms: Some(60_000),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let click = |button: &str| {
+            HashMap::from([
+                ("distinct_id".to_string(), serde_json::json!("user")),
+                ("button".to_string(), serde_json::json!(button)),
+            ])
+        };
+        mp.track("Button Clicked", Some(click("buy")))
+            .await
+            .unwrap();
+        mp.track("Button Clicked", Some(click("buy")))
+            .await
+            .unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(mp.stats().events_deduplicated, 1);
+
+        mp.track("Button Clicked", Some(click("cancel")))
+            .await
+            .unwrap();
+        mp.track("Page Viewed", Some(click("buy"))).await.unwrap();
+        assert_eq!(server.requests().len(), 3);
+        assert_eq!(mp.stats().events_deduplicated, 1);
+    }
+
+    #[tokio::test]
+    async fn test_dedup_window_allows_retry_after_failure() {
+        let server =
+            MockServer::with_responses(vec![MockResponse::new(500, ""), MockResponse::ok()]).await;
+        let config = Config {
+            dedup_window_ms: Some(60_000),
+            internal_retries_enabled: false,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let properties = HashMap::from([("distinct_id".to_string(), serde_json::json!("user"))]);
+        assert!(mp
+            .track("Purchase", Some(properties.clone()))
+            .await
+            .is_err());
+        mp.track("Purchase", Some(properties)).await.unwrap();
+        assert_eq!(server.requests().len(), 2);
+        assert_eq!(mp.stats().events_deduplicated, 0);
+    }
+
+    #[tokio::test]
+    async fn test_stats_shared_with_clones() {
+        let server =
+            MockServer::with_responses(vec![MockResponse::new(503, ""), MockResponse::ok()]).await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+
+        mp.track("Event", None).await.unwrap();
+        mp.people.set("user", HashMap::new(), None).await.unwrap();
+
+        let stats = mp.clone().stats();
+        assert_eq!(stats.requests_sent, 2);
+        assert_eq!(stats.retries, 1);
+        assert_eq!(stats.requests_failed, 0);
+    }
+
+    #[tokio::test]
+    async fn test_use_json_body() {
+        let server = MockServer::start().await;
+        let config = Config {
+            use_json_body: true,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        mp.track_batch(vec![Event {
+            event: "Batch".to_string(),
+            properties: HashMap::new(),
+        }])
+        .await
+        .unwrap();
+        mp.track("Single", None).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("content-type"), Some("application/json"));
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body[0]["event"], "Batch");
+        assert_eq!(body[0]["properties"]["token"], "test_token");
+
+        assert_eq!(requests[1].method, "GET");
+        assert_eq!(requests[1].data()["event"], "Single");
+    }
+
+    #[tokio::test]
+    async fn test_url_safe_base64() {
+        let server = MockServer::start().await;
+        let config = Config {
+            url_safe_base64: true,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        // Encodes to `+` and `/` in the standard alphabet
+        let mut props = HashMap::new();
+        props.insert("query".to_string(), serde_json::json!("???>>>~~~"));
+
+        mp.track("Search", Some(props)).await.unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(request.method, "GET");
+        let encoded = &request.query["data"];
+        assert!(!encoded.contains('+') && !encoded.contains('/'));
+        assert!(encoded.contains('-') || encoded.contains('_'));
+        let data: serde_json::Value =
+            serde_json::from_slice(&URL_SAFE.decode(encoded).unwrap()).unwrap();
+        assert_eq!(data["event"], "Search");
+        assert_eq!(data["properties"]["query"], "???>>>~~~");
+    }
+
+    #[tokio::test]
+    async fn test_internal_retries_disabled() {
+        let server = MockServer::with_responses(vec![MockResponse::new(503, "")]).await;
+        let config = Config {
+            internal_retries_enabled: false,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let result = mp.track("Event", None).await;
+        assert!(matches!(result, Err(Error::ApiServerError(_))));
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(mp.stats().retries, 0);
+        assert_eq!(mp.stats().requests_failed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_custom_backoff() {
+        struct ConstantBackoff(Mutex<Vec<u32>>);
+
+        impl BackoffStrategy for ConstantBackoff {
+            fn next_delay(&self, attempt: u32) -> Duration {
+                self.0.lock().unwrap().push(attempt);
+                Duration::from_millis(30)
+            }
+        }
+
+        let server = MockServer::with_responses(vec![
+            MockResponse::new(503, ""),
+            MockResponse::new(503, ""),
+            MockResponse::ok(),
+        ])
+        .await;
+        let backoff = Arc::new(ConstantBackoff(Mutex::new(Vec::new())));
+        let config = Config {
+            backoff: Some(backoff.clone()),
+            // Would add a 10s wait if it were used instead of the strategy
+            retry_base_delay_ms: 10_000,
+            retry_max_delay_ms: 10_000,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let started = Instant::now();
+        mp.track("Event", None).await.unwrap();
+        let elapsed = started.elapsed();
+
+        assert_eq!(*backoff.0.lock().unwrap(), vec![0, 1]);
+        assert!(elapsed >= Duration::from_millis(60));
+        assert!(elapsed < Duration::from_secs(5));
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_warmup() {
+        let server = MockServer::start().await;
+        let config = Config {
+            secret: Some("secret".to_string()),
+            custom_headers: HashMap::from([(
+                "X-Api-Gateway-Key".to_string(),
+                "gateway".to_string(),
+            )]),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        mp.warmup().await.unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "HEAD");
+        assert_eq!(requests[0].header("x-api-gateway-key"), Some("gateway"));
+        assert_eq!(
+            requests[0].header("authorization"),
+            Some("Basic c2VjcmV0Og==")
+        );
+
+        mp.track("After Warmup", None).await.unwrap();
+        assert_eq!(server.requests()[1].data()["event"], "After Warmup");
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        assert!(mp.spawn_keep_alive().is_none());
+
+        let config = Config {
+            keep_alive_interval_ms: Some(20),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        let keep_alive = mp.spawn_keep_alive().unwrap();
+        time::sleep(Duration::from_millis(100)).await;
+        drop(keep_alive);
+        // Let a ping already on the wire land
+        time::sleep(Duration::from_millis(10)).await;
+
+        let pings = server.requests();
+        assert!(pings.len() >= 2, "{} pings", pings.len());
+        assert!(pings.iter().all(|request| request.method == "HEAD"));
+        // Dropping the handle stopped the task
+        time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(server.requests().len(), pings.len());
+    }
+
+    #[tokio::test]
+    async fn test_retry_budget() {
+        let server = MockServer::with_responses(vec![MockResponse::new(503, "")]).await;
+        let config = Config {
+            max_retries: 10,
+            retry_base_delay_ms: 40,
+            retry_max_delay_ms: 40,
+            retry_budget_total_ms: Some(100),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let started = Instant::now();
+        let result = mp.track("Event", None).await;
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert!(matches!(result, Err(Error::ApiServerError(503))));
+
+        let attempts = server.requests().len();
+        assert!((2..=3).contains(&attempts), "{} attempts", attempts);
+        assert_eq!(mp.stats().retries as usize, attempts - 1);
+        assert_eq!(mp.stats().requests_failed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_and_reset_stats() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+
+        mp.track("Event", None).await.unwrap();
+        mp.track("Event", None).await.unwrap();
+
+        assert_eq!(mp.snapshot_and_reset_stats().requests_sent, 2);
+        assert_eq!(mp.snapshot_and_reset_stats(), ClientStats::default());
+        assert_eq!(mp.clone().stats(), ClientStats::default());
+    }
+
+    #[tokio::test]
+    async fn test_operation_stats() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        let props = || HashMap::from([("plan".to_string(), serde_json::json!("pro"))]);
+
+        mp.people.set("user", props(), None).await.unwrap();
+        mp.people.union("user", props(), None).await.unwrap();
+        mp.people.union("other", props(), None).await.unwrap();
+        mp.groups
+            .set("company", "Acme", props(), None)
+            .await
+            .unwrap();
+        mp.track("Event", None).await.unwrap();
+
+        let stats = mp.operation_stats();
+        let counts = |pairs: &[(&str, u64)]| {
+            pairs
+                .iter()
+                .map(|(key, count)| (key.to_string(), *count))
+                .collect::<std::collections::BTreeMap<_, _>>()
+        };
+        assert_eq!(
+            stats.requests_by_endpoint,
+            counts(&[("/engage", 3), ("/groups", 1), ("/track", 1)])
+        );
+        assert_eq!(
+            stats.engage_operations,
+            counts(&[("$set", 1), ("$union", 2)])
+        );
+        assert_eq!(stats.group_operations, counts(&[("$set", 1)]));
+
+        assert_eq!(mp.snapshot_and_reset_stats().requests_sent, 5);
+        assert_eq!(mp.operation_stats(), stats);
+        assert_eq!(mp.snapshot_and_reset_operation_stats(), stats);
+        assert_eq!(mp.operation_stats(), OperationStats::default());
+    }
+
+    #[tokio::test]
+    async fn test_stable_payload() {
+        let server = MockServer::start().await;
+        let config = Config {
+            default_distinct_id: Some("user".to_string()),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let keys: Vec<String> = (0..32).map(|i| format!("key{}", i)).collect();
+        for order in [keys.clone(), keys.into_iter().rev().collect()] {
+            let mut props = HashMap::new();
+            for key in order {
+                props.insert(key, serde_json::json!({"b": 1, "a": 2}));
+            }
+            props.insert("time".to_string(), 1_700_000_000u64.into());
+            mp.track("Event", Some(props)).await.unwrap();
+        }
+
+        let requests = server.requests();
+        assert_eq!(requests[0].query["data"], requests[1].query["data"]);
+    }
+
+    proptest::proptest! {
+        #![proptest_config(proptest::prelude::ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn prop_super_properties_merge(
+            super_properties in crate::arbitrary::properties(),
+            event_properties in crate::arbitrary::properties(),
+        ) {
+            let mp = Mixpanel::init("test_token", None);
+            mp.register(super_properties.clone());
+            let mut props = event_properties.clone();
+            mp.add_event_properties("test_token", "Event", &mut props);
+
+            proptest::prop_assert_eq!(&props["token"], "test_token");
+            for (key, value) in &event_properties {
+                if !["token", "mp_lib", "$lib_version"].contains(&key.as_str()) {
+                    proptest::prop_assert_eq!(&props[key], value);
+                }
+            }
+            for (key, value) in &super_properties {
+                if !MANAGED_PROPERTIES.contains(&key.as_str()) && !event_properties.contains_key(key) {
+                    proptest::prop_assert_eq!(&props[key], value);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_defaults() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        mp.register_defaults("1.4.2", "production");
+
+        let mut props = HashMap::new();
+        props.insert("environment".to_string(), "staging".into());
+        mp.track("First", None).await.unwrap();
+        mp.clone().track("Second", Some(props)).await.unwrap();
+
+        let requests = server.requests();
+        let first = &requests[0].data()["properties"];
+        assert_eq!(first["$app_version"], "1.4.2");
+        assert_eq!(first["environment"], "production");
+        assert_eq!(first["mp_lib"], "rust");
+        let second = &requests[1].data()["properties"];
+        assert_eq!(second["$app_version"], "1.4.2");
+        assert_eq!(second["environment"], "staging");
+
+        mp.unregister("environment");
+        assert_eq!(mp.super_properties().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_register_runtime_context() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        mp.register_runtime_context();
+        mp.track("Started", None).await.unwrap();
+
+        let properties = &server.requests()[0].data()["properties"];
+        assert_eq!(properties["$os"], std::env::consts::OS);
+        assert_eq!(properties["$arch"], std::env::consts::ARCH);
+        assert_eq!(properties["$process_id"], std::process::id());
+        assert_eq!(
+            properties["$server_hostname"].as_str(),
+            utils::hostname().as_deref()
+        );
+        assert_eq!(properties["$lib_version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[tokio::test]
+    async fn test_host_resolution_not_retried() {
+        let config = Config {
+            host: "mixpanel-rs-test.invalid".to_string(),
+            retry_base_delay_ms: 1,
+            retry_max_delay_ms: 1,
+            ..Default::default()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        match mp.track("Event", None).await {
+            Err(Error::HostResolution(message)) => {
+                assert!(message.starts_with("mixpanel-rs-test.invalid"))
+            }
+            other => panic!("expected HostResolution, got {:?}", other),
+        }
+        assert_eq!(mp.stats().retries, 0);
+        assert_eq!(mp.stats().requests_failed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_request_hook_payload_size() {
+        let server = MockServer::with_responses(vec![
+            MockResponse::ok(),
+            MockResponse::new(503, "unavailable"),
+            MockResponse::ok(),
+        ])
+        .await;
+        let metrics = Arc::new(Mutex::new(Vec::new()));
+        let captured = Arc::clone(&metrics);
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let recorded = warnings.clone();
+        let config = Config {
+            request_hook: Some(Arc::new(move |m: &RequestMetrics| {
+                captured.lock().unwrap().push(m.clone())
+            })),
+            warning_hook: Some(Arc::new(move |message: &str| {
+                recorded.lock().unwrap().push(message.to_string())
+            })),
+            item_size_warning_bytes: 1024,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        mp.track("Small", None).await.unwrap();
+        let mut props = HashMap::new();
+        props.insert("blob".to_string(), "x".repeat(2048).into());
+        mp.track_batch(vec![
+            Event {
+                event: "Small".to_string(),
+                properties: HashMap::new(),
+            },
+            Event {
+                event: "Large".to_string(),
+                properties: props,
+            },
+        ])
+        .await
+        .unwrap();
+
+        // The batch is retried once, and reported on each attempt but warned about once
+        let metrics = metrics.lock().unwrap();
+        assert_eq!(metrics.len(), 3);
+        assert!(!metrics[0].oversized);
+        assert_eq!(metrics[0].endpoint, "/track");
+        assert!(metrics[1].oversized);
+        assert!(metrics[1].largest_item_bytes > 2048);
+        assert!(metrics[1].payload_bytes > metrics[1].largest_item_bytes);
+        assert_eq!(metrics[2], metrics[1]);
+        let warnings = warnings.lock().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("/track payload item is"));
+    }
+
+    #[tokio::test]
+    async fn test_export_residency_host() {
+        let rows = concat!(
+            r#"{"event": "Signed Up", "properties": {"distinct_id": "a", "time": 1}}"#,
+            "\n",
+            r#"{"event": "Purchased", "properties": {"distinct_id": "b", "time": 2}}"#,
+            "\n"
+        );
+        let proxy = MockServer::with_responses(vec![MockResponse::new(200, rows)]).await;
+        let config = Config {
+            protocol: "http".to_string(),
+            proxy: Some(format!("http://{}", proxy.address())),
+            secret: Some("secret".to_string()),
+            residency: Residency::Eu,
+            ..Default::default()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let events = mp
+            .export(
+                "2024-01-01",
+                "2024-01-31",
+                Some(vec!["Signed Up".to_string()]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].event, "Purchased");
+
+        let request = &proxy.requests()[0];
+        assert_eq!(request.header("host"), Some("data-eu.mixpanel.com"));
+        assert_eq!(request.path, "/api/2.0/export");
+        assert_eq!(request.query["from_date"], "2024-01-01");
+        assert_eq!(request.query["event"], r#"["Signed Up"]"#);
+        assert!(request.header("authorization").is_some());
+
+        assert_eq!(Residency::default().export_host(), "data.mixpanel.com");
+        assert_eq!(Residency::India.export_host(), "data-in.mixpanel.com");
+    }
+
+    #[tokio::test]
+    async fn test_super_property_precedence() {
+        let server = MockServer::start().await;
+        let config = Config {
+            default_distinct_id: Some("default".to_string()),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let mut supers = HashMap::new();
+        for key in [
+            "plan",
+            "distinct_id",
+            "token",
+            "mp_lib",
+            "$lib_version",
+            "$insert_id",
+        ] {
+            supers.insert(key.to_string(), "super".into());
+        }
+        mp.register(supers);
+
+        // Super properties beat the default distinct_id but not the managed keys
+        mp.track("Supers", None).await.unwrap();
+        // Per-call properties beat super properties
+        let mut props = HashMap::new();
+        props.insert("plan".to_string(), "call".into());
+        props.insert("distinct_id".to_string(), "call".into());
+        props.insert("$insert_id".to_string(), "call".into());
+        mp.track("Call", Some(props.clone())).await.unwrap();
+        // Managed keys beat per-call properties
+        props.insert("token".to_string(), "call".into());
+        props.insert("mp_lib".to_string(), "call".into());
+        mp.track_batch(vec![Event {
+            event: "Batch".to_string(),
+            properties: props,
+        }])
+        .await
+        .unwrap();
+
+        let requests = server.requests();
+        let supers = &requests[0].data()["properties"];
+        assert_eq!(supers["plan"], "super");
+        assert_eq!(supers["distinct_id"], "super");
+        assert_eq!(supers["token"], "test_token");
+        assert_eq!(supers["mp_lib"], "rust");
+        assert_eq!(supers["$lib_version"], env!("CARGO_PKG_VERSION"));
+        assert!(supers.get("$insert_id").is_none());
+
+        let call = &requests[1].data()["properties"];
+        assert_eq!(call["plan"], "call");
+        assert_eq!(call["distinct_id"], "call");
+        assert_eq!(call["$insert_id"], "call");
+
+        let batch = &requests[2].data()[0]["properties"];
+        assert_eq!(batch["distinct_id"], "call");
+        assert_eq!(batch["token"], "test_token");
+        assert_eq!(batch["mp_lib"], "rust");
+    }
+
+    #[tokio::test]
+    async fn test_managed_super_properties_with_minimal_payload() {
+        let server = MockServer::start().await;
+        let config = Config {
+            minimal_payload: true,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let mut supers = HashMap::new();
+        supers.insert("mp_lib".to_string(), "super".into());
+        supers.insert("$lib_version".to_string(), "super".into());
+        mp.register(supers);
+        mp.track("Event", None).await.unwrap();
+
+        let props = &server.requests()[0].data()["properties"];
+        assert!(props.get("mp_lib").is_none());
+        assert!(props.get("$lib_version").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_batch_time_normalization() {
+        let server =
+            MockServer::with_responses(vec![MockResponse::ok(), MockResponse::imported(1)]).await;
+        let config = Config {
+            secret: Some("secret".to_string()),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        let event = |time: &str| Event {
+            event: "Order Placed".to_string(),
+            properties: HashMap::from([("time".to_string(), serde_json::json!(time))]),
+        };
+
+        mp.track_batch(vec![event("2024-05-01T12:00:00Z"), event("1714564800")])
+            .await
+            .unwrap();
+        mp.import_batch(vec![event("2024-05-01T14:00:00+02:00")])
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        let tracked = requests[0].data();
+        assert_eq!(tracked[0]["properties"]["time"], 1714564800);
+        assert_eq!(tracked[1]["properties"]["time"], 1714564800);
+        assert_eq!(requests[1].path, "/import");
+        assert_eq!(requests[1].data()[0]["properties"]["time"], 1714564800);
+    }
+
+    #[tokio::test]
+    async fn test_import_batch_requires_secret() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+
+        let events = vec![Event {
+            event: "Old".to_string(),
+            properties: HashMap::new(),
+        }];
+        assert!(matches!(
+            mp.import_batch(events).await,
+            Err(Error::InvalidConfig(_))
+        ));
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_import_batch_preflight() {
+        let server = MockServer::with_responses(vec![MockResponse::imported(1)]).await;
+        let config = Config {
+            secret: Some("secret".to_string()),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        let event = |name: &str, time: Option<u64>| {
+            let mut properties = HashMap::new();
+            if let Some(time) = time {
+                properties.insert("time".to_string(), time.into());
+            }
+            Event {
+                event: name.to_string(),
+                properties,
+            }
+        };
+        let day = 24 * 60 * 60;
+
+        for rejected in [
+            event("Future", Some(Mixpanel::now() + day)),
+            event("Ancient", Some(1000)),
+            event("Untimed", None),
+        ] {
+            let events = vec![event("Valid", Some(Mixpanel::now() - day)), rejected];
+            match mp.import_batch(events).await {
+                Err(Error::InvalidProperty { key, .. }) => assert_eq!(key, "time"),
+                other => panic!("expected InvalidProperty, got {:?}", other),
+            }
+        }
+        assert!(server.requests().is_empty());
+
+        let service_account = Config {
+            secret: Some("analytics.abc123.mp-service-account:s3cret".to_string()),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(service_account));
+        let events = vec![event("Valid", Some(Mixpanel::now() - day))];
+        assert!(matches!(
+            mp.import_batch(events).await,
+            Err(Error::MissingProjectId(_))
+        ));
+        assert!(server.requests().is_empty());
+
+        // A little clock skew is tolerated
+        let config = Config {
+            secret: Some("analytics.abc123.mp-service-account:s3cret".to_string()),
+            project_id: Some("12345".to_string()),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        let events = vec![event("Skewed", Some(Mixpanel::now() + 60))];
+        mp.import_batch(events).await.unwrap();
+        let requests = server.requests();
+        assert_eq!(requests[0].query["project_id"], "12345");
+        let credentials = BASE64.encode("analytics.abc123.mp-service-account:s3cret");
+        assert_eq!(
+            requests[0].header("authorization"),
+            Some(format!("Basic {}", credentials).as_str())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_batch_response() {
+        let server = MockServer::with_responses(vec![
+            MockResponse::imported(MAX_BATCH_SIZE),
+            MockResponse::imported(10),
+        ])
+        .await;
+        let config = Config {
+            secret: Some("secret".to_string()),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        let events: Vec<Event> = (0..MAX_BATCH_SIZE + 10)
+            .map(|i| Event {
+                event: format!("Imported {}", i),
+                properties: HashMap::from([("time".to_string(), Mixpanel::now().into())]),
+            })
+            .collect();
+
+        mp.import_batch(events).await.unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].data().as_array().unwrap().len(), 10);
+
+        let server = MockServer::with_responses(vec![MockResponse::new(
+            200,
+            r#"{"code": 400, "error": "some data points in the request failed validation", "status": "Bad Request"}"#,
+        )])
+        .await;
+        let config = Config {
+            secret: Some("secret".to_string()),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        let event = Event {
+            event: "Imported".to_string(),
+            properties: HashMap::from([("time".to_string(), Mixpanel::now().into())]),
+        };
+        assert!(matches!(
+            mp.import_batch(vec![event]).await,
+            Err(Error::ApiClientError(400, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_per_call_verbose_import() {
+        let rejected =
+            r#"{"code": 400, "error": "event time is in the future", "status": "Bad Request"}"#;
+        let server =
+            MockServer::with_responses(vec![MockResponse::new(200, rejected), MockResponse::ok()])
+                .await;
+        let config = Config {
+            secret: Some("secret".to_string()),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let mut properties = HashMap::new();
+        properties.insert("time".to_string(), Mixpanel::now().into());
+        let event = Event {
+            event: "Imported".to_string(),
+            properties,
+        };
+        let verbose = TrackOptions {
+            verbose: Some(true),
+            ..Default::default()
+        };
+        match mp.import_batch_with_options(vec![event], verbose).await {
+            Err(Error::ApiClientError(_, message)) => {
+                assert_eq!(message, "event time is in the future")
+            }
+            other => panic!("expected ApiClientError, got {:?}", other),
+        }
+        mp.track("Event", None).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].query["verbose"], "1");
+        assert_eq!(requests[1].query["verbose"], "0");
+    }
+
+    #[tokio::test]
+    async fn test_track_or_import() {
+        let day = 24 * 60 * 60;
+        let event = |name: &str, time: u64| {
+            let mut properties = HashMap::new();
+            properties.insert("time".to_string(), time.into());
+            Event {
+                event: name.to_string(),
+                properties,
+            }
+        };
+        let events = vec![
+            event("Recent", Mixpanel::now() - day),
+            event("Old", Mixpanel::now() - 30 * day),
+            // Milliseconds are compared as seconds
+            event("Old in ms", (Mixpanel::now() - 30 * day) * 1000),
+            Event {
+                event: "Old as a date".to_string(),
+                properties: HashMap::from([("time".to_string(), "2024-05-01T12:00:00Z".into())]),
+            },
+            Event {
+                event: "Untimed".to_string(),
+                properties: HashMap::new(),
+            },
+        ];
+
+        let server =
+            MockServer::with_responses(vec![MockResponse::ok(), MockResponse::imported(3)]).await;
+        let config = Config {
+            secret: Some("secret".to_string()),
+            project_id: Some("1234".to_string()),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        let report = mp.track_or_import(events.clone()).await.unwrap();
+        assert_eq!(
+            report,
+            RouteReport {
+                tracked: 2,
+                imported: 3,
+                skipped: 0
+            }
+        );
+
+        let requests = server.requests();
+        assert_eq!(requests[0].path, "/track");
+        assert_eq!(requests[0].data()[0]["event"], "Recent");
+        assert_eq!(requests[0].data()[1]["event"], "Untimed");
+        assert_eq!(requests[1].path, "/import");
+        assert_eq!(requests[1].query["project_id"], "1234");
+        assert_eq!(requests[1].data()[0]["event"], "Old");
+        assert_eq!(requests[1].data()[1]["event"], "Old in ms");
+        assert_eq!(requests[1].data()[2]["event"], "Old as a date");
+
+        // Without a secret old events are skipped rather than lost in /track
+        let server = MockServer::start().await;
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let recorded = warnings.clone();
+        let config = Config {
+            warning_hook: Some(Arc::new(move |message: &str| {
+                recorded.lock().unwrap().push(message.to_string())
+            })),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        let report = mp.track_or_import(events).await.unwrap();
+        assert_eq!((report.tracked, report.skipped), (2, 3));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].data().as_array().unwrap().len(), 2);
+        assert!(warnings.lock().unwrap()[0].starts_with("skipping 3 events"));
+    }
+
+    #[tokio::test]
+    async fn test_event_source() {
+        let server = MockServer::start().await;
+        let config = Config {
+            event_source: Some("server".to_string()),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        mp.track("Tagged", None).await.unwrap();
+        let props = HashMap::from([("$source".to_string(), "cron".into())]);
+        mp.track("Overridden", Some(props)).await.unwrap();
+
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        mp.track("Default", None).await.unwrap();
+
+        let requests = server.requests();
+        let properties = &requests[0].data()["properties"];
+        assert_eq!(properties["$source"], "server");
+        assert_eq!(properties["mp_api_endpoint"], server.address().to_string());
+        assert_eq!(requests[1].data()["properties"]["$source"], "cron");
+        let properties = requests[2].data()["properties"].clone();
+        assert!(properties.get("$source").is_none());
+        assert!(properties.get("mp_api_endpoint").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_build_metadata() {
+        let server = MockServer::start().await;
+        let mut build_metadata = HashMap::new();
+        build_metadata.insert("$git_sha".to_string(), "abc123".to_string());
+        build_metadata.insert("$build_id".to_string(), "42".to_string());
+        let config = Config {
+            build_metadata,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        mp.track("Event", None).await.unwrap();
+        let mut props = HashMap::new();
+        props.insert("$build_id".to_string(), "43".into());
+        mp.track_batch(vec![Event {
+            event: "Batch".to_string(),
+            properties: props,
+        }])
+        .await
+        .unwrap();
+
+        let requests = server.requests();
+        let event = &requests[0].data()["properties"];
+        assert_eq!(event["$git_sha"], "abc123");
+        assert_eq!(event["$build_id"], "42");
+        let batch = &requests[1].data()[0]["properties"];
+        assert_eq!(batch["$git_sha"], "abc123");
+        assert_eq!(batch["$build_id"], "43");
+    }
+
+    #[tokio::test]
+    async fn test_strict_numbers() {
+        let server = MockServer::start().await;
+        let mut props = HashMap::new();
+        props.insert("order_id".to_string(), (1u64 << 60).into());
+
+        let lenient = Mixpanel::init("test_token", Some(server.config()));
+        lenient.track("Order", Some(props.clone())).await.unwrap();
+
+        let config = Config {
+            strict_numbers: true,
+            ..server.config()
+        };
+        let strict = Mixpanel::init("test_token", Some(config));
+        let result = strict.track("Order", Some(props.clone())).await;
+        assert!(
+            matches!(result, Err(Error::InvalidProperties(ref msg)) if msg.contains("order_id"))
+        );
+        let batch = strict
+            .track_batch(vec![Event {
+                event: "Order".to_string(),
+                properties: props,
+            }])
+            .await;
+        assert!(matches!(batch, Err(Error::InvalidProperties(_))));
+
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_strict_list_properties() {
+        let server = MockServer::start().await;
+        let config = Config {
+            strict_list_properties: true,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let nested = HashMap::from([("matrix".to_string(), serde_json::json!([[[[1]]]]))]);
+        match mp.track("Event", Some(nested.clone())).await {
+            Err(Error::InvalidProperty { key, .. }) => assert_eq!(key, "matrix"),
+            other => panic!("expected InvalidProperty, got {:?}", other),
+        }
+        assert!(matches!(
+            mp.people.set("user", nested.clone(), None).await,
+            Err(Error::InvalidProperty { .. })
+        ));
+        assert!(server.requests().is_empty());
+
+        let shallow = HashMap::from([("matrix".to_string(), serde_json::json!([[1, 2]]))]);
+        mp.track("Event", Some(shallow)).await.unwrap();
+
+        let lenient = Mixpanel::init("test_token", Some(server.config()));
+        lenient.track("Event", Some(nested)).await.unwrap();
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_empty_success_responses() {
+        let server = MockServer::with_responses(vec![
+            MockResponse::new(204, ""),
+            MockResponse::new(200, ""),
+            MockResponse::new(200, "0"),
+        ])
+        .await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+
+        assert!(mp.track("No Content", None).await.is_ok());
+        assert!(mp.track("Empty Body", None).await.is_ok());
+        assert!(matches!(
+            mp.track("Rejected", None).await,
+            Err(Error::ApiUnexpectedResponse(_))
+        ));
     }
 }
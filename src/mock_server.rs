@@ -0,0 +1,252 @@
+// Minimal HTTP server used by the tests to capture outgoing requests without
+// touching the real Mixpanel API.
+#![allow(dead_code)]
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::Config;
+
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    pub delay: Option<Duration>,
+}
+
+impl MockResponse {
+    pub fn new(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.to_string(),
+            delay: None,
+        }
+    }
+
+    pub fn ok() -> Self {
+        Self::new(200, "1")
+    }
+
+    /// What `/import` answers once it has stored `records` events
+    pub fn imported(records: usize) -> Self {
+        Self::new(
+            200,
+            &format!(
+                r#"{{"code":200,"num_records_imported":{},"status":"OK"}}"#,
+                records
+            ),
+        )
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Wait before answering, to simulate a slow server
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CapturedRequest {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl CapturedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(String::as_str)
+    }
+
+    /// Parse the form-encoded request body
+    pub fn form(&self) -> HashMap<String, String> {
+        url::form_urlencoded::parse(&self.body)
+            .into_owned()
+            .collect()
+    }
+
+    /// Decode the base64 `data` parameter from the query string or form body
+    pub fn data(&self) -> Value {
+        let encoded = match self.query.get("data") {
+            Some(data) => data.clone(),
+            None => self
+                .form()
+                .remove("data")
+                .expect("request has no data parameter"),
+        };
+        let decoded = BASE64.decode(encoded).expect("data is not valid base64");
+        serde_json::from_slice(&decoded).expect("data is not valid JSON")
+    }
+}
+
+pub struct MockServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<CapturedRequest>>>,
+}
+
+impl MockServer {
+    /// Start a server answering every request with `200 "1"`
+    pub async fn start() -> Self {
+        Self::with_responses(vec![MockResponse::ok()]).await
+    }
+
+    /// Start a server answering with `responses` in order, repeating the last one
+    pub async fn with_responses(responses: Vec<MockResponse>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let responses = Arc::new(responses);
+
+        let captured = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let captured = Arc::clone(&captured);
+                let responses = Arc::clone(&responses);
+                tokio::spawn(serve_connection(stream, captured, responses));
+            }
+        });
+
+        Self { addr, requests }
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// A config pointing the client at this server
+    pub fn config(&self) -> Config {
+        Config {
+            protocol: "http".to_string(),
+            host: self.addr.to_string(),
+            query_host: self.addr.to_string(),
+            retry_base_delay_ms: 1,
+            retry_max_delay_ms: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Like [`config`](Self::config), with the project secret the query and export APIs
+    /// require
+    pub fn query_config(&self) -> Config {
+        Config {
+            secret: Some("secret".to_string()),
+            ..self.config()
+        }
+    }
+
+    pub fn requests(&self) -> Vec<CapturedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn serve_connection(
+    mut stream: TcpStream,
+    captured: Arc<Mutex<Vec<CapturedRequest>>>,
+    responses: Arc<Vec<MockResponse>>,
+) {
+    let mut buffer = Vec::new();
+    loop {
+        let request = match read_request(&mut stream, &mut buffer).await {
+            Some(request) => request,
+            None => return,
+        };
+
+        let method = request.method.clone();
+        let response = {
+            let mut captured = captured.lock().unwrap();
+            captured.push(request);
+            let index = std::cmp::min(captured.len(), responses.len()) - 1;
+            responses[index].clone()
+        };
+
+        if let Some(delay) = response.delay {
+            tokio::time::sleep(delay).await;
+        }
+
+        let mut head = format!("HTTP/1.1 {} Mock\r\n", response.status);
+        for (name, value) in &response.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", response.body.len()));
+        // Answers to HEAD carry the length but no body
+        let body = if method == "HEAD" { "" } else { &response.body };
+
+        if stream.write_all(head.as_bytes()).await.is_err()
+            || stream.write_all(body.as_bytes()).await.is_err()
+        {
+            return;
+        }
+    }
+}
+
+async fn read_request(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> Option<CapturedRequest> {
+    let header_end = loop {
+        if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        let mut chunk = [0u8; 4096];
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?.to_string();
+
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+
+    let content_length = headers
+        .get("content-length")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    let body_start = header_end + 4;
+    while buffer.len() < body_start + content_length {
+        let mut chunk = [0u8; 4096];
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    let body = buffer[body_start..body_start + content_length].to_vec();
+    buffer.drain(..body_start + content_length);
+
+    // Requests sent through a proxy carry an absolute URL
+    let url = if target.starts_with('/') {
+        url::Url::parse(&format!("http://localhost{}", target)).ok()?
+    } else {
+        url::Url::parse(&target).ok()?
+    };
+    let query = url.query_pairs().into_owned().collect();
+
+    Some(CapturedRequest {
+        method,
+        path: url.path().to_string(),
+        query,
+        headers,
+        body,
+    })
+}
@@ -1,6 +1,101 @@
-use crate::{Mixpanel, Modifiers, Result};
-use serde_json::Value;
+use crate::{
+    EngageOp, Error, GuardPolicy, Mixpanel, Modifiers, PropertyInput, RequestOptions, Result,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Most profiles kept by the `Config::profile_cache_ttl_ms` cache
+const PROFILE_CACHE_CAPACITY: usize = 1000;
+
+/// Profile fields with special meaning to Mixpanel, see `Config::system_fields`
+const SYSTEM_FIELDS: &[&str] = &["$created", "$email", "$last_seen", "$name"];
+
+/// Reason Mixpanel gives for a `$delete` it didn't apply because there was no profile
+const PROFILE_NOT_FOUND: &str = "profile not found";
+
+/// A user profile returned by the Engage query API
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(rename = "$distinct_id")]
+    pub distinct_id: String,
+
+    #[serde(rename = "$properties", default)]
+    pub properties: HashMap<String, Value>,
+}
+
+/// Whether Mixpanel applied a deletion, see [`MixpanelPeople::delete_user_confirmed`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DeleteOutcome {
+    pub deleted: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct EngagePage {
+    #[serde(default)]
+    results: Vec<Profile>,
+    #[serde(default)]
+    page: u64,
+    page_size: Option<usize>,
+    session_id: Option<String>,
+    total: Option<usize>,
+}
+
+/// Recently fetched profiles, including ones that don't exist, evicting the least
+/// recently used beyond `PROFILE_CACHE_CAPACITY`
+#[derive(Debug, Default)]
+pub(crate) struct ProfileCache {
+    entries: HashMap<String, CachedProfile>,
+    tick: u64,
+}
+
+#[derive(Debug)]
+struct CachedProfile {
+    profile: Option<Profile>,
+    fetched: Instant,
+    used: u64,
+}
+
+impl ProfileCache {
+    fn get(&mut self, distinct_id: &str, ttl: Duration) -> Option<Option<Profile>> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(distinct_id)?;
+        if entry.fetched.elapsed() >= ttl {
+            self.entries.remove(distinct_id);
+            return None;
+        }
+        entry.used = self.tick;
+        Some(entry.profile.clone())
+    }
+
+    fn insert(&mut self, distinct_id: String, profile: Option<Profile>) {
+        self.tick += 1;
+        if self.entries.len() >= PROFILE_CACHE_CAPACITY && !self.entries.contains_key(&distinct_id)
+        {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(
+            distinct_id,
+            CachedProfile {
+                profile,
+                fetched: Instant::now(),
+                used: self.tick,
+            },
+        );
+    }
+
+    fn remove(&mut self, distinct_id: &str) {
+        self.entries.remove(distinct_id);
+    }
+}
 
 #[derive(Debug, Clone, Default)]
 pub struct MixpanelPeople {
@@ -9,75 +104,171 @@ pub struct MixpanelPeople {
 
 impl MixpanelPeople {
     /// Set properties on a user profile
-    pub async fn set<S: Into<String>>(
+    pub async fn set<S: Into<String>, P: Into<PropertyInput>>(
         &self,
         distinct_id: S,
-        properties: HashMap<String, Value>,
+        properties: P,
         modifiers: Option<Modifiers>,
     ) -> Result<()> {
-        self._set(distinct_id.into(), properties, modifiers, false)
+        self.set_returning_id(distinct_id, properties, modifiers)
             .await
+            .map(|_| ())
     }
 
     /// Set properties on a user profile only if they haven't been set before
-    pub async fn set_once<S: Into<String>>(
+    pub async fn set_once<S: Into<String>, P: Into<PropertyInput>>(
         &self,
         distinct_id: S,
-        properties: HashMap<String, Value>,
+        properties: P,
         modifiers: Option<Modifiers>,
     ) -> Result<()> {
-        self._set(distinct_id.into(), properties, modifiers, true)
+        self.set_once_returning_id(distinct_id, properties, modifiers)
             .await
+            .map(|_| ())
     }
 
-    /// Increment numeric properties on a user profile
-    pub async fn increment<S: Into<String>>(
+    /// Like [`set`](Self::set), returning the profile's distinct_id
+    ///
+    /// The returned id can be reused to track an event for the same user:
+    ///
+    /// ```no_run
+    /// # use mixpanel_rs::Mixpanel;
+    /// # use std::collections::HashMap;
+    /// # async fn run(mp: Mixpanel) -> mixpanel_rs::Result<()> {
+    /// let mut properties = HashMap::new();
+    /// properties.insert("plan".to_string(), "pro".into());
+    /// let distinct_id = mp.people.set_returning_id("user_123", properties, None).await?;
+    ///
+    /// let mut event = HashMap::new();
+    /// event.insert("distinct_id".to_string(), distinct_id.into());
+    /// mp.track("Upgraded", Some(event)).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn set_returning_id<S: Into<String>, P: Into<PropertyInput>>(
         &self,
         distinct_id: S,
-        properties: HashMap<String, i64>,
+        properties: P,
+        modifiers: Option<Modifiers>,
+    ) -> Result<String> {
+        let properties = properties.into().into_properties()?;
+        let token = self.mixpanel.as_ref().unwrap().token.clone();
+        self._set(&token, distinct_id.into(), properties, modifiers, false)
+            .await
+    }
+
+    /// Like [`set_once`](Self::set_once), returning the profile's distinct_id
+    pub async fn set_once_returning_id<S: Into<String>, P: Into<PropertyInput>>(
+        &self,
+        distinct_id: S,
+        properties: P,
+        modifiers: Option<Modifiers>,
+    ) -> Result<String> {
+        let properties = properties.into().into_properties()?;
+        let token = self.mixpanel.as_ref().unwrap().token.clone();
+        self._set(&token, distinct_id.into(), properties, modifiers, true)
+            .await
+    }
+
+    /// Set properties on a user profile from any value serializing to a JSON object
+    pub async fn set_struct<S: Into<String>, T: Serialize + ?Sized>(
+        &self,
+        distinct_id: S,
+        properties: &T,
         modifiers: Option<Modifiers>,
     ) -> Result<()> {
-        let mut data = serde_json::json!({
-            "$token": self.mixpanel.as_ref().unwrap().token,
-            "$distinct_id": distinct_id.into(),
-            "$add": properties
-        });
+        let properties = crate::utils::to_properties(properties)?;
+        self.set(distinct_id, properties, modifiers).await
+    }
 
-        if let Some(modifiers) = modifiers {
-            data = crate::utils::merge_modifiers(data, Some(modifiers));
-        }
+    /// Set properties on a user profile from key/value pairs, e.g.
+    /// `[("$name", json!("Mike")), ("age", json!(25))]`
+    pub async fn set_props<S, K, V, I>(&self, distinct_id: S, properties: I) -> Result<()>
+    where
+        S: Into<String>,
+        K: Into<String>,
+        V: Into<Value>,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let properties: HashMap<String, Value> = properties
+            .into_iter()
+            .map(|(key, value)| (key.into(), value.into()))
+            .collect();
+        self.set(distinct_id, properties, None).await
+    }
 
-        self.mixpanel
-            .as_ref()
-            .unwrap()
-            .send_request("GET", "/engage", &data)
+    /// Set properties on a user profile in the project identified by `token`
+    pub async fn set_for_token<S: Into<String>, P: Into<PropertyInput>>(
+        &self,
+        token: &str,
+        distinct_id: S,
+        properties: P,
+        modifiers: Option<Modifiers>,
+    ) -> Result<()> {
+        let properties = properties.into().into_properties()?;
+        self._set(token, distinct_id.into(), properties, modifiers, false)
             .await
+            .map(|_| ())
     }
 
-    /// Append values to list properties on a user profile
-    pub async fn append<S: Into<String>>(
+    /// Set properties only if they haven't been set before, in the project identified by `token`
+    pub async fn set_once_for_token<S: Into<String>, P: Into<PropertyInput>>(
         &self,
+        token: &str,
         distinct_id: S,
-        properties: HashMap<String, Value>,
+        properties: P,
         modifiers: Option<Modifiers>,
     ) -> Result<()> {
-        let mut data = serde_json::json!({
-            "$token": self.mixpanel.as_ref().unwrap().token,
-            "$distinct_id": distinct_id.into(),
-            "$append": properties
-        });
-
-        if let Some(modifiers) = modifiers {
-            data = crate::utils::merge_modifiers(data, Some(modifiers));
-        }
+        let properties = properties.into().into_properties()?;
+        self._set(token, distinct_id.into(), properties, modifiers, true)
+            .await
+            .map(|_| ())
+    }
 
-        self.mixpanel
-            .as_ref()
-            .unwrap()
-            .send_request("GET", "/engage", &data)
+    /// Increment numeric properties on a user profile
+    pub async fn increment<S: Into<String>>(
+        &self,
+        distinct_id: S,
+        properties: HashMap<String, i64>,
+        modifiers: Option<Modifiers>,
+    ) -> Result<()> {
+        let properties = properties
+            .into_iter()
+            .map(|(key, value)| (key, value.into()))
+            .collect();
+        self.increment_numbers(distinct_id, properties, modifiers)
             .await
     }
 
+    /// Increment properties by any JSON number, keeping floats and integers beyond `i64`
+    pub async fn increment_numbers<S: Into<String>>(
+        &self,
+        distinct_id: S,
+        properties: HashMap<String, Number>,
+        modifiers: Option<Modifiers>,
+    ) -> Result<()> {
+        let op = EngageOp::Add {
+            distinct_id: distinct_id.into(),
+            properties,
+        };
+        self.send_op(op, modifiers).await
+    }
+
+    /// Append values to list properties on a user profile
+    pub async fn append<S: Into<String>, P: Into<PropertyInput>>(
+        &self,
+        distinct_id: S,
+        properties: P,
+        modifiers: Option<Modifiers>,
+    ) -> Result<()> {
+        let properties = properties.into().into_properties()?;
+        let op = EngageOp::Append {
+            distinct_id: distinct_id.into(),
+            properties: self.prepare_properties(properties),
+        };
+        self.send_op(op, modifiers).await
+    }
+
     /// Track a charge on a user profile
     pub async fn track_charge<S: Into<String>>(
         &self,
@@ -89,23 +280,11 @@ impl MixpanelPeople {
         let mut charge = properties.unwrap_or_default();
         charge.insert("$amount".to_string(), amount.into());
 
-        let mut data = serde_json::json!({
-            "$token": self.mixpanel.as_ref().unwrap().token,
-            "$distinct_id": distinct_id.into(),
-            "$append": {
-                "$transactions": charge
-            }
-        });
-
-        if let Some(modifiers) = modifiers {
-            data = crate::utils::merge_modifiers(data, Some(modifiers));
-        }
-
-        self.mixpanel
-            .as_ref()
-            .unwrap()
-            .send_request("GET", "/engage", &data)
-            .await
+        let op = EngageOp::Append {
+            distinct_id: distinct_id.into(),
+            properties: HashMap::from([("$transactions".to_string(), serde_json::json!(charge))]),
+        };
+        self.send_op(op, modifiers).await
     }
 
     /// Clear all charges from a user profile
@@ -114,23 +293,11 @@ impl MixpanelPeople {
         distinct_id: S,
         modifiers: Option<Modifiers>,
     ) -> Result<()> {
-        let mut data = serde_json::json!({
-            "$token": self.mixpanel.as_ref().unwrap().token,
-            "$distinct_id": distinct_id.into(),
-            "$set": {
-                "$transactions": []
-            }
-        });
-
-        if let Some(modifiers) = modifiers {
-            data = crate::utils::merge_modifiers(data, Some(modifiers));
-        }
-
-        self.mixpanel
-            .as_ref()
-            .unwrap()
-            .send_request("GET", "/engage", &data)
-            .await
+        let op = EngageOp::Set {
+            distinct_id: distinct_id.into(),
+            properties: HashMap::from([("$transactions".to_string(), serde_json::json!([]))]),
+        };
+        self.send_op(op, modifiers).await
     }
 
     /// Delete a user profile
@@ -139,126 +306,333 @@ impl MixpanelPeople {
         distinct_id: S,
         modifiers: Option<Modifiers>,
     ) -> Result<()> {
-        let mut data = serde_json::json!({
-            "$token": self.mixpanel.as_ref().unwrap().token,
-            "$distinct_id": distinct_id.into(),
-            "$delete": ""
-        });
+        let op = EngageOp::Delete {
+            distinct_id: distinct_id.into(),
+        };
+        self.send_op(op, modifiers).await
+    }
 
-        if let Some(modifiers) = modifiers {
-            data = crate::utils::merge_modifiers(data, Some(modifiers));
+    /// Delete a user profile, asking Mixpanel to confirm the deletion took effect
+    ///
+    /// The request is sent with `verbose=1` whatever `Config::verbose` says. A deletion
+    /// Mixpanel reports as not applied because the profile wasn't found returns
+    /// `deleted: false` instead of an error, so compliance flows can record it and retry
+    /// later. Any other rejection is an error.
+    pub async fn delete_user_confirmed<S: Into<String>>(
+        &self,
+        distinct_id: S,
+        modifiers: Option<Modifiers>,
+    ) -> Result<DeleteOutcome> {
+        let op = EngageOp::Delete {
+            distinct_id: distinct_id.into(),
+        };
+        let data = op.to_payload(&self.mixpanel.as_ref().unwrap().token);
+        match self.send_engage(data, modifiers, Some(true)).await {
+            Ok(()) => Ok(DeleteOutcome { deleted: true }),
+            // A verbose `"status": 0` body, as opposed to an HTTP error
+            Err(Error::ApiClientError(200, reason))
+                if reason.eq_ignore_ascii_case(PROFILE_NOT_FOUND) =>
+            {
+                if self.mixpanel.as_ref().unwrap().config.debug {
+                    println!(
+                        "Mixpanel: deletion of {} not applied: {}",
+                        op.distinct_id(),
+                        reason
+                    );
+                }
+                Ok(DeleteOutcome { deleted: false })
+            }
+            Err(err) => Err(err),
         }
-
-        self.mixpanel
-            .as_ref()
-            .unwrap()
-            .send_request("GET", "/engage", &data)
-            .await
     }
 
     /// Remove values from list properties on a user profile
-    pub async fn remove<S: Into<String>>(
+    pub async fn remove<S: Into<String>, P: Into<PropertyInput>>(
         &self,
         distinct_id: S,
-        properties: HashMap<String, Value>,
+        properties: P,
         modifiers: Option<Modifiers>,
     ) -> Result<()> {
-        let mut data = serde_json::json!({
-            "$token": self.mixpanel.as_ref().unwrap().token,
-            "$distinct_id": distinct_id.into(),
-            "$remove": properties
-        });
-
-        if let Some(modifiers) = modifiers {
-            data = crate::utils::merge_modifiers(data, Some(modifiers));
-        }
-
-        self.mixpanel
-            .as_ref()
-            .unwrap()
-            .send_request("GET", "/engage", &data)
-            .await
+        let properties = properties.into().into_properties()?;
+        let op = EngageOp::Remove {
+            distinct_id: distinct_id.into(),
+            properties: self.prepare_properties(properties),
+        };
+        self.send_op(op, modifiers).await
     }
 
     /// Union values to list properties on a user profile
-    pub async fn union<S: Into<String>>(
+    pub async fn union<S: Into<String>, P: Into<PropertyInput>>(
         &self,
         distinct_id: S,
-        properties: HashMap<String, Value>,
+        properties: P,
         modifiers: Option<Modifiers>,
     ) -> Result<()> {
-        let mut data = serde_json::json!({
-            "$token": self.mixpanel.as_ref().unwrap().token,
-            "$distinct_id": distinct_id.into(),
-            "$union": properties
-        });
+        let properties = properties.into().into_properties()?;
+        let op = EngageOp::Union {
+            distinct_id: distinct_id.into(),
+            properties: self.prepare_properties(properties),
+        };
+        self.send_op(op, modifiers).await
+    }
 
-        if let Some(modifiers) = modifiers {
-            data = crate::utils::merge_modifiers(data, Some(modifiers));
+    /// Unset properties on a user profile
+    pub async fn unset<S: Into<String>, P: Into<PropertyInput>>(
+        &self,
+        distinct_id: S,
+        properties: P,
+        modifiers: Option<Modifiers>,
+    ) -> Result<()> {
+        let properties = properties.into().into_keys()?;
+        let op = EngageOp::Unset {
+            distinct_id: distinct_id.into(),
+            properties,
+        };
+        self.send_op(op, modifiers).await
+    }
+
+    /// Fetch a single user profile, returning `None` if it doesn't exist
+    ///
+    /// Requires `config.secret` (or a service account secret with `config.project_id`).
+    /// With `config.profile_cache_ttl_ms` set, a profile fetched within the TTL is returned
+    /// from memory; updates sent through this client drop the cached copy.
+    pub async fn get<S: Into<String>>(&self, distinct_id: S) -> Result<Option<Profile>> {
+        let distinct_id = distinct_id.into();
+        let mixpanel = self.mixpanel.as_ref().unwrap();
+        let cache = &mixpanel.profile_cache;
+        let ttl = mixpanel.config.profile_cache_ttl_ms;
+        if let Some(ttl) = ttl.map(Duration::from_millis) {
+            if let Some(profile) = cache.lock().unwrap().get(&distinct_id, ttl) {
+                return Ok(profile);
+            }
+        }
+
+        let mut profiles = self.get_many(vec![distinct_id.clone()]).await?;
+        let profile = profiles.remove(&distinct_id);
+        if ttl.is_some() {
+            cache.lock().unwrap().insert(distinct_id, profile.clone());
+        }
+        Ok(profile)
+    }
+
+    /// Fetch the current contents of a list property on a user profile
+    ///
+    /// Returns an empty list if the profile or the property doesn't exist.
+    pub async fn get_list<S: Into<String>>(&self, distinct_id: S, key: &str) -> Result<Vec<Value>> {
+        let distinct_id = distinct_id.into();
+        let Some(mut profile) = self.get(distinct_id.clone()).await? else {
+            return Ok(Vec::new());
+        };
+
+        match profile.properties.remove(key) {
+            None | Some(Value::Null) => Ok(Vec::new()),
+            Some(Value::Array(values)) => Ok(values),
+            Some(other) => Err(crate::Error::InvalidProperties(format!(
+                "'{}' on profile '{}' is not a list: {}",
+                key, distinct_id, other
+            ))),
+        }
+    }
+
+    /// Fetch several user profiles at once, keyed by distinct_id
+    ///
+    /// Profiles that don't exist are absent from the returned map.
+    pub async fn get_many(&self, distinct_ids: Vec<String>) -> Result<HashMap<String, Profile>> {
+        let mut profiles = HashMap::new();
+        if distinct_ids.is_empty() {
+            return Ok(profiles);
+        }
+
+        let mixpanel = self.mixpanel.as_ref().unwrap();
+        let distinct_ids = serde_json::to_string(&distinct_ids)?;
+        let mut params = vec![("distinct_ids", distinct_ids)];
+
+        loop {
+            let response = mixpanel.send_query("/api/query/engage", &params).await?;
+            let page: EngagePage = serde_json::from_value(response)?;
+            let received = page.results.len();
+
+            for profile in page.results {
+                profiles.insert(profile.distinct_id.clone(), profile);
+            }
+
+            let more = match (page.total, page.page_size) {
+                (Some(total), _) => profiles.len() < total,
+                (None, Some(page_size)) => received == page_size,
+                (None, None) => false,
+            };
+            if !more || received == 0 {
+                return Ok(profiles);
+            }
+
+            params.retain(|(key, _)| *key == "distinct_ids");
+            params.push(("page", (page.page + 1).to_string()));
+            if let Some(session_id) = page.session_id {
+                params.push(("session_id", session_id));
+            }
         }
+    }
+
+    /// Send a profile update, e.g. one replayed from a log or queue
+    ///
+    /// Properties are sent as given, without `config.flatten_nested` applied.
+    pub async fn send_op(&self, op: EngageOp, modifiers: Option<Modifiers>) -> Result<()> {
+        let token = self.mixpanel.as_ref().unwrap().token.clone();
+        self.send_op_for_token(&token, op, modifiers).await
+    }
 
-        self.mixpanel
-            .as_ref()
-            .unwrap()
-            .send_request("GET", "/engage", &data)
+    /// Send a profile update to the project identified by `token`
+    pub async fn send_op_for_token(
+        &self,
+        token: &str,
+        op: EngageOp,
+        modifiers: Option<Modifiers>,
+    ) -> Result<()> {
+        self.check_op(&op)?;
+        self.send_engage(op.to_payload(token), modifiers, None)
             .await
     }
 
-    /// Unset properties on a user profile
-    pub async fn unset<S: Into<String>>(
+    // Merge modifiers, over `Config::default_modifiers`, into an engage payload and send
+    // it, with `Modifiers::geolocate`,
+    // `Modifiers::test` and `verbose` overriding the config for this request. The
+    // profile's cached copy is dropped, as it no longer reflects the update.
+    async fn send_engage(
         &self,
-        distinct_id: S,
-        properties: Vec<String>,
+        data: Value,
         modifiers: Option<Modifiers>,
+        verbose: Option<bool>,
     ) -> Result<()> {
-        let mut data = serde_json::json!({
-            "$token": self.mixpanel.as_ref().unwrap().token,
-            "$distinct_id": distinct_id.into(),
-            "$unset": properties
-        });
+        let mixpanel = self.mixpanel.as_ref().unwrap();
+        let distinct_id = data["$distinct_id"].as_str().map(String::from);
+        let invalidate = || {
+            if let Some(ref distinct_id) = distinct_id {
+                mixpanel.profile_cache.lock().unwrap().remove(distinct_id);
+            }
+        };
+        invalidate();
+        let modifiers = mixpanel.config.modifiers_for(modifiers);
+        let options = RequestOptions::with_modifiers(&mixpanel.config, modifiers.as_ref(), verbose);
+        let data = crate::utils::merge_modifiers(data, modifiers);
 
-        if let Some(modifiers) = modifiers {
-            data = crate::utils::merge_modifiers(data, Some(modifiers));
+        let result = mixpanel
+            .send_request_with("GET", "/engage", &data, options)
+            .await;
+        // A fetch racing with the update may have cached the old profile meanwhile
+        invalidate();
+        result.map(|_| ())
+    }
+
+    // Check the list properties of an update, see `Config::strict_list_properties`, and
+    // the system fields it touches, see `Config::system_fields`
+    fn check_op(&self, op: &EngageOp) -> Result<()> {
+        match op {
+            EngageOp::Unset { properties, .. }
+                if properties.iter().any(|p| p == "$distinct_id") =>
+            {
+                Err(Error::InvalidProperty {
+                    key: "$distinct_id".to_string(),
+                    reason: "can't be unset, it identifies the profile".to_string(),
+                })
+            }
+            EngageOp::Set { properties, .. } => {
+                self.check_system_fields(op.distinct_id(), properties)?;
+                let context = format!("{} {}", op.action(), op.distinct_id());
+                self.mixpanel
+                    .as_ref()
+                    .unwrap()
+                    .check_lists(&context, properties)
+            }
+            EngageOp::SetOnce { properties, .. }
+            | EngageOp::Append { properties, .. }
+            | EngageOp::Remove { properties, .. }
+            | EngageOp::Union { properties, .. } => {
+                let context = format!("{} {}", op.action(), op.distinct_id());
+                self.mixpanel
+                    .as_ref()
+                    .unwrap()
+                    .check_lists(&context, properties)
+            }
+            _ => Ok(()),
         }
+    }
 
-        self.mixpanel
-            .as_ref()
-            .unwrap()
-            .send_request("GET", "/engage", &data)
-            .await
+    fn check_system_fields(
+        &self,
+        distinct_id: &str,
+        properties: &HashMap<String, Value>,
+    ) -> Result<()> {
+        let config = &self.mixpanel.as_ref().unwrap().config;
+        let mut fields = overwritten_system_fields(properties);
+        if fields.is_empty() || config.system_fields == GuardPolicy::Allow {
+            return Ok(());
+        }
+        fields.sort_unstable();
+        if config.system_fields == GuardPolicy::Reject {
+            return Err(Error::InvalidProperty {
+                key: fields[0].to_string(),
+                reason: format!("is a system field, not overwritten on {}", distinct_id),
+            });
+        }
+        self.mixpanel.as_ref().unwrap().warn(&format!(
+            "$set on {} overwrites system fields {}",
+            distinct_id,
+            fields.join(", ")
+        ));
+        Ok(())
+    }
+
+    // Apply client-wide property transforms before building a payload
+    fn prepare_properties(&self, properties: HashMap<String, Value>) -> HashMap<String, Value> {
+        if self.mixpanel.as_ref().unwrap().config.flatten_nested {
+            crate::utils::flatten_properties(properties)
+        } else {
+            properties
+        }
     }
 
     // Internal helper for set and set_once
     async fn _set(
         &self,
+        token: &str,
         distinct_id: String,
         properties: HashMap<String, Value>,
         modifiers: Option<Modifiers>,
         set_once: bool,
-    ) -> Result<()> {
-        let operation = if set_once { "$set_once" } else { "$set" };
-
-        let mut data = serde_json::json!({
-            "$token": self.mixpanel.as_ref().unwrap().token,
-            "$distinct_id": distinct_id,
-            operation: properties
-        });
-
-        if let Some(modifiers) = modifiers {
-            data = crate::utils::merge_modifiers(data, Some(modifiers));
-        }
+    ) -> Result<String> {
+        let properties = self.prepare_properties(properties);
+        let op = if set_once {
+            EngageOp::SetOnce {
+                distinct_id: distinct_id.clone(),
+                properties,
+            }
+        } else {
+            EngageOp::Set {
+                distinct_id: distinct_id.clone(),
+                properties,
+            }
+        };
 
-        self.mixpanel
-            .as_ref()
-            .unwrap()
-            .send_request("GET", "/engage", &data)
-            .await
+        self.check_op(&op)?;
+        self.send_engage(op.to_payload(token), modifiers, None)
+            .await?;
+        Ok(distinct_id)
     }
 }
 
+/// System fields an update would overwrite, see `Config::system_fields`
+fn overwritten_system_fields(properties: &HashMap<String, Value>) -> Vec<&str> {
+    SYSTEM_FIELDS
+        .iter()
+        .copied()
+        .filter(|field| properties.contains_key(*field))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock_server::{MockResponse, MockServer};
 
     #[tokio::test]
     async fn test_set() {
@@ -301,6 +675,24 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_increment_numbers() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+
+        let mut props = HashMap::new();
+        props.insert("balance".to_string(), Number::from_f64(2.5).unwrap());
+        props.insert("bytes".to_string(), Number::from(u64::MAX));
+        mp.people
+            .increment_numbers("test_user", props, None)
+            .await
+            .unwrap();
+
+        let data = server.requests()[0].data();
+        assert_eq!(data["$add"]["balance"], 2.5);
+        assert_eq!(data["$add"]["bytes"], u64::MAX);
+    }
+
     #[tokio::test]
     async fn test_append() {
         let mp = Mixpanel::init("test_token", None);
@@ -360,6 +752,85 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_delete_user_confirmed() {
+        let server = MockServer::with_responses(vec![
+            MockResponse::new(200, r#"{"status": 1, "error": null}"#),
+            MockResponse::new(200, r#"{"status": 0, "error": "profile not found"}"#),
+            MockResponse::new(200, r#"{"status": 0, "error": "invalid token"}"#),
+        ])
+        .await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+
+        let found = mp.people.delete_user_confirmed("user", None).await.unwrap();
+        assert_eq!(found, DeleteOutcome { deleted: true });
+        let missing = mp
+            .people
+            .delete_user_confirmed("ghost", None)
+            .await
+            .unwrap();
+        assert_eq!(missing, DeleteOutcome { deleted: false });
+        assert!(matches!(
+            mp.people.delete_user_confirmed("user", None).await,
+            Err(Error::ApiClientError(200, _))
+        ));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].query["verbose"], "1");
+        assert_eq!(requests[0].data()["$delete"], "");
+        assert_eq!(requests[1].data()["$distinct_id"], "ghost");
+    }
+
+    #[tokio::test]
+    async fn test_system_fields() {
+        let server = MockServer::start().await;
+        let warnings = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = warnings.clone();
+        let config = crate::Config {
+            warning_hook: Some(std::sync::Arc::new(move |message: &str| {
+                recorded.lock().unwrap().push(message.to_string())
+            })),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        let props = HashMap::from([
+            (
+                "$last_seen".to_string(),
+                serde_json::json!("2024-01-01T00:00:00"),
+            ),
+            ("plan".to_string(), serde_json::json!("pro")),
+        ]);
+
+        let unset = vec!["plan".to_string(), "$distinct_id".to_string()];
+        match mp.people.unset("user", unset, None).await {
+            Err(Error::InvalidProperty { key, .. }) => assert_eq!(key, "$distinct_id"),
+            other => panic!("expected InvalidProperty, got {:?}", other),
+        }
+        assert!(server.requests().is_empty());
+
+        // Warned about, but sent
+        assert_eq!(overwritten_system_fields(&props), vec!["$last_seen"]);
+        mp.people.set("user", props.clone(), None).await.unwrap();
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(
+            *warnings.lock().unwrap(),
+            vec!["$set on user overwrites system fields $last_seen"]
+        );
+
+        let config = crate::Config {
+            system_fields: GuardPolicy::Reject,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+        match mp.people.set("user", props.clone(), None).await {
+            Err(Error::InvalidProperty { key, .. }) => assert_eq!(key, "$last_seen"),
+            other => panic!("expected InvalidProperty, got {:?}", other),
+        }
+        mp.people.set_once("user", props, None).await.unwrap();
+        assert_eq!(server.requests().len(), 2);
+    }
+
     #[tokio::test]
     async fn test_delete_user_with_modifiers() {
         let mp = Mixpanel::init("test_token", None);
@@ -448,6 +919,402 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_set_for_token() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        let mut props = HashMap::new();
+        props.insert("key1".to_string(), "value1".into());
+
+        mp.people
+            .set_for_token("tenant_token", "test_user", props, None)
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].path, "/engage");
+        assert_eq!(requests[0].data()["$token"], "tenant_token");
+        assert_eq!(requests[0].data()["$set"]["key1"], "value1");
+    }
+
+    #[tokio::test]
+    async fn test_set_props() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+
+        mp.people
+            .set_props(
+                "test_user",
+                [("$name", Value::from("Mike")), ("age", 25.into())],
+            )
+            .await
+            .unwrap();
+        mp.people
+            .set_props("test_user", vec![("plan".to_string(), "pro")])
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(
+            requests[0].data()["$set"],
+            serde_json::json!({"$name": "Mike", "age": 25})
+        );
+        assert_eq!(
+            requests[1].data()["$set"],
+            serde_json::json!({"plan": "pro"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_returning_id() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        let props = || HashMap::from([("plan".to_string(), Value::from("pro"))]);
+
+        let distinct_id = mp
+            .people
+            .set_returning_id("test_user", props(), None)
+            .await
+            .unwrap();
+        assert_eq!(distinct_id, "test_user");
+        let distinct_id = mp
+            .people
+            .set_once_returning_id("test_user", props(), None)
+            .await
+            .unwrap();
+        assert_eq!(distinct_id, "test_user");
+
+        let requests = server.requests();
+        assert_eq!(requests[0].data()["$set"]["plan"], "pro");
+        assert_eq!(requests[1].data()["$set_once"]["plan"], "pro");
+    }
+
+    #[tokio::test]
+    async fn test_property_input_shapes() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+
+        let map = HashMap::from([("plan".to_string(), Value::from("pro"))]);
+        mp.people.set("user", map, None).await.unwrap();
+        mp.people
+            .union("user", serde_json::json!({"tags": ["beta"]}), None)
+            .await
+            .unwrap();
+        mp.people.unset("user", "plan", None).await.unwrap();
+        mp.people
+            .unset("user", vec!["plan", "seats"], None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            mp.people.unset("user", serde_json::json!([1]), None).await,
+            Err(Error::InvalidProperties(_))
+        ));
+        // Names alone carry no values, so only `unset` takes them
+        assert!(matches!(
+            mp.people.set_once("user", "referrer", None).await,
+            Err(Error::InvalidProperties(_))
+        ));
+        assert!(matches!(
+            mp.people.union("user", vec!["tags"], None).await,
+            Err(Error::InvalidProperties(_))
+        ));
+        assert!(matches!(
+            mp.people.append("user", "tags", None).await,
+            Err(Error::InvalidProperties(_))
+        ));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(
+            requests[0].data()["$set"],
+            serde_json::json!({"plan": "pro"})
+        );
+        assert_eq!(
+            requests[1].data()["$union"],
+            serde_json::json!({"tags": ["beta"]})
+        );
+        assert_eq!(requests[2].data()["$unset"], serde_json::json!(["plan"]));
+        assert_eq!(
+            requests[3].data()["$unset"],
+            serde_json::json!(["plan", "seats"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_op() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        let op = EngageOp::Union {
+            distinct_id: "test_user".to_string(),
+            properties: HashMap::from([("tags".to_string(), serde_json::json!(["a"]))]),
+        };
+
+        let replayed: EngageOp =
+            serde_json::from_str(&serde_json::to_string(&op).unwrap()).unwrap();
+        mp.people.send_op(replayed, None).await.unwrap();
+
+        assert_eq!(server.requests()[0].data(), op.to_payload("test_token"));
+    }
+
+    #[tokio::test]
+    async fn test_send_op_for_token() {
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        let op = EngageOp::Add {
+            distinct_id: "test_user".to_string(),
+            properties: HashMap::from([("logins".to_string(), 1.into())]),
+        };
+
+        mp.people
+            .send_op_for_token("tenant_token", op.clone(), None)
+            .await
+            .unwrap();
+        mp.people.send_op(op, None).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].data()["$token"], "tenant_token");
+        assert_eq!(requests[0].data()["$add"]["logins"], 1);
+        assert_eq!(requests[1].data()["$token"], "test_token");
+    }
+
+    #[tokio::test]
+    async fn test_set_struct() {
+        #[derive(Serialize)]
+        struct Customer {
+            #[serde(rename = "$name")]
+            name: String,
+            age: u32,
+        }
+
+        let server = MockServer::start().await;
+        let mp = Mixpanel::init("test_token", Some(server.config()));
+        let customer = Customer {
+            name: "Mike".to_string(),
+            age: 25,
+        };
+
+        mp.people
+            .set_struct("test_user", &customer, None)
+            .await
+            .unwrap();
+        assert!(mp
+            .people
+            .set_struct("test_user", "scalar", None)
+            .await
+            .is_err());
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].data()["$set"],
+            serde_json::json!({"$name": "Mike", "age": 25})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_many() {
+        let first_page = serde_json::json!({
+            "page": 0,
+            "page_size": 2,
+            "session_id": "session",
+            "total": 3,
+            "results": [
+                {"$distinct_id": "a", "$properties": {"$name": "A"}},
+                {"$distinct_id": "c", "$properties": {"$name": "C"}}
+            ]
+        });
+        let second_page = serde_json::json!({
+            "page": 1,
+            "page_size": 2,
+            "session_id": "session",
+            "total": 3,
+            "results": [{"$distinct_id": "e", "$properties": {}}]
+        });
+        let server = MockServer::with_responses(vec![
+            MockResponse::new(200, &first_page.to_string()),
+            MockResponse::new(200, &second_page.to_string()),
+        ])
+        .await;
+        let mp = Mixpanel::init("test_token", Some(server.query_config()));
+
+        let ids = ["a", "b", "c", "d", "e"].map(String::from).to_vec();
+        let profiles = mp.people.get_many(ids).await.unwrap();
+
+        assert_eq!(profiles.len(), 3);
+        assert_eq!(profiles["a"].properties["$name"], "A");
+        assert_eq!(profiles["c"].properties["$name"], "C");
+        assert!(profiles.contains_key("e"));
+        assert!(!profiles.contains_key("b"));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].path, "/api/query/engage");
+        assert_eq!(
+            requests[0].form()["distinct_ids"],
+            r#"["a","b","c","d","e"]"#
+        );
+        assert!(!requests[0].form().contains_key("page"));
+        assert_eq!(requests[1].form()["page"], "1");
+        assert_eq!(requests[1].form()["session_id"], "session");
+    }
+
+    #[tokio::test]
+    async fn test_get_missing_profile() {
+        let empty = serde_json::json!({"page": 0, "page_size": 1000, "total": 0, "results": []});
+        let server =
+            MockServer::with_responses(vec![MockResponse::new(200, &empty.to_string())]).await;
+        let mp = Mixpanel::init("test_token", Some(server.query_config()));
+
+        assert_eq!(mp.people.get("missing").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_profile_cache() {
+        let page = serde_json::json!({
+            "page": 0,
+            "page_size": 1000,
+            "total": 1,
+            "results": [{"$distinct_id": "user", "$properties": {"plan": "pro"}}]
+        });
+        let page = MockResponse::new(200, &page.to_string());
+        let server = MockServer::with_responses(vec![page.clone(), MockResponse::ok(), page]).await;
+        let config = crate::Config {
+            profile_cache_ttl_ms: Some(60_000),
+            ..server.query_config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let first = mp.people.get("user").await.unwrap();
+        let second = mp.people.get("user").await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(server.requests().len(), 1);
+
+        let props = HashMap::from([("plan".to_string(), "free".into())]);
+        mp.people.set("user", props, None).await.unwrap();
+        mp.people.get("user").await.unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[2].path, "/api/query/engage");
+    }
+
+    #[tokio::test]
+    async fn test_profile_cache_concurrent_update() {
+        let page = serde_json::json!({
+            "page": 0,
+            "page_size": 1000,
+            "total": 1,
+            "results": [{"$distinct_id": "user", "$properties": {"plan": "pro"}}]
+        });
+        let page = MockResponse::new(200, &page.to_string());
+        let slow_update = MockResponse::ok().delay(Duration::from_millis(200));
+        let server = MockServer::with_responses(vec![slow_update, page]).await;
+        let config = crate::Config {
+            profile_cache_ttl_ms: Some(60_000),
+            ..server.query_config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let props = HashMap::from([("plan".to_string(), "free".into())]);
+        let (updated, fetched) = tokio::join!(mp.people.set("user", props, None), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            mp.people.get("user").await
+        });
+        updated.unwrap();
+        fetched.unwrap();
+
+        mp.people.get("user").await.unwrap();
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_list() {
+        let page = serde_json::json!({
+            "page": 0,
+            "page_size": 1000,
+            "total": 1,
+            "results": [{
+                "$distinct_id": "user",
+                "$properties": {"groups": ["admin", "beta"], "plan": "pro"}
+            }]
+        });
+        let server =
+            MockServer::with_responses(vec![MockResponse::new(200, &page.to_string())]).await;
+        let mp = Mixpanel::init("test_token", Some(server.query_config()));
+
+        let groups = mp.people.get_list("user", "groups").await.unwrap();
+        assert_eq!(groups, vec![Value::from("admin"), Value::from("beta")]);
+        let missing = mp.people.get_list("user", "missing").await.unwrap();
+        assert!(missing.is_empty());
+        assert!(matches!(
+            mp.people.get_list("user", "plan").await,
+            Err(crate::Error::InvalidProperties(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_geolocate_override() {
+        let server = MockServer::start().await;
+        let config = crate::Config {
+            geolocate: true,
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let mut props = HashMap::new();
+        props.insert("plan".to_string(), "pro".into());
+        let no_geolocation = Modifiers {
+            geolocate: Some(false),
+            ..Default::default()
+        };
+        mp.people
+            .set("test_user", props.clone(), Some(no_geolocation))
+            .await
+            .unwrap();
+        mp.people.set("test_user", props, None).await.unwrap();
+        mp.track("Event", None).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].query["ip"], "0");
+        assert!(requests[0].data().get("geolocate").is_none());
+        assert_eq!(requests[1].query["ip"], "1");
+        assert_eq!(requests[2].query["ip"], "1");
+    }
+
+    #[tokio::test]
+    async fn test_default_modifiers() {
+        let server = MockServer::start().await;
+        let config = crate::Config {
+            default_modifiers: Some(Modifiers {
+                ip: Some("10.0.0.1".to_string()),
+                ignore_time: Some(true),
+                ..Default::default()
+            }),
+            ..server.config()
+        };
+        let mp = Mixpanel::init("test_token", Some(config));
+
+        let mut props = HashMap::new();
+        props.insert("plan".to_string(), "pro".into());
+        mp.people
+            .set("test_user", props.clone(), None)
+            .await
+            .unwrap();
+        let modifiers = Modifiers {
+            ip: Some("1.2.3.4".to_string()),
+            ..Default::default()
+        };
+        mp.people
+            .set("test_user", props, Some(modifiers))
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].data()["$ip"], "10.0.0.1");
+        assert_eq!(requests[0].data()["$ignore_time"], true);
+        assert_eq!(requests[1].data()["$ip"], "1.2.3.4");
+        assert_eq!(requests[1].data()["$ignore_time"], true);
+    }
+
     #[tokio::test]
     async fn test_with_modifiers() {
         let mp = Mixpanel::init("test_token", None);
@@ -0,0 +1,28 @@
+//! Convenience re-exports for glob importing.
+//!
+//! `Error` and `Result` are re-exported as `MixpanelError` and `MixpanelResult`
+//! so that `use mixpanel_rs::prelude::*` doesn't shadow `std::result::Result`.
+//!
+//! ```
+//! use mixpanel_rs::prelude::*;
+//! use std::collections::HashMap;
+//!
+//! async fn signed_up(mp: &Mixpanel) -> MixpanelResult<()> {
+//!     let mut properties: HashMap<String, Value> = HashMap::new();
+//!     properties.insert("plan".to_string(), json!("premium"));
+//!     mp.track("Signed Up", Some(properties)).await
+//! }
+//!
+//! let config = Config {
+//!     debug: true,
+//!     ..Default::default()
+//! };
+//! let mp = Mixpanel::init("token", Some(config));
+//! let _ = signed_up(&mp);
+//! ```
+
+pub use crate::error::Error as MixpanelError;
+pub use crate::{
+    ClientStats, Config, Event, Mixpanel, Modifiers, PropertyInput, Result as MixpanelResult,
+};
+pub use serde_json::{json, Value};
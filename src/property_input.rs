@@ -0,0 +1,184 @@
+use crate::{Error, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Properties of a `people` or `groups` update, in any of the shapes the Tauri plugin
+/// accepts from JavaScript
+///
+/// A map gives each property its value. A single property name, or a list of them, only
+/// names properties, so it's accepted by `unset` alone; operations that carry values reject
+/// it rather than send `null`. `unset` uses only the keys of a map.
+///
+/// ```
+/// use mixpanel_rs::PropertyInput;
+/// use serde_json::json;
+/// use std::collections::HashMap;
+///
+/// let map: PropertyInput = HashMap::from([("plan".to_string(), json!("pro"))]).into();
+/// let scalar: PropertyInput = "plan".into();
+/// let list: PropertyInput = vec!["plan", "seats"].into();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyInput {
+    /// A single property name, or a JSON object of properties
+    Scalar(Value),
+    /// Properties and their values
+    Map(HashMap<String, Value>),
+    /// Property names
+    List(Vec<Value>),
+}
+
+impl PropertyInput {
+    /// The properties and their values, an error for property names without values
+    pub fn into_properties(self) -> Result<HashMap<String, Value>> {
+        match self {
+            PropertyInput::Map(properties) => Ok(properties),
+            PropertyInput::Scalar(Value::Object(properties)) => {
+                Ok(properties.into_iter().collect())
+            }
+            PropertyInput::Scalar(value) => Err(Error::InvalidProperties(format!(
+                "expected properties and their values, got {}",
+                value
+            ))),
+            PropertyInput::List(values) => Err(Error::InvalidProperties(format!(
+                "expected properties and their values, got {}",
+                Value::Array(values)
+            ))),
+        }
+    }
+
+    /// The property names, in order for a list and sorted for a map
+    pub fn into_keys(self) -> Result<Vec<String>> {
+        match self {
+            PropertyInput::Map(properties) => Ok(sorted_keys(properties.into_keys())),
+            PropertyInput::Scalar(Value::Object(properties)) => {
+                Ok(sorted_keys(properties.into_iter().map(|(key, _)| key)))
+            }
+            PropertyInput::Scalar(Value::Null) => Ok(Vec::new()),
+            PropertyInput::Scalar(value) => Ok(vec![property_name(value)?]),
+            PropertyInput::List(values) => values.into_iter().map(property_name).collect(),
+        }
+    }
+}
+
+fn sorted_keys(keys: impl Iterator<Item = String>) -> Vec<String> {
+    let mut keys: Vec<String> = keys.collect();
+    keys.sort();
+    keys
+}
+
+fn property_name(value: Value) -> Result<String> {
+    match value {
+        Value::String(name) => Ok(name),
+        value => Err(Error::InvalidProperties(format!(
+            "expected a property name, got {}",
+            value
+        ))),
+    }
+}
+
+impl From<HashMap<String, Value>> for PropertyInput {
+    fn from(properties: HashMap<String, Value>) -> Self {
+        PropertyInput::Map(properties)
+    }
+}
+
+impl From<Value> for PropertyInput {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Array(values) => PropertyInput::List(values),
+            value => PropertyInput::Scalar(value),
+        }
+    }
+}
+
+impl From<&str> for PropertyInput {
+    fn from(name: &str) -> Self {
+        PropertyInput::Scalar(name.into())
+    }
+}
+
+impl From<String> for PropertyInput {
+    fn from(name: String) -> Self {
+        PropertyInput::Scalar(name.into())
+    }
+}
+
+impl From<Vec<String>> for PropertyInput {
+    fn from(names: Vec<String>) -> Self {
+        PropertyInput::List(names.into_iter().map(Value::from).collect())
+    }
+}
+
+impl From<Vec<&str>> for PropertyInput {
+    fn from(names: Vec<&str>) -> Self {
+        PropertyInput::List(names.into_iter().map(Value::from).collect())
+    }
+}
+
+impl From<Vec<Value>> for PropertyInput {
+    fn from(names: Vec<Value>) -> Self {
+        PropertyInput::List(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_into_properties() {
+        let map = HashMap::from([("plan".to_string(), json!("pro"))]);
+        assert_eq!(
+            PropertyInput::from(map.clone()).into_properties().unwrap(),
+            map
+        );
+        assert_eq!(
+            PropertyInput::from(json!({"plan": "pro"}))
+                .into_properties()
+                .unwrap(),
+            map
+        );
+        assert!(matches!(
+            PropertyInput::from("plan").into_properties(),
+            Err(Error::InvalidProperties(_))
+        ));
+        assert!(matches!(
+            PropertyInput::from(vec!["plan", "seats"]).into_properties(),
+            Err(Error::InvalidProperties(_))
+        ));
+        assert!(matches!(
+            PropertyInput::from(json!(42)).into_properties(),
+            Err(Error::InvalidProperties(_))
+        ));
+    }
+
+    #[test]
+    fn test_into_keys() {
+        assert_eq!(
+            PropertyInput::from("plan").into_keys().unwrap(),
+            vec!["plan"]
+        );
+        assert_eq!(
+            PropertyInput::from(vec!["seats", "plan"])
+                .into_keys()
+                .unwrap(),
+            vec!["seats", "plan"]
+        );
+        assert_eq!(
+            PropertyInput::from(json!({"seats": 3, "plan": "pro"}))
+                .into_keys()
+                .unwrap(),
+            vec!["plan", "seats"]
+        );
+        assert!(PropertyInput::from(Value::Null)
+            .into_keys()
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            PropertyInput::from(json!(["plan", 1])).into_keys(),
+            Err(Error::InvalidProperties(_))
+        ));
+    }
+}
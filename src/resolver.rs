@@ -0,0 +1,79 @@
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use std::io;
+
+/// Resolves hosts with the system resolver, like reqwest's default one, but wraps failures
+/// in a `ResolveError` so they can be told apart from other connect errors
+#[derive(Debug, Default)]
+pub(crate) struct Resolver;
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            match tokio::net::lookup_host((host.clone(), 0)).await {
+                Ok(addrs) => Ok(Box::new(addrs) as Addrs),
+                Err(source) => Err(ResolveError { host, source }.into()),
+            }
+        })
+    }
+}
+
+/// A host the resolver failed to resolve
+#[derive(Debug, thiserror::Error)]
+#[error("{host}: {source}")]
+pub(crate) struct ResolveError {
+    pub host: String,
+    #[source]
+    pub source: io::Error,
+}
+
+impl ResolveError {
+    /// Whether the failure may go away on retry, e.g. EAI_AGAIN or a resolver that
+    /// couldn't be reached, rather than a host that doesn't exist
+    pub fn is_transient(&self) -> bool {
+        // getaddrinfo's EAI_SYSTEM carries the OS error, other codes only their message
+        if self.source.raw_os_error().is_some()
+            || matches!(
+                self.source.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+            )
+        {
+            return true;
+        }
+        // EAI_AGAIN is "Temporary failure in name resolution" and its equivalents
+        self.source.to_string().to_lowercase().contains("temporary")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_transient() {
+        let error = |source: io::Error| ResolveError {
+            host: "api.mixpanel.com".to_string(),
+            source,
+        };
+        assert!(!error(io::Error::other(
+            "failed to lookup address information: Name or service not known"
+        ))
+        .is_transient());
+        assert!(error(io::Error::other(
+            "failed to lookup address information: Temporary failure in name resolution"
+        ))
+        .is_transient());
+        assert!(error(io::Error::from(io::ErrorKind::TimedOut)).is_transient());
+        assert!(error(io::Error::from_raw_os_error(24)).is_transient());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_error() {
+        let name: Name = "mixpanel-rs-test.invalid".parse().unwrap();
+        let err = Resolver.resolve(name).await.err().unwrap();
+        let err = err.downcast_ref::<ResolveError>().unwrap();
+        assert_eq!(err.host, "mixpanel-rs-test.invalid");
+        assert!(!err.is_transient());
+    }
+}
@@ -0,0 +1,167 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Callback receiving [`RequestMetrics`] for every request sent to Mixpanel
+pub type RequestHook = Arc<dyn Fn(&RequestMetrics) + Send + Sync>;
+
+/// Size information about an outgoing request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RequestMetrics {
+    /// Endpoint the payload is sent to, e.g. `/track`
+    pub endpoint: String,
+    /// Size of the encoded payload: the base64 `data` parameter, or the raw JSON body
+    /// when sent with `Config::use_json_body`
+    pub payload_bytes: usize,
+    /// JSON size of the largest event or profile update in the payload
+    pub largest_item_bytes: usize,
+    /// Whether `largest_item_bytes` exceeds `Config::item_size_warning_bytes`
+    pub oversized: bool,
+}
+
+/// What a single request sent, see [`Mixpanel::track_with_report`](crate::Mixpanel::track_with_report)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SendReport {
+    /// Size of the encoded payload, as in [`RequestMetrics::payload_bytes`]
+    pub bytes_sent: usize,
+    /// HTTP status Mixpanel answered with
+    pub status: u16,
+    /// How far Mixpanel confirmed the payload
+    pub ingested: Ingested,
+}
+
+/// What a successful ingestion response says about the payload
+///
+/// Without `verbose`, Mixpanel answers `1` as soon as it has received a payload, before
+/// validating it, so events it later rejects (e.g. for a bad token) still look sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Ingested {
+    /// Received but not validated: the request wasn't `verbose`, or was a dry run
+    AcceptedUnverified,
+    /// Validated by Mixpanel through a `verbose` response
+    Validated {
+        /// Events or profile updates in the payload
+        records: usize,
+    },
+}
+
+/// Profile operations counted in [`OperationStats::engage_operations`] and
+/// [`OperationStats::group_operations`]
+const OPERATIONS: &[&str] = &[
+    "$set",
+    "$set_once",
+    "$add",
+    "$append",
+    "$remove",
+    "$union",
+    "$unset",
+    "$delete",
+];
+
+/// Counters describing a client's activity, see [`Mixpanel::stats`](crate::Mixpanel::stats)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ClientStats {
+    /// Requests Mixpanel accepted
+    pub requests_sent: u64,
+    /// Requests that failed, after any retries
+    pub requests_failed: u64,
+    /// Retry attempts across all requests
+    pub retries: u64,
+    /// Events dropped by `Config::event_filter`
+    pub events_filtered: u64,
+    /// Events dropped as duplicates by `Config::dedup_window_ms`
+    pub events_deduplicated: u64,
+    /// Events left out by `Config::sample_rate`
+    pub events_sampled_out: u64,
+    /// Events a [`BufferedClient`](crate::BufferedClient) dropped because its queue was full
+    pub events_dropped_buffer_full: u64,
+}
+
+/// Accepted requests broken down by endpoint and profile operation, see
+/// [`Mixpanel::operation_stats`](crate::Mixpanel::operation_stats)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct OperationStats {
+    /// Requests Mixpanel accepted, by endpoint, e.g. `/track`
+    pub requests_by_endpoint: BTreeMap<String, u64>,
+    /// User profile operations Mixpanel accepted, by name, e.g. `$union`
+    pub engage_operations: BTreeMap<String, u64>,
+    /// Group profile operations Mixpanel accepted, by name
+    pub group_operations: BTreeMap<String, u64>,
+}
+
+/// Live counters shared by a client and its clones
+#[derive(Debug, Default)]
+pub(crate) struct StatsCounters {
+    pub requests_sent: AtomicU64,
+    pub requests_failed: AtomicU64,
+    pub retries: AtomicU64,
+    pub events_filtered: AtomicU64,
+    pub events_deduplicated: AtomicU64,
+    pub events_sampled_out: AtomicU64,
+    pub events_dropped_buffer_full: AtomicU64,
+    operations: Mutex<OperationStats>,
+}
+
+impl StatsCounters {
+    /// Count a request Mixpanel accepted, with the profile operations in its payload
+    pub fn record_sent(&self, endpoint: &str, payload: Option<&Value>) {
+        self.requests_sent.fetch_add(1, Ordering::Relaxed);
+
+        let mut counts = self.operations.lock().unwrap();
+        *counts
+            .requests_by_endpoint
+            .entry(endpoint.to_string())
+            .or_default() += 1;
+        let Some(payload) = payload else {
+            return;
+        };
+        let operations = match endpoint {
+            "/engage" => &mut counts.engage_operations,
+            "/groups" => &mut counts.group_operations,
+            _ => return,
+        };
+        let updates = match payload {
+            Value::Array(updates) => updates.iter().collect(),
+            update => vec![update],
+        };
+        for update in updates {
+            for operation in OPERATIONS.iter().filter(|op| update.get(**op).is_some()) {
+                *operations.entry(operation.to_string()).or_default() += 1;
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> ClientStats {
+        ClientStats {
+            requests_sent: self.requests_sent.load(Ordering::Relaxed),
+            requests_failed: self.requests_failed.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            events_filtered: self.events_filtered.load(Ordering::Relaxed),
+            events_deduplicated: self.events_deduplicated.load(Ordering::Relaxed),
+            events_sampled_out: self.events_sampled_out.load(Ordering::Relaxed),
+            events_dropped_buffer_full: self.events_dropped_buffer_full.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn snapshot_and_reset(&self) -> ClientStats {
+        ClientStats {
+            requests_sent: self.requests_sent.swap(0, Ordering::Relaxed),
+            requests_failed: self.requests_failed.swap(0, Ordering::Relaxed),
+            retries: self.retries.swap(0, Ordering::Relaxed),
+            events_filtered: self.events_filtered.swap(0, Ordering::Relaxed),
+            events_deduplicated: self.events_deduplicated.swap(0, Ordering::Relaxed),
+            events_sampled_out: self.events_sampled_out.swap(0, Ordering::Relaxed),
+            events_dropped_buffer_full: self.events_dropped_buffer_full.swap(0, Ordering::Relaxed),
+        }
+    }
+
+    pub fn operations(&self) -> OperationStats {
+        self.operations.lock().unwrap().clone()
+    }
+
+    pub fn operations_and_reset(&self) -> OperationStats {
+        std::mem::take(&mut *self.operations.lock().unwrap())
+    }
+}
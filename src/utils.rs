@@ -1,8 +1,14 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
 use serde_json::Value;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Convert a timestamp to Unix epoch seconds
-#[allow(dead_code)]
 pub fn ensure_timestamp(time: Option<u64>) -> Option<u64> {
     time.map(|t| {
         if t > 9999999999 {
@@ -13,6 +19,138 @@ pub fn ensure_timestamp(time: Option<u64>) -> Option<u64> {
     })
 }
 
+/// Read an event `time` given as a number, a numeric string or an ISO-8601 date, e.g.
+/// `2024-05-01T12:00:00Z`. Numbers are kept as is, so milliseconds stay milliseconds;
+/// dates without an offset are read as UTC.
+pub fn parse_time(time: &Value) -> Option<u64> {
+    if let Some(time) = time.as_u64() {
+        return Some(time);
+    }
+    let time = time.as_str()?.trim();
+    if let Ok(time) = time.parse::<u64>() {
+        return Some(time);
+    }
+    let date = match chrono::DateTime::parse_from_rfc3339(time) {
+        Ok(date) => date.naive_utc(),
+        Err(_) => chrono::NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%S%.f").ok()?,
+    };
+    u64::try_from(date.and_utc().timestamp()).ok()
+}
+
+/// Encode a request payload, returning its JSON and the base64 `data` parameter
+pub fn encode_payload(payload: &Value) -> (String, String) {
+    let json = payload.to_string();
+    let encoded = BASE64.encode(json.as_bytes());
+    (json, encoded)
+}
+
+/// Property keys `convert_case` leaves alone besides `$` and `mp_` prefixed ones
+const CASE_EXEMPT_KEYS: &[&str] = &["token", "distinct_id", "time"];
+
+/// Rewrite a property key in `case`, splitting words at `_`, `-`, spaces and case changes
+/// (`UserID` is `user` + `id`). Dot-separated parts of flattened keys are converted
+/// one by one; reserved keys are returned unchanged.
+pub fn convert_case(key: &str, case: crate::PropertyCase) -> String {
+    if key.starts_with('$') || key.starts_with("mp_") || CASE_EXEMPT_KEYS.contains(&key) {
+        return key.to_string();
+    }
+    key.split('.')
+        .map(|part| {
+            let words = split_words(part);
+            match case {
+                crate::PropertyCase::SnakeCase => words.join("_"),
+                crate::PropertyCase::CamelCase => words
+                    .iter()
+                    .enumerate()
+                    .map(|(i, word)| {
+                        if i == 0 {
+                            word.clone()
+                        } else {
+                            capitalize(word)
+                        }
+                    })
+                    .collect(),
+                crate::PropertyCase::PascalCase => words.iter().map(|w| capitalize(w)).collect(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Lowercase words of an identifier
+fn split_words(identifier: &str) -> Vec<String> {
+    let chars: Vec<char> = identifier.chars().collect();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '_' || c == '-' || c.is_whitespace() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+        if c.is_uppercase() && !word.is_empty() {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            // `userId` and the `S` of `HTTPServer` start a word, the `D` of `ID` doesn't
+            if !prev.is_uppercase() || next_is_lower {
+                words.push(std::mem::take(&mut word));
+            }
+        }
+        word.extend(c.to_lowercase());
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Rename the keys of `properties` with [`convert_case`], returning the renamed
+/// properties and the keys dropped because another key converted to the same name
+///
+/// A key already in `case` wins over the keys converted to it; among the others the
+/// first in sorted order wins, so the outcome doesn't depend on `HashMap` order.
+pub fn convert_keys(
+    properties: HashMap<String, Value>,
+    case: crate::PropertyCase,
+) -> (HashMap<String, Value>, Vec<String>) {
+    let mut renamed: Vec<(String, String, Value)> = properties
+        .into_iter()
+        .map(|(key, value)| (convert_case(&key, case), key, value))
+        .collect();
+    renamed.sort_by(|(a_target, a_key, _), (b_target, b_key, _)| {
+        (a_target, a_key != a_target, a_key).cmp(&(b_target, b_key != b_target, b_key))
+    });
+
+    let mut converted = HashMap::new();
+    let mut dropped = Vec::new();
+    for (target, key, value) in renamed {
+        match converted.entry(target) {
+            Entry::Occupied(_) => dropped.push(key),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+            }
+        }
+    }
+    (converted, dropped)
+}
+
+/// Gzip-compress a request body
+pub fn gzip(body: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    // Writing to a `Vec` can't fail
+    encoder.write_all(body).expect("gzip to memory failed");
+    encoder.finish().expect("gzip to memory failed")
+}
+
 /// Get current Unix timestamp in seconds
 #[allow(dead_code)]
 pub fn now() -> u64 {
@@ -22,6 +160,298 @@ pub fn now() -> u64 {
         .as_secs()
 }
 
+/// Serialize a value into a property map, failing if it isn't a JSON object
+pub fn to_properties<T: Serialize + ?Sized>(value: &T) -> crate::Result<HashMap<String, Value>> {
+    match serde_json::to_value(value)? {
+        Value::Object(map) => Ok(map.into_iter().collect()),
+        other => Err(crate::Error::InvalidProperties(format!(
+            "expected properties to serialize to an object, got {}",
+            other
+        ))),
+    }
+}
+
+/// Flatten nested objects into dot-delimited top-level keys, leaving arrays intact
+pub fn flatten_properties(properties: HashMap<String, Value>) -> HashMap<String, Value> {
+    let mut flattened = HashMap::new();
+    for (key, value) in properties {
+        flatten_into(&mut flattened, key, value);
+    }
+    flattened
+}
+
+fn flatten_into(flattened: &mut HashMap<String, Value>, key: String, value: Value) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (nested_key, nested_value) in map {
+                flatten_into(flattened, format!("{}.{}", key, nested_key), nested_value);
+            }
+        }
+        value => {
+            flattened.insert(key, value);
+        }
+    }
+}
+
+/// Recursively order object keys, so a payload serializes to the same string every time
+///
+/// `Value` objects are already sorted by default, this also covers builds where
+/// serde_json's `preserve_order` feature is enabled by another crate.
+pub fn sort_keys(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let sorted: BTreeMap<String, Value> =
+                map.into_iter().map(|(k, v)| (k, sort_keys(v))).collect();
+            Value::Object(sorted.into_iter().collect())
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(sort_keys).collect()),
+        value => value,
+    }
+}
+
+/// Hash an event's name and properties, ignoring `time` and `$insert_id`, so repeats of
+/// the same event fired moments apart hash equal
+pub fn event_fingerprint(event: &crate::Event) -> u64 {
+    let properties: BTreeMap<&String, &Value> = event
+        .properties
+        .iter()
+        .filter(|(key, _)| key.as_str() != "time" && key.as_str() != "$insert_id")
+        .collect();
+    let properties = sort_keys(serde_json::json!(properties));
+
+    fnv1a(&[
+        event.event.as_bytes(),
+        &[0],
+        properties.to_string().as_bytes(),
+    ])
+}
+
+/// 64-bit FNV-1a over `parts`, stable across processes and Rust versions unlike
+/// `DefaultHasher`
+pub fn fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// Strip a scheme and trailing slashes from a configured host, e.g.
+/// `https://api.mixpanel.com/` becomes `api.mixpanel.com`
+pub fn normalize_host(host: &str) -> String {
+    split_scheme(host).1
+}
+
+/// Split a configured host into its lowercased `http`/`https` scheme, if it has one, and
+/// the host as [`normalize_host`] returns it
+pub fn split_scheme(host: &str) -> (Option<String>, String) {
+    let host = host.trim();
+    let (scheme, host) = match host.split_once("://") {
+        Some((scheme, rest))
+            if scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https") =>
+        {
+            (Some(scheme.to_ascii_lowercase()), rest)
+        }
+        _ => (None, host),
+    };
+    (scheme, host.trim_end_matches('/').to_string())
+}
+
+/// Whether a response looks like an HTML page rather than a Mixpanel answer, as sent by
+/// misconfigured proxies and captive portals
+pub fn is_html_response(content_type: &str, body: &str) -> bool {
+    content_type.to_ascii_lowercase().contains("html") || body.trim_start().starts_with('<')
+}
+
+/// The first `max_chars` characters of `body`, with an ellipsis if it was cut
+pub fn snippet(body: &str, max_chars: usize) -> String {
+    let body = body.trim();
+    match body.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}...", &body[..end]),
+        None => body.to_string(),
+    }
+}
+
+/// Whether `event` falls within a sample of `rate` (0.0 to 1.0) of all events, bucketed by
+/// its `distinct_id` so all of a user's events get the same answer
+pub fn in_sample(event: &crate::Event, rate: f64) -> bool {
+    let bucket = match event.properties.get("distinct_id") {
+        Some(distinct_id) => fnv1a(&[distinct_id.to_string().as_bytes()]),
+        None => event_fingerprint(event),
+    };
+    ((bucket % 10_000) as f64) < rate * 10_000.0
+}
+
+/// Split `items` into chunks of at most `max_count` items whose JSON array measures at
+/// most `max_bytes` by `size`, e.g. [`form_body_size`]. An item larger than `max_bytes` on
+/// its own is sent in a chunk by itself.
+pub fn chunk_by_size<T: Serialize>(
+    items: &[T],
+    max_count: usize,
+    max_bytes: usize,
+    size: impl Fn(&str) -> usize,
+) -> serde_json::Result<Vec<&[T]>> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    // The chunk's items so far, comma separated
+    let mut joined = String::new();
+    for (index, item) in items.iter().enumerate() {
+        let item = serde_json::to_string(item)?;
+        let count = index - start;
+        let fits = count == 0
+            || (count < max_count && size(&format!("[{},{}]", joined, item)) <= max_bytes);
+        if !fits {
+            chunks.push(&items[start..index]);
+            start = index;
+            joined = item;
+        } else {
+            if count > 0 {
+                joined.push(',');
+            }
+            joined.push_str(&item);
+        }
+    }
+    if start < items.len() {
+        chunks.push(&items[start..]);
+    }
+    Ok(chunks)
+}
+
+/// Size of the `data=...` form body carrying `json`: its base64, with the `+`, `/` and `=`
+/// characters percent-encoded to three bytes each
+pub fn form_body_size(json: &str) -> usize {
+    let encoded = BASE64.encode(json.as_bytes());
+    let escaped = encoded
+        .bytes()
+        .filter(|byte| matches!(byte, b'+' | b'/' | b'='))
+        .count();
+    "data=".len() + encoded.len() + 2 * escaped
+}
+
+/// Name of the machine the process runs on, from `HOSTNAME`/`COMPUTERNAME` or
+/// `/etc/hostname`
+pub fn hostname() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// Add the active OpenTelemetry trace and span ids as `$trace_id`/`$span_id`, unless
+/// the properties already set them. Nothing is added outside of a valid span.
+#[cfg(feature = "otel")]
+pub fn add_trace_context(properties: &mut HashMap<String, Value>) {
+    use opentelemetry::trace::TraceContextExt;
+
+    let context = opentelemetry::Context::current();
+    let span = context.span();
+    let span_context = span.span_context();
+    if !span_context.is_valid() {
+        return;
+    }
+
+    properties
+        .entry("$trace_id".to_string())
+        .or_insert_with(|| span_context.trace_id().to_string().into());
+    properties
+        .entry("$span_id".to_string())
+        .or_insert_with(|| span_context.span_id().to_string().into());
+}
+
+/// Largest integer Mixpanel stores without losing precision (`2^53 - 1`)
+pub const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// Keys of properties holding an integer, directly or nested, outside the range
+/// Mixpanel stores exactly. Such values should be sent as strings instead.
+pub fn unsafe_integer_properties(properties: &HashMap<String, Value>) -> Vec<String> {
+    let mut keys: Vec<String> = properties
+        .iter()
+        .filter(|(_, value)| contains_unsafe_integer(value))
+        .map(|(key, _)| key.clone())
+        .collect();
+    keys.sort();
+    keys
+}
+
+fn contains_unsafe_integer(value: &Value) -> bool {
+    match value {
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(n), _) => n > MAX_SAFE_INTEGER,
+            (None, Some(n)) => n.unsigned_abs() > MAX_SAFE_INTEGER,
+            _ => false,
+        },
+        Value::Array(items) => items.iter().any(contains_unsafe_integer),
+        Value::Object(map) => map.values().any(contains_unsafe_integer),
+        _ => false,
+    }
+}
+
+/// Most elements Mixpanel keeps in a list property
+pub const MAX_LIST_LENGTH: usize = 255;
+
+/// Deepest nesting of lists and objects Mixpanel keeps in a property value
+pub const MAX_NESTING_DEPTH: usize = 3;
+
+/// The first list property (by key) Mixpanel would truncate or reject, with the reason
+pub fn invalid_list_property(properties: &HashMap<String, Value>) -> Option<(String, String)> {
+    let mut keys: Vec<&String> = properties.keys().collect();
+    keys.sort();
+    keys.into_iter().find_map(|key| {
+        let value = &properties[key];
+        if let Value::Array(items) = value {
+            if items.len() > MAX_LIST_LENGTH {
+                let reason = format!(
+                    "list has {} elements, more than {}",
+                    items.len(),
+                    MAX_LIST_LENGTH
+                );
+                return Some((key.clone(), reason));
+            }
+        }
+        let depth = nesting_depth(value);
+        (depth > MAX_NESTING_DEPTH).then(|| {
+            let reason = format!(
+                "nested {} levels deep, more than {}",
+                depth, MAX_NESTING_DEPTH
+            );
+            (key.clone(), reason)
+        })
+    })
+}
+
+fn nesting_depth(value: &Value) -> usize {
+    match value {
+        Value::Array(items) => 1 + items.iter().map(nesting_depth).max().unwrap_or(0),
+        Value::Object(map) => 1 + map.values().map(nesting_depth).max().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// Parse newline-delimited JSON, as answered by the export API
+///
+/// A final line cut off mid-record, e.g. by a dropped connection, is reported as
+/// `Error::TruncatedStream` rather than as malformed JSON.
+pub fn parse_ndjson(body: &str) -> crate::Result<Vec<Value>> {
+    let lines: Vec<&str> = body
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    let mut rows = Vec::with_capacity(lines.len());
+    for (index, line) in lines.iter().enumerate() {
+        match serde_json::from_str(line) {
+            Ok(row) => rows.push(row),
+            Err(e) if e.is_eof() && index == lines.len() - 1 => {
+                return Err(crate::Error::TruncatedStream { rows: rows.len() });
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(rows)
+}
+
 /// Merge modifiers into a data map
 pub fn merge_modifiers(mut data: Value, modifiers: Option<crate::Modifiers>) -> Value {
     if let Some(modifiers) = modifiers {
@@ -35,7 +465,7 @@ pub fn merge_modifiers(mut data: Value, modifiers: Option<crate::Modifiers>) ->
                 .unwrap()
                 .insert("$ignore_time".to_string(), ignore_time.into());
         }
-        if let Some(time) = modifiers.time {
+        if let Some(time) = ensure_timestamp(modifiers.time) {
             data.as_object_mut()
                 .unwrap()
                 .insert("$time".to_string(), time.into());
@@ -60,7 +490,229 @@ pub fn merge_modifiers(mut data: Value, modifiers: Option<crate::Modifiers>) ->
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::arbitrary;
     use crate::Modifiers;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn prop_encode_payload_roundtrip(properties in arbitrary::properties()) {
+            let payload = sort_keys(serde_json::json!([{"event": "Event", "properties": properties}]));
+            let (json, encoded) = encode_payload(&payload);
+
+            let decoded = BASE64.decode(&encoded).unwrap();
+            prop_assert_eq!(&decoded, json.as_bytes());
+            // The encoding is exact: the bytes above match. Parsing them back may still
+            // move a float by its last bits, as serde_json's default float parser isn't
+            // correctly rounded without the `float_roundtrip` feature, so only that
+            // parsing step is compared approximately.
+            let parsed: Value = serde_json::from_slice(&decoded).unwrap();
+            prop_assert!(arbitrary::approx_eq(&parsed, &payload), "{} != {}", parsed, payload);
+        }
+
+        #[test]
+        fn prop_merge_modifiers_keeps_keys(
+            properties in arbitrary::properties(),
+            ip in proptest::option::of(arbitrary::key()),
+            time in proptest::option::of(any::<u64>()),
+            ignore_time in proptest::option::of(any::<bool>()),
+            location in proptest::option::of((any::<f64>(), any::<f64>())),
+        ) {
+            let data = serde_json::json!({"$token": "token", "$set": properties});
+            let modifiers = crate::Modifiers {
+                ip: ip.clone(),
+                time,
+                ignore_time,
+                latitude: location.map(|(latitude, _)| latitude),
+                longitude: location.map(|(_, longitude)| longitude),
+                ..Default::default()
+            };
+
+            let merged = merge_modifiers(data.clone(), Some(modifiers));
+            for (key, value) in data.as_object().unwrap() {
+                prop_assert_eq!(&merged[key], value);
+            }
+            prop_assert_eq!(merged.get("$ip").and_then(|ip| ip.as_str()), ip.as_deref());
+            prop_assert_eq!(merged.get("$time").is_some(), time.is_some());
+            prop_assert_eq!(merged.get("$latitude").is_some(), location.is_some());
+        }
+    }
+
+    #[test]
+    fn test_parse_time() {
+        use serde_json::json;
+
+        assert_eq!(parse_time(&json!(1714564800)), Some(1714564800));
+        assert_eq!(parse_time(&json!(1714564800000u64)), Some(1714564800000));
+        assert_eq!(parse_time(&json!("1714564800")), Some(1714564800));
+        assert_eq!(parse_time(&json!("2024-05-01T12:00:00Z")), Some(1714564800));
+        assert_eq!(
+            parse_time(&json!("2024-05-01T14:00:00.250+02:00")),
+            Some(1714564800)
+        );
+        assert_eq!(parse_time(&json!("2024-05-01T12:00:00")), Some(1714564800));
+        assert_eq!(parse_time(&json!("yesterday")), None);
+        assert_eq!(parse_time(&json!(-1)), None);
+    }
+
+    #[test]
+    fn test_html_response() {
+        assert!(is_html_response("text/html; charset=utf-8", "1"));
+        assert!(is_html_response("", "\n<!DOCTYPE html><html></html>"));
+        assert!(!is_html_response("text/plain", "0"));
+        assert!(!is_html_response("application/json", "{\"status\": 1}"));
+
+        assert_eq!(snippet("  short  ", 10), "short");
+        assert_eq!(snippet("<html>é</html>", 7), "<html>é...");
+    }
+
+    #[test]
+    fn test_convert_case() {
+        use crate::PropertyCase::*;
+
+        for key in ["userId", "user_id", "UserID", "user-id", "User Id"] {
+            assert_eq!(convert_case(key, SnakeCase), "user_id", "{}", key);
+            assert_eq!(convert_case(key, CamelCase), "userId", "{}", key);
+            assert_eq!(convert_case(key, PascalCase), "UserId", "{}", key);
+        }
+        assert_eq!(
+            convert_case("HTTPServerName", SnakeCase),
+            "http_server_name"
+        );
+        assert_eq!(
+            convert_case("address.zipCode", SnakeCase),
+            "address.zip_code"
+        );
+        for key in ["$userId", "mp_sentBy", "token", "distinct_id", "time"] {
+            assert_eq!(convert_case(key, CamelCase), key);
+        }
+    }
+
+    #[test]
+    fn test_convert_keys_collisions() {
+        use crate::PropertyCase::*;
+
+        let properties = HashMap::from([
+            ("UserID".to_string(), Value::from(1)),
+            ("user_id".to_string(), Value::from(2)),
+            ("userId".to_string(), Value::from(3)),
+        ]);
+        let (converted, mut dropped) = convert_keys(properties.clone(), SnakeCase);
+        assert_eq!(
+            converted,
+            HashMap::from([("user_id".to_string(), Value::from(2))])
+        );
+        dropped.sort();
+        assert_eq!(dropped, vec!["UserID", "userId"]);
+
+        // Without a key already in the target case, the first key in sorted order wins
+        let properties = HashMap::from([
+            ("user_id".to_string(), Value::from(2)),
+            ("UserID".to_string(), Value::from(1)),
+        ]);
+        let (converted, dropped) = convert_keys(properties, PascalCase);
+        assert_eq!(
+            converted,
+            HashMap::from([("UserId".to_string(), Value::from(1))])
+        );
+        assert_eq!(dropped, vec!["user_id"]);
+    }
+
+    #[test]
+    fn test_normalize_host() {
+        assert_eq!(normalize_host("api.mixpanel.com"), "api.mixpanel.com");
+        assert_eq!(
+            normalize_host("https://api.mixpanel.com/"),
+            "api.mixpanel.com"
+        );
+        assert_eq!(normalize_host("HTTP://localhost:8080//"), "localhost:8080");
+        assert_eq!(
+            normalize_host(" api-eu.mixpanel.com "),
+            "api-eu.mixpanel.com"
+        );
+        assert_eq!(
+            normalize_host("api.mixpanel.com/track"),
+            "api.mixpanel.com/track"
+        );
+
+        assert_eq!(
+            split_scheme("HTTP://localhost:8080/"),
+            (Some("http".to_string()), "localhost:8080".to_string())
+        );
+        assert_eq!(
+            split_scheme("api.mixpanel.com"),
+            (None, "api.mixpanel.com".to_string())
+        );
+    }
+
+    #[test]
+    fn test_in_sample() {
+        let event = |distinct_id: &str| crate::Event {
+            event: "Visit".to_string(),
+            properties: HashMap::from([(
+                "distinct_id".to_string(),
+                serde_json::json!(distinct_id),
+            )]),
+        };
+
+        assert!(in_sample(&event("user"), 1.0));
+        assert!(!in_sample(&event("user"), 0.0));
+        assert_eq!(
+            in_sample(&event("user"), 0.5),
+            in_sample(&event("user"), 0.5)
+        );
+
+        let kept = (0..1000)
+            .filter(|id| in_sample(&event(&id.to_string()), 0.25))
+            .count();
+        assert!((150..350).contains(&kept), "{} kept", kept);
+    }
+
+    #[test]
+    fn test_chunk_by_size() {
+        let items: Vec<String> = [3, 3, 3, 10, 1, 1, 1, 1]
+            .iter()
+            .map(|len| "x".repeat(*len))
+            .collect();
+        let sizes = |chunks: Vec<&[String]>| chunks.iter().map(|c| c.len()).collect::<Vec<_>>();
+
+        // `["xxx","xxx"]` is 13 bytes, a third 3-char item makes it 19
+        let chunks = chunk_by_size(&items, 50, 15, str::len).unwrap();
+        assert_eq!(sizes(chunks.clone()), vec![2, 1, 1, 3, 1]);
+        for chunk in chunks {
+            assert!(serde_json::to_vec(chunk).unwrap().len() <= 15 || chunk.len() == 1);
+        }
+        let chunks = chunk_by_size(&items, 3, 1024, str::len).unwrap();
+        assert_eq!(sizes(chunks), vec![3, 3, 2]);
+        assert!(chunk_by_size::<String>(&[], 50, 1024, str::len)
+            .unwrap()
+            .is_empty());
+
+        // Measured on the wire, the same items no longer fit two to a chunk
+        let chunks = chunk_by_size(&items, 50, 25, form_body_size).unwrap();
+        for chunk in &chunks {
+            let json = serde_json::to_string(chunk).unwrap();
+            assert!(form_body_size(&json) <= 25 || chunk.len() == 1);
+        }
+        assert!(chunks.len() > 5);
+    }
+
+    #[test]
+    fn test_form_body_size() {
+        for json in ["[]", "[\"xxx\"]", "{\"a\":\"~~~???\"}"] {
+            let body = url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("data", &BASE64.encode(json.as_bytes()))
+                .finish();
+            assert_eq!(form_body_size(json), body.len(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(&[]), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(&[b"a"]), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(&[b"foo", b"bar"]), 0x8594_4171_f739_67e8);
+    }
 
     #[test]
     fn test_ensure_timestamp() {
@@ -69,6 +721,127 @@ mod tests {
         assert_eq!(ensure_timestamp(None), None);
     }
 
+    #[test]
+    fn test_sort_keys() {
+        let value = serde_json::json!({"b": [{"d": 1, "c": 2}], "a": {"f": 3, "e": 4}});
+        assert_eq!(
+            sort_keys(value).to_string(),
+            r#"{"a":{"e":4,"f":3},"b":[{"c":2,"d":1}]}"#
+        );
+    }
+
+    #[test]
+    fn test_to_properties() {
+        #[derive(Serialize)]
+        struct Plan {
+            name: &'static str,
+            seats: u32,
+        }
+
+        let props = to_properties(&Plan {
+            name: "team",
+            seats: 5,
+        })
+        .unwrap();
+        assert_eq!(props.get("name"), Some(&serde_json::json!("team")));
+        assert_eq!(props.get("seats"), Some(&serde_json::json!(5)));
+
+        assert!(matches!(
+            to_properties(&vec![1, 2]),
+            Err(crate::Error::InvalidProperties(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_list_property() {
+        let props: HashMap<String, Value> = serde_json::from_value(serde_json::json!({
+            "tags": ["a", "b"],
+            "items": [{"sku": "1", "options": ["red"]}],
+            "name": "order"
+        }))
+        .unwrap();
+        assert_eq!(invalid_list_property(&props), None);
+
+        let mut too_deep = props.clone();
+        too_deep.insert("matrix".to_string(), serde_json::json!([[[["x"]]]]));
+        let (key, reason) = invalid_list_property(&too_deep).unwrap();
+        assert_eq!(key, "matrix");
+        assert!(reason.contains("4 levels"));
+
+        let mut too_long = props;
+        too_long.insert(
+            "ids".to_string(),
+            serde_json::json!(vec![1; MAX_LIST_LENGTH + 1]),
+        );
+        assert_eq!(invalid_list_property(&too_long).unwrap().0, "ids");
+    }
+
+    #[test]
+    fn test_parse_ndjson() {
+        let complete = "{\"event\": \"a\"}\n\n{\"event\": \"b\"}\n";
+        assert_eq!(parse_ndjson(complete).unwrap().len(), 2);
+
+        let truncated = "{\"event\": \"a\"}\n{\"event\": \"b\", \"properties\": {\"ti";
+        assert!(matches!(
+            parse_ndjson(truncated),
+            Err(crate::Error::TruncatedStream { rows: 1 })
+        ));
+
+        // A cut-off line followed by more data is malformed, not truncated
+        let malformed = "{\"event\": \"a\"\n{\"event\": \"b\"}\n";
+        assert!(matches!(
+            parse_ndjson(malformed),
+            Err(crate::Error::JsonError(_))
+        ));
+    }
+
+    #[test]
+    fn test_unsafe_integer_properties() {
+        let props: HashMap<String, Value> = serde_json::from_value(serde_json::json!({
+            "order_id": 1u64 << 60,
+            "negative": -(1i64 << 60),
+            "nested": {"ids": [1, 1u64 << 60]},
+            "safe": MAX_SAFE_INTEGER,
+            "float": 1e300,
+            "name": "order"
+        }))
+        .unwrap();
+        assert_eq!(
+            unsafe_integer_properties(&props),
+            vec!["negative", "nested", "order_id"]
+        );
+    }
+
+    #[test]
+    fn test_flatten_properties() {
+        let mut props = HashMap::new();
+        props.insert("a".to_string(), serde_json::json!({"b": 1}));
+        props.insert(
+            "address".to_string(),
+            serde_json::json!({"city": "Paris", "geo": {"lat": 48.8}}),
+        );
+        props.insert("tags".to_string(), serde_json::json!([{"x": 1}, "y"]));
+        props.insert("empty".to_string(), serde_json::json!({}));
+
+        let flattened = flatten_properties(props);
+
+        assert_eq!(flattened.len(), 5);
+        assert_eq!(flattened.get("a.b"), Some(&serde_json::json!(1)));
+        assert_eq!(
+            flattened.get("address.city"),
+            Some(&serde_json::json!("Paris"))
+        );
+        assert_eq!(
+            flattened.get("address.geo.lat"),
+            Some(&serde_json::json!(48.8))
+        );
+        assert_eq!(
+            flattened.get("tags"),
+            Some(&serde_json::json!([{"x": 1}, "y"]))
+        );
+        assert_eq!(flattened.get("empty"), Some(&serde_json::json!({})));
+    }
+
     #[test]
     fn test_merge_modifiers() {
         let data = serde_json::json!({
@@ -82,6 +855,8 @@ mod tests {
             ignore_alias: Some(true),
             latitude: Some(40.7127753),
             longitude: Some(-74.0059728),
+            geolocate: None,
+            test: None,
         };
 
         let result = merge_modifiers(data, Some(modifiers));
@@ -164,6 +939,17 @@ mod tests {
         assert!(obj.get("$longitude").is_none());
     }
 
+    #[test]
+    fn test_merge_modifiers_time_millis() {
+        let modifiers = Modifiers {
+            time: Some(1234567890123),
+            ..Default::default()
+        };
+
+        let result = merge_modifiers(serde_json::json!({}), Some(modifiers));
+        assert_eq!(result["$time"], 1234567890);
+    }
+
     #[test]
     fn test_merge_modifiers_ignore_alias_only() {
         let data = serde_json::json!({